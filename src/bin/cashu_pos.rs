@@ -1,20 +1,120 @@
+use std::collections::BTreeSet;
+use std::future::IntoFuture;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, bail};
+use axum::Router;
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum_server::tls_rustls::RustlsConfig;
 use bip39::Mnemonic;
-use cashu_pos::config::AppConfig;
-use cashu_pos::create_cashu_pos_router;
-use cashu_pos::db::Db;
-use cashu_pos::types::CashuPosInfo;
+use cashu_pos::CashuPos;
+use cashu_pos::config::{
+    AppConfig, LogFormat, LoggingSettings, default_shutdown_grace_period_seconds,
+};
+use cashu_pos::consolidation::spawn_consolidation;
+use cashu_pos::create_reloadable_cashu_pos_router;
+use cashu_pos::db::{Db, QuoteStore};
+use cashu_pos::maintenance::{default_expiry_sweep_interval_seconds, spawn_maintenance};
+use cashu_pos::pos_server::ReloadHandle;
+use cashu_pos::seed::{derive_htlc_preimage_key, derive_p2pk_key, load_or_create_mnemonic};
+use cashu_pos::setup::{SetupAnswers, run_setup};
+use cashu_pos::sweep::spawn_sweep;
+use cashu_pos::types::{
+    CashuPosInfo, ConfigDuration, PosAmount, QuoteFilter, QuoteState, QuoteTimeField, Withdrawal,
+    default_accepted_units, default_legacy_routes, default_max_memo_length,
+    default_payment_request_warn_length, parse_accepted_units,
+};
 use cdk::mint_url::MintUrl;
 use cdk::nuts::CurrencyUnit;
+use cdk::util::unix_time;
+use cdk::wallet::types::WalletKey;
 use cdk::wallet::{MultiMintWallet, Wallet};
-use tower_http::cors::CorsLayer;
-use tracing_subscriber::EnvFilter;
+use clap::{Args, Parser, Subcommand};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+#[derive(Parser)]
+#[command(version, about = "Cashu NUT-18 payment backend")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Config file, `config.toml` in the work directory if unset
+    #[arg(long, global = true, env = "CASHU_POS_CONFIG")]
+    config: Option<PathBuf>,
+    /// Directory holding the wallet, seed and quote databases, `~/.cashu-pos` if unset
+    #[arg(long, global = true, env = "CASHU_POS_WORK_DIR")]
+    work_dir: Option<PathBuf>,
+    /// Address to listen on, overriding `listen_host`, `listen_port` and `listen_socket`
+    #[arg(long, global = true, env = "CASHU_POS_LISTEN")]
+    listen: Option<SocketAddr>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the payment server, the default without a subcommand
+    Serve,
+    /// Create a config file, prompting for anything not passed as a flag
+    Setup(SetupArgs),
+    /// Print the balance of every wallet
+    Balance,
+    /// List stored quotes
+    Quotes(QuotesArgs),
+    /// Pay a lightning invoice from a wallet
+    Sweep(SweepArgs),
+}
+
+#[derive(Args)]
+struct QuotesArgs {
+    /// Only quotes in this state, e.g. paid or unpaid
+    #[arg(long)]
+    state: Option<String>,
+    /// Only quotes created within this long, e.g. 24h or 30m
+    #[arg(long)]
+    since: Option<ConfigDuration>,
+    /// Only quotes created with this tag, e.g. a batch of bulk quotes
+    #[arg(long)]
+    tag: Option<String>,
+}
+
+#[derive(Args)]
+struct SweepArgs {
+    /// Invoice to pay
+    #[arg(long)]
+    bolt11: String,
+    /// Mint whose wallet pays, the first accepted mint if unset
+    #[arg(long)]
+    mint: Option<String>,
+    #[arg(long, default_value = "sat")]
+    unit: String,
+}
+
+#[derive(Args)]
+struct SetupArgs {
+    /// Fail instead of prompting for missing answers
+    #[arg(long)]
+    non_interactive: bool,
+    /// Overwrite an existing config file
+    #[arg(long)]
+    force: bool,
+    #[arg(long)]
+    listen_host: Option<String>,
+    #[arg(long)]
+    listen_port: Option<u16>,
+    /// Public URL wallets POST payments to
+    #[arg(long)]
+    payment_url: Option<String>,
+    /// Accepted mint URL, may be repeated
+    #[arg(long = "mint")]
+    mints: Vec<String>,
+}
 
 fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()?;
@@ -22,130 +122,790 @@ fn main() -> anyhow::Result<()> {
     let runtime = Arc::new(runtime);
 
     runtime.block_on(async {
-        let work_dir = home::home_dir()
-            .ok_or(anyhow!("Could not get home dir"))?
-            .join(".cashu-pos");
+        let work_dir = match cli.work_dir {
+            Some(work_dir) => work_dir,
+            None => home::home_dir()
+                .ok_or(anyhow!("Could not get home dir"))?
+                .join(".cashu-pos"),
+        };
 
         // Ensure work directory exists
         std::fs::create_dir_all(&work_dir)
             .map_err(|e| anyhow!("Failed to create work directory: {}", e))?;
 
-        // Load configuration
-        let config_path = work_dir.join("config.toml");
-        let config = match AppConfig::new(Some(&config_path)) {
-            Ok(config) => config,
-            Err(e) => {
-                eprintln!("Failed to load configuration: {}", e);
-                eprintln!(
-                    "An example configuration has been created at: {}",
-                    work_dir.join("example.config.toml").display()
-                );
-                eprintln!(
-                    "Please copy and modify this file to: {}",
-                    config_path.display()
-                );
-                return Err(anyhow::anyhow!("Configuration error: {}", e));
+        let config_path = cli.config.unwrap_or_else(|| work_dir.join("config.toml"));
+        let config = || load_config(&config_path, cli.listen);
+
+        match cli.command.unwrap_or(Command::Serve) {
+            Command::Setup(args) => {
+                let answers = SetupAnswers {
+                    listen_host: args.listen_host,
+                    listen_port: args.listen_port,
+                    payment_url: args.payment_url,
+                    accepted_mints: args.mints,
+                };
+
+                run_setup(&config_path, answers, !args.non_interactive, args.force).await?;
+                Ok(())
             }
-        };
+            Command::Serve => serve(config()?, &work_dir, &config_path, cli.listen).await,
+            Command::Balance => print_balances(config()?, &work_dir).await,
+            Command::Quotes(args) => print_quotes(&work_dir, args).await,
+            Command::Sweep(args) => pay_invoice(config()?, &work_dir, args).await,
+        }
+    })
+}
 
-        let default_filter = "debug";
-        let sqlx_filter = "sqlx=warn";
-        let hyper_filter = "hyper=warn";
-        let h2_filter = "h2=warn";
-        let rustls_filter = "rustls=warn";
+/// Load and validate the config, explaining how to create one if it is missing
+///
+/// Values come from the defaults, then the file, then `CASHU_POS__` environment variables,
+/// then `listen` from the command line, each overriding the ones before.
+fn load_config(config_path: &Path, listen: Option<SocketAddr>) -> anyhow::Result<AppConfig> {
+    let mut config = match AppConfig::new(Some(config_path)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            eprintln!(
+                "An example configuration has been created at: {}",
+                config_path.with_file_name("example.config.toml").display()
+            );
+            eprintln!(
+                "Please copy and modify this file to: {}",
+                config_path.display()
+            );
+            eprintln!("Or run `cashu-pos setup` to create one interactively");
+            return Err(anyhow::anyhow!("Configuration error: {}", e));
+        }
+    };
 
-        let env_filter = EnvFilter::new(format!(
-            "{},{},{},{},{}",
-            default_filter, sqlx_filter, hyper_filter, h2_filter, rustls_filter
-        ));
+    if let Some(listen) = listen {
+        config.override_listen(listen);
+    }
 
-        tracing_subscriber::fmt().with_env_filter(env_filter).init();
+    config
+        .validate()
+        .map_err(|e| anyhow!("Invalid configuration in {}: {}", config_path.display(), e))?;
 
+    Ok(config)
+}
+
+/// Wallet database and the seed wallets are derived from
+struct WalletStore {
+    localstore: Arc<cdk_redb::WalletRedbDatabase>,
+    seed: Mnemonic,
+}
+
+impl WalletStore {
+    fn open(config: &AppConfig, work_dir: &Path) -> anyhow::Result<Self> {
         let localstore = Arc::new(cdk_redb::WalletRedbDatabase::new(
             &work_dir.join("cdk-wallet.redb"),
         )?);
 
-        let seed = Mnemonic::generate(12)?;
+        let seed = load_or_create_mnemonic(&work_dir.join("seed"), config.pos.mnemonic.as_deref())?;
 
-        let mut wallets = vec![];
+        Ok(Self { localstore, seed })
+    }
 
-        for mint in config.pos.accepted_mints.iter() {
-            let wallet = Wallet::new(
-                mint,
-                CurrencyUnit::Usd,
-                localstore.clone(),
-                &seed.to_seed_normalized(""),
-                None,
-            )?;
+    fn wallet(&self, mint: &str, unit: &CurrencyUnit) -> anyhow::Result<Wallet> {
+        Ok(Wallet::new(
+            mint,
+            unit.clone(),
+            self.localstore.clone(),
+            &self.seed.to_seed_normalized(""),
+            None,
+        )?)
+    }
+}
+
+/// Wallets for every accepted mint and unit, with their store and the accepted units
+fn open_wallets(
+    config: &AppConfig,
+    work_dir: &Path,
+) -> anyhow::Result<(MultiMintWallet, WalletStore, Vec<CurrencyUnit>)> {
+    let store = WalletStore::open(config, work_dir)?;
+
+    let accepted_units = match &config.pos.accepted_units {
+        Some(units) => parse_accepted_units(units)?,
+        None => default_accepted_units(),
+    };
+
+    let mut wallets = vec![];
+
+    for mint in config.pos.accepted_mints.iter() {
+        for unit in accepted_units.iter() {
+            wallets.push(store.wallet(mint, unit)?);
+        }
+    }
+
+    let wallet = MultiMintWallet::new(wallets);
+
+    Ok((wallet, store, accepted_units))
+}
+
+/// Server settings from `config`, with HTLC preimages encrypted by a key derived from `seed`
+fn pos_info(
+    config: &AppConfig,
+    seed: &Mnemonic,
+    accepted_units: Vec<CurrencyUnit>,
+    p2pk_key: Option<String>,
+) -> anyhow::Result<CashuPosInfo> {
+    Ok(CashuPosInfo {
+        accepted_mints: config
+            .pos
+            .accepted_mints
+            .clone()
+            .iter()
+            .map(|s| MintUrl::from_str(s))
+            .collect::<Result<Vec<MintUrl>, _>>()?,
+        max_mints_per_request: config.pos.max_mints_per_request,
+        payment_request_warn_length: config
+            .pos
+            .payment_request_warn_length
+            .unwrap_or_else(default_payment_request_warn_length),
+        accepted_units,
+        max_memo_length: config
+            .pos
+            .max_memo_length
+            .unwrap_or_else(default_max_memo_length),
+        log_throttle: config.pos.log_throttle,
+        disconnect_policy: config.pos.disconnect_policy,
+        shadow_mode: config.pos.shadow_mode.clone(),
+        diagnostics: config.pos.diagnostics,
+        sandbox: config.pos.sandbox,
+        quote_expiry_seconds: config
+            .pos
+            .quote_expiry_seconds
+            .map(|expiry| expiry.as_duration().as_secs()),
+        max_open_quotes: config.pos.max_open_quotes,
+        debug_capture: config.pos.debug_capture,
+        receipt_utc_offset_minutes: config.pos.receipt_utc_offset_minutes,
+        overpayment_policy: config.pos.overpayment_policy,
+        overpayment_tolerance: config.pos.overpayment_tolerance,
+        amount_encoding: config.pos.amount_encoding,
+        partial_payments: config.pos.partial_payments,
+        webhook_url: config.pos.webhook_url.clone(),
+        wallet_routes: config.pos.wallet_routes,
+        legacy_routes: config
+            .pos
+            .legacy_routes
+            .unwrap_or_else(default_legacy_routes),
+        api_key: config.pos.api_key.clone(),
+        rate_limit: config.pos.rate_limit,
+        exchange_rate: config.pos.exchange_rate.clone(),
+        consolidation: config.pos.consolidation,
+        sweep: config.pos.sweep.clone(),
+        nostr_relays: config.pos.nostr_relays.clone(),
+        nostr_key: config.pos.nostr_key.clone(),
+        p2pk_key,
+        require_p2pk: config.pos.require_p2pk,
+        htlc_preimage_key: Some(derive_htlc_preimage_key(seed).to_hex()),
+    })
+}
+
+/// Fail with a clear message if a running server holds the lock on the redb database at `path`
+///
+/// redb allows one process at a time, so commands run alongside `cashu-pos serve` would
+/// otherwise fail with an obscure storage error.
+fn ensure_not_in_use(path: &Path) -> anyhow::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    match redb::Database::open(path) {
+        Err(redb::DatabaseError::DatabaseAlreadyOpen) => bail!(
+            "{} is in use by another process, stop the running `cashu-pos serve` first",
+            path.display()
+        ),
+        _ => Ok(()),
+    }
+}
+
+async fn print_balances(config: AppConfig, work_dir: &Path) -> anyhow::Result<()> {
+    ensure_not_in_use(&work_dir.join("cdk-wallet.redb"))?;
+
+    let (wallet, _, _) = open_wallets(&config, work_dir)?;
+    let cdk_pos = cashu_pos::CashuPos::new(wallet)?;
+
+    for (mint, unit, balance) in cdk_pos.balances().await? {
+        println!("{}\t{}", mint, PosAmount::new(balance.into(), unit));
+    }
+
+    Ok(())
+}
+
+async fn print_quotes(work_dir: &Path, args: QuotesArgs) -> anyhow::Result<()> {
+    let state = args
+        .state
+        .map(|state| parse_quote_state(&state))
+        .transpose()?;
 
-            wallets.push(wallet);
+    let db_path = work_dir.join("cashu-lsp.redb");
+    ensure_not_in_use(&db_path)?;
+    let db = Db::new(db_path)?;
 
-            let wallet_sat = Wallet::new(
-                mint,
-                CurrencyUnit::Sat,
-                localstore.clone(),
-                &seed.to_seed_normalized(""),
-                None,
-            )?;
-            wallets.push(wallet_sat);
+    let now = unix_time();
+    let filter = QuoteFilter {
+        state,
+        from: args
+            .since
+            .map(|since| now.saturating_sub(since.as_duration().as_secs())),
+        to: None,
+        time_field: QuoteTimeField::Created,
+        tag: args.tag,
+    };
+
+    let mut after = None;
+    loop {
+        let (quotes, next) = db.list_quotes(after, 500, &filter, now).await?;
+
+        for quote in quotes {
+            println!(
+                "{}\t{:?}\t{}\t{}",
+                quote.id,
+                quote.state_at(now),
+                quote.amount,
+                quote
+                    .created_at
+                    .map(|created_at| created_at.to_string())
+                    .unwrap_or_default()
+            );
         }
 
-        let wallet = MultiMintWallet::new(wallets);
+        match next {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
 
-        let cdk_pos = cashu_pos::CashuPos::new(wallet)?;
+    Ok(())
+}
 
-        let cdk_pos = Arc::new(cdk_pos);
+/// Parse a quote state ignoring case, so `paid` works as well as `Paid`
+fn parse_quote_state(state: &str) -> anyhow::Result<QuoteState> {
+    [
+        QuoteState::Unpaid,
+        QuoteState::Pending,
+        QuoteState::PartiallyPaid,
+        QuoteState::Paid,
+        QuoteState::Expired,
+        QuoteState::Cancelled,
+        QuoteState::Unsettled,
+    ]
+    .into_iter()
+    .find(|candidate| format!("{:?}", candidate).eq_ignore_ascii_case(state))
+    .ok_or(anyhow!(
+        "Unknown quote state {}, expected unpaid, pending, partiallypaid, paid, expired, cancelled \
+         or unsettled",
+        state
+    ))
+}
 
-        // Configure POS server
-        let cashu_pos_info = CashuPosInfo {
-            accepted_mints: config
+/// Melt proofs of one wallet to pay `args.bolt11`, recording it like `POST /withdraw`
+async fn pay_invoice(config: AppConfig, work_dir: &Path, args: SweepArgs) -> anyhow::Result<()> {
+    let db_path = work_dir.join("cashu-lsp.redb");
+    ensure_not_in_use(&work_dir.join("cdk-wallet.redb"))?;
+    ensure_not_in_use(&db_path)?;
+
+    let mint = match &args.mint {
+        Some(mint) => MintUrl::from_str(mint)?,
+        None => MintUrl::from_str(
+            config
                 .pos
                 .accepted_mints
-                .clone()
-                .iter()
-                .map(|s| MintUrl::from_str(s))
-                .collect::<Result<Vec<MintUrl>, _>>()?,
-        };
+                .first()
+                .ok_or(anyhow!("No accepted mints configured"))?,
+        )?,
+    };
+    let unit = CurrencyUnit::from_str(&args.unit)?;
 
-        let payment_url = config.pos.payment_url.clone();
+    let (wallet, _, _) = open_wallets(&config, work_dir)?;
+    let wallet = wallet
+        .get_wallet(&WalletKey::new(mint.clone(), unit.clone()))
+        .await
+        .ok_or(anyhow!("No {} wallet for {}", unit, mint))?;
 
-        let db = Db::new(work_dir.join("cashu-lsp.redb"))?;
+    let melt_quote = wallet.melt_quote(args.bolt11.clone(), None).await?;
 
-        let service =
-            create_cashu_pos_router(Arc::clone(&cdk_pos), cashu_pos_info, payment_url, db).await?;
+    // The mint holds back the fee reserve until it knows the actual routing fee
+    let required = u64::from(melt_quote.amount).saturating_add(melt_quote.fee_reserve.into());
+    let available: u64 = wallet.total_balance().await?.into();
 
-        let service = service.layer(CorsLayer::permissive());
+    if available < required {
+        bail!(
+            "Insufficient balance: {} available, {} required including the fee reserve",
+            PosAmount::new(available, unit.clone()),
+            PosAmount::new(required, unit)
+        );
+    }
 
-        // Start POS HTTP server
-        let socket_addr = SocketAddr::from_str(&format!(
-            "{}:{}",
-            config.pos.listen_host, config.pos.listen_port
-        ))?;
+    let melted = wallet.melt(&melt_quote.id).await?;
 
-        tracing::info!("Starting POS server on {}", socket_addr);
+    let withdrawal = Withdrawal {
+        id: Uuid::new_v4(),
+        mint,
+        unit,
+        bolt11: args.bolt11,
+        melt_quote_id: melt_quote.id,
+        state: melted.state,
+        amount: melted.amount.into(),
+        fee_paid: melted.fee_paid.into(),
+        preimage: melted.preimage,
+        created_at: unix_time(),
+    };
 
-        let listener = tokio::net::TcpListener::bind(socket_addr).await?;
+    println!(
+        "Paid {} with {} fee from {} ({:?})",
+        PosAmount::new(withdrawal.amount, withdrawal.unit.clone()),
+        PosAmount::new(withdrawal.fee_paid, withdrawal.unit.clone()),
+        withdrawal.mint,
+        withdrawal.state
+    );
 
-        let axum_result = axum::serve(listener, service).with_graceful_shutdown(shutdown_signal());
+    // The invoice is already paid, so a failed write must not turn into an error
+    let db = Db::new(db_path)?;
+    if let Err(e) = db.add_withdrawal(&withdrawal).await {
+        eprintln!("Failed to record withdrawal {}: {}", withdrawal.id, e);
+    }
 
-        match axum_result.await {
-            Ok(_) => {
-                tracing::info!("Axum server stopped with okay status");
-            }
-            Err(err) => {
-                tracing::warn!("Axum server stopped with error");
-                tracing::error!("{}", err);
-                bail!("Axum exited with error")
+    Ok(())
+}
+
+/// Settings a config reload applies, named as in the config file
+const RELOADABLE_SETTINGS: [&str; 9] = [
+    "accepted_mints",
+    "max_mints_per_request",
+    "max_memo_length",
+    "quote_expiry_seconds",
+    "overpayment_policy",
+    "overpayment_tolerance",
+    "webhook_url",
+    "rate_limit.create_per_minute",
+    "rate_limit.payment_per_minute",
+];
+
+/// Re-reads the config file and applies the settings that can change while running
+struct ConfigReloader {
+    config_path: PathBuf,
+    listen: Option<SocketAddr>,
+    /// `[pos]` section the server started with
+    started_with: serde_json::Value,
+    store: WalletStore,
+    accepted_units: Vec<CurrencyUnit>,
+    p2pk_key: Option<String>,
+    cdk_pos: Arc<CashuPos>,
+    handle: ReloadHandle,
+}
+
+impl ConfigReloader {
+    /// Apply the config file, failing without changes if it is invalid
+    async fn reload(&self) -> anyhow::Result<()> {
+        let config = load_config(&self.config_path, self.listen)?;
+        let pos_info = pos_info(
+            &config,
+            &self.store.seed,
+            self.accepted_units.clone(),
+            self.p2pk_key.clone(),
+        )?;
+
+        let mut ignored = Vec::new();
+        changed_settings(
+            &self.started_with,
+            &serde_json::to_value(&config.pos)?,
+            "",
+            &mut ignored,
+        );
+        ignored.retain(|name| !RELOADABLE_SETTINGS.contains(&name.as_str()));
+
+        if !ignored.is_empty() {
+            tracing::warn!(
+                "Ignoring changes to {}, they only apply after a restart",
+                ignored.join(", ")
+            );
+        }
+
+        // Payments to a newly accepted mint need a wallet for it
+        for mint in config.pos.accepted_mints.iter() {
+            for unit in self.accepted_units.iter() {
+                self.cdk_pos
+                    .add_wallet(self.store.wallet(mint, unit)?)
+                    .await;
             }
         }
 
+        match self.handle.reload(&pos_info) {
+            changed if changed.is_empty() => tracing::info!("Reloaded config, nothing changed"),
+            changed => tracing::info!("Reloaded config, changed {}", changed.join(", ")),
+        }
+
         Ok(())
-    })
+    }
 }
 
+/// Dotted names of the settings that differ between `old` and `new`
+fn changed_settings(
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+    prefix: &str,
+    changed: &mut Vec<String>,
+) {
+    match (old, new) {
+        (serde_json::Value::Object(old_fields), serde_json::Value::Object(new_fields)) => {
+            let keys: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+
+            for key in keys {
+                let name = match prefix.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", prefix, key),
+                };
+
+                changed_settings(
+                    old_fields.get(key).unwrap_or(&serde_json::Value::Null),
+                    new_fields.get(key).unwrap_or(&serde_json::Value::Null),
+                    &name,
+                    changed,
+                );
+            }
+        }
+        (old, new) if old != new => changed.push(prefix.to_string()),
+        _ => (),
+    }
+}
+
+/// Reload the config every time the process receives SIGHUP
+#[cfg(unix)]
+fn spawn_reload_on_sighup(reloader: ConfigReloader) -> anyhow::Result<()> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!("Reloading {}", reloader.config_path.display());
+
+            if let Err(e) = reloader.reload().await {
+                tracing::error!("Keeping the current config: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn init_logging(settings: &LoggingSettings) -> anyhow::Result<()> {
+    let env_filter = settings.filter()?;
+
+    match settings.format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(env_filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_env_filter(env_filter)
+            .init(),
+    }
+
+    Ok(())
+}
+
+async fn serve(
+    config: AppConfig,
+    work_dir: &Path,
+    config_path: &Path,
+    listen: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    init_logging(&config.logging)?;
+
+    let (wallet, store, accepted_units) = open_wallets(&config, work_dir)?;
+
+    let cdk_pos = cashu_pos::CashuPos::new(wallet)?;
+
+    let cdk_pos = Arc::new(cdk_pos);
+
+    let p2pk_key = match (&config.pos.p2pk_key, config.pos.p2pk_lock) {
+        (Some(key), _) => Some(key.clone()),
+        (None, true) => Some(derive_p2pk_key(&store.seed)?.to_secret_hex()),
+        (None, false) => None,
+    };
+
+    let cashu_pos_info = pos_info(
+        &config,
+        &store.seed,
+        accepted_units.clone(),
+        p2pk_key.clone(),
+    )?;
+
+    let payment_url = config.pos.payment_url.clone();
+
+    let db: Arc<dyn QuoteStore> = Arc::new(Db::new(work_dir.join("cashu-lsp.redb"))?);
+
+    let shutdown = CancellationToken::new();
+    let maintenance = spawn_maintenance(
+        Arc::clone(&db),
+        config.pos.expiry_sweep_interval_seconds.map_or_else(
+            || Duration::from_secs(default_expiry_sweep_interval_seconds()),
+            |interval| interval.as_duration(),
+        ),
+        shutdown.clone(),
+    );
+
+    let consolidation = config.pos.consolidation.enabled.then(|| {
+        spawn_consolidation(
+            Arc::clone(&cdk_pos),
+            config.pos.consolidation,
+            shutdown.clone(),
+        )
+    });
+
+    let sweep = spawn_sweep(
+        Arc::clone(&cdk_pos),
+        Arc::clone(&db),
+        config.pos.sweep.clone(),
+        shutdown.clone(),
+    )?;
+
+    let (mut service, reload) =
+        create_reloadable_cashu_pos_router(Arc::clone(&cdk_pos), cashu_pos_info, payment_url, db)
+            .await?;
+
+    let reloader = ConfigReloader {
+        config_path: config_path.to_path_buf(),
+        listen,
+        started_with: serde_json::to_value(&config.pos)?,
+        store,
+        accepted_units,
+        p2pk_key,
+        cdk_pos: Arc::clone(&cdk_pos),
+        handle: reload,
+    };
+
+    #[cfg(unix)]
+    spawn_reload_on_sighup(reloader)?;
+    #[cfg(not(unix))]
+    drop(reloader);
+
+    if let Some(cors) = config.pos.cors.layer()? {
+        service = service.layer(cors);
+    }
+
+    let tls = match (&config.pos.tls_cert_path, &config.pos.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let tls = RustlsConfig::from_pem_file(cert_path, key_path)
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "Could not load TLS certificate {} and key {}, both must be PEM files and the key must match the certificate: {}",
+                        cert_path.display(),
+                        key_path.display(),
+                        e
+                    )
+                })?;
+
+            if config.pos.payment_url.starts_with("http://") {
+                tracing::warn!(
+                    "TLS is enabled but payment_url {} is http://, wallets will not use HTTPS",
+                    config.pos.payment_url
+                );
+            }
+
+            Some(tls)
+        }
+        _ => None,
+    };
+
+    let grace_period = config.pos.shutdown_grace_period_seconds.map_or_else(
+        || Duration::from_secs(default_shutdown_grace_period_seconds()),
+        |grace_period| grace_period.as_duration(),
+    );
+
+    let stop = CancellationToken::new();
+    tokio::spawn({
+        let stop = stop.clone();
+        async move {
+            shutdown_signal().await;
+            stop.cancel();
+        }
+    });
+
+    let axum_result = match (&config.pos.listen_socket, tls) {
+        #[cfg(unix)]
+        (Some(socket_path), _) => {
+            tracing::info!(
+                "Starting POS server on unix socket {}",
+                socket_path.display()
+            );
+
+            // Without a peer address clients are only told apart by X-Forwarded-For
+            let listener = bind_unix_socket(socket_path)?;
+            let server = axum::serve(listener, service.into_make_service())
+                .with_graceful_shutdown(stop.clone().cancelled_owned());
+            serve_until_stopped(server, &stop, grace_period).await
+        }
+        #[cfg(not(unix))]
+        (Some(_), _) => bail!("pos.listen_socket is only supported on unix"),
+        (None, tls) => {
+            let socket_addr = SocketAddr::from_str(&format!(
+                "{}:{}",
+                config.pos.listen_host, config.pos.listen_port
+            ))?;
+
+            // Client addresses are needed for per client rate limits
+            let service = service.into_make_service_with_connect_info::<SocketAddr>();
+
+            serve_tcp(socket_addr, service, tls, &stop, grace_period).await
+        }
+    };
+
+    // Payments run in their own tasks and outlive a closed connection, so a quote is
+    // never left unpaid after the mint swapped its proofs
+    let unfinished = cdk_pos.drain_payments(grace_period).await;
+    if unfinished > 0 {
+        tracing::error!(
+            "{} payments still in progress after {:?}, their quotes may need reconciling",
+            unfinished,
+            grace_period
+        );
+    }
+
+    // Let a sweep in progress finish its batch before exiting
+    shutdown.cancel();
+    if let Err(e) = maintenance.await {
+        tracing::warn!("Quote maintenance task failed: {}", e);
+    }
+    if let Some(consolidation) = consolidation {
+        match consolidation.await {
+            Ok(()) => (),
+            Err(e) => tracing::warn!("Proof consolidation task failed: {}", e),
+        }
+    }
+    if let Some(sweep) = sweep {
+        match sweep.await {
+            Ok(()) => (),
+            Err(e) => tracing::warn!("Sweep task failed: {}", e),
+        }
+    }
+
+    match axum_result {
+        Ok(_) => {
+            tracing::info!("Axum server stopped with okay status");
+        }
+        Err(err) => {
+            tracing::warn!("Axum server stopped with error");
+            tracing::error!("{}", err);
+            bail!("Axum exited with error")
+        }
+    }
+
+    Ok(())
+}
+
+/// Serve on a TCP port, over HTTPS if `tls` is set, until `stop` is cancelled
+async fn serve_tcp(
+    socket_addr: SocketAddr,
+    service: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    tls: Option<RustlsConfig>,
+    stop: &CancellationToken,
+    grace_period: Duration,
+) -> std::io::Result<()> {
+    match tls {
+        Some(tls) => {
+            tracing::info!("Starting POS server on https://{}", socket_addr);
+
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                let stop = stop.clone();
+                async move {
+                    stop.cancelled().await;
+                    handle.graceful_shutdown(Some(grace_period));
+                }
+            });
+
+            axum_server::bind_rustls(socket_addr, tls)
+                .handle(handle)
+                .serve(service)
+                .await
+        }
+        None => {
+            tracing::info!("Starting POS server on {}", socket_addr);
+
+            let listener = tokio::net::TcpListener::bind(socket_addr).await?;
+            let server = axum::serve(listener, service)
+                .with_graceful_shutdown(stop.clone().cancelled_owned());
+            serve_until_stopped(server, stop, grace_period).await
+        }
+    }
+}
+
+/// Bind a unix socket at `path` that the group can connect to, replacing a stale socket
+/// left by an earlier run
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => bail!(
+            "pos.listen_socket {} exists and is not a socket",
+            path.display()
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| anyhow!("Could not bind unix socket {}: {}", path.display(), e))?;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+
+    Ok(listener)
+}
+
+/// Run `server` until it stops after `stop` is cancelled, giving open connections up to
+/// `grace_period` to finish
+async fn serve_until_stopped<S>(
+    server: S,
+    stop: &CancellationToken,
+    grace_period: Duration,
+) -> std::io::Result<()>
+where
+    S: IntoFuture<Output = std::io::Result<()>>,
+{
+    // Once signalled the server stops accepting connections and waits for open ones, but
+    // WebSocket subscribers could keep it waiting forever
+    tokio::select! {
+        result = server.into_future() => result,
+        _ = async {
+            stop.cancelled().await;
+            tokio::time::sleep(grace_period).await;
+        } => {
+            tracing::warn!("Connections still open after {:?}, closing them", grace_period);
+            Ok(())
+        }
+    }
+}
+
+/// Resolve on Ctrl+C or, on unix, SIGTERM as sent by systemd
 async fn shutdown_signal() {
-    tokio::signal::ctrl_c()
-        .await
-        .expect("failed to install CTRL+C handler");
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install CTRL+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
     tracing::info!("Shutdown signal received");
 }
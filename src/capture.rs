@@ -0,0 +1,310 @@
+//! Debug capture of payment request and response bodies
+//!
+//! Proofs are bearer tokens, so captured payloads are parsed and every proof
+//! secret, signature, witness and DLEQ proof is replaced by its hash before it
+//! is stored. Capturing switches itself off once `duration` has passed since
+//! startup so it cannot be left running by accident.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use cdk::util::unix_time;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::types::ConfigDuration;
+
+/// Payload fields that allow spending or linking proofs
+const REDACTED_FIELDS: [&str; 4] = ["secret", "C", "witness", "dleq"];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureSettings {
+    pub enabled: bool,
+    /// How long after startup bodies are captured
+    pub duration: ConfigDuration,
+    /// Maximum number of captures kept, the oldest are dropped first
+    pub max_entries: usize,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            duration: ConfigDuration::from_secs(60 * 60),
+            max_entries: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capture {
+    pub quote_id: Option<Uuid>,
+    /// Unix time the request was received
+    pub captured_at: u64,
+    pub route: String,
+    /// Redacted request body, or a note when the body was not JSON
+    pub request: Value,
+    pub status: u16,
+    pub response: String,
+}
+
+#[derive(Debug)]
+pub struct CaptureLog {
+    settings: CaptureSettings,
+    started: Instant,
+    captures: Mutex<VecDeque<Capture>>,
+}
+
+impl CaptureLog {
+    pub fn new(settings: CaptureSettings) -> Self {
+        if settings.enabled {
+            tracing::warn!(
+                "Debug capture of payment bodies is on for the next {}",
+                settings.duration
+            );
+        }
+
+        Self {
+            settings,
+            started: Instant::now(),
+            captures: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether bodies are currently being captured
+    pub fn is_active(&self) -> bool {
+        self.settings.enabled && self.started.elapsed() < self.settings.duration.as_duration()
+    }
+
+    /// Redact and store a request body with the response it got, if capturing is active
+    pub fn record(&self, route: &str, body: &[u8], status: u16, response: String) {
+        if !self.is_active() {
+            return;
+        }
+
+        let request = match serde_json::from_slice::<Value>(body) {
+            Ok(mut value) => {
+                redact(&mut value);
+                value
+            }
+            // Without structure there is no telling where the secrets are, so keep nothing
+            Err(e) => Value::String(format!(
+                "<{} byte body that is not JSON: {}>",
+                body.len(),
+                e
+            )),
+        };
+
+        let quote_id = request
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(|id| Uuid::parse_str(id).ok());
+
+        let mut captures = self.captures.lock().unwrap_or_else(|e| e.into_inner());
+
+        while captures.len() >= self.settings.max_entries.max(1) {
+            captures.pop_front();
+        }
+
+        captures.push_back(Capture {
+            quote_id,
+            captured_at: unix_time(),
+            route: route.to_string(),
+            request,
+            status,
+            response,
+        });
+    }
+
+    /// Stored captures, oldest first, optionally only those for `quote_id`
+    pub fn captures(&self, quote_id: Option<Uuid>) -> Vec<Capture> {
+        let captures = self.captures.lock().unwrap_or_else(|e| e.into_inner());
+
+        captures
+            .iter()
+            .filter(|capture| quote_id.is_none() || capture.quote_id == quote_id)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Replace every sensitive field in `value` with the hash of its JSON encoding
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    let digest = Sha256::digest(field.to_string().as_bytes());
+                    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+                    *field = Value::String(format!("sha256:{}", hex));
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+
+    const SECRET: &str = "407915bc212be61a77e3e6d2aeb4c727980bda51cd06a6afc29e2861768a7837";
+    const SIGNATURE: &str = "02bc9097997d81afb2cc7346b5e4345a9346bd2a506eb7958598a72f0cf85163ea";
+    const WITNESS_SIGNATURE: &str = "60f3c9b766770b46caac1d27e1ae6b77c8866ebaeba0b9489fe6a15a837eaa6fcd6eaa825499c72ac342983983fd3ba3a8a41f56677cc99ffd73da68b59e1383";
+    const DLEQ_E: &str = "b31e58ac6527f34975ffab13e70a48b6d2b0d35abc4b03f0151f09ee1a9763d4";
+
+    fn settings(max_entries: usize) -> CaptureSettings {
+        CaptureSettings {
+            enabled: true,
+            max_entries,
+            ..Default::default()
+        }
+    }
+
+    fn payload(quote_id: Uuid) -> Value {
+        json!({
+            "id": quote_id.to_string(),
+            "mint": "https://mint.example.com",
+            "unit": "sat",
+            "memo": "coffee",
+            "proofs": [
+                {
+                    "amount": 2,
+                    "id": "009a1f293253e41e",
+                    "secret": SECRET,
+                    "C": SIGNATURE,
+                    "dleq": { "e": DLEQ_E, "s": DLEQ_E, "r": DLEQ_E },
+                },
+                {
+                    "amount": 8,
+                    "id": "009a1f293253e41e",
+                    "secret": format!(r#"["P2PK",{{"nonce":"{}","data":"{}"}}]"#, SECRET, SIGNATURE),
+                    "C": SIGNATURE,
+                    "witness": format!(r#"{{"signatures":["{}"]}}"#, WITNESS_SIGNATURE),
+                },
+            ],
+        })
+    }
+
+    fn record(log: &CaptureLog, body: &Value) {
+        log.record(
+            "/v1/payment",
+            body.to_string().as_bytes(),
+            200,
+            String::new(),
+        );
+    }
+
+    #[test]
+    fn redaction_removes_every_secret() {
+        let log = CaptureLog::new(settings(10));
+        let quote_id = Uuid::new_v4();
+        record(&log, &payload(quote_id));
+
+        let captures = log.captures(Some(quote_id));
+        assert_eq!(captures.len(), 1);
+
+        let captured = captures[0].request.to_string();
+        for secret in [SECRET, SIGNATURE, WITNESS_SIGNATURE, DLEQ_E] {
+            assert!(
+                !captured.contains(secret),
+                "{} left in {}",
+                secret,
+                captured
+            );
+        }
+
+        let proofs = captures[0].request["proofs"].as_array().unwrap();
+        for proof in proofs {
+            for field in ["secret", "C"] {
+                assert!(proof[field].as_str().unwrap().starts_with("sha256:"));
+            }
+        }
+        assert!(proofs[0]["dleq"].as_str().unwrap().starts_with("sha256:"));
+        assert!(
+            proofs[1]["witness"]
+                .as_str()
+                .unwrap()
+                .starts_with("sha256:")
+        );
+
+        // Equal values hash alike, so captures still show which proofs repeat
+        assert_eq!(proofs[0]["C"], proofs[1]["C"]);
+        assert_ne!(proofs[0]["secret"], proofs[1]["secret"]);
+
+        // Everything else is kept as sent
+        assert_eq!(captures[0].request["mint"], "https://mint.example.com");
+        assert_eq!(captures[0].request["memo"], "coffee");
+        assert_eq!(proofs[1]["amount"], 8);
+        assert_eq!(proofs[1]["id"], "009a1f293253e41e");
+    }
+
+    #[test]
+    fn bodies_that_are_not_json_are_not_kept() {
+        let log = CaptureLog::new(settings(10));
+        let body = format!("secret={}", SECRET);
+        log.record(
+            "/v1/payment",
+            body.as_bytes(),
+            400,
+            "Invalid JSON".to_string(),
+        );
+
+        let captures = log.captures(None);
+        assert_eq!(captures.len(), 1);
+        assert!(!captures[0].request.to_string().contains(SECRET));
+        assert_eq!(captures[0].quote_id, None);
+    }
+
+    #[test]
+    fn oldest_captures_are_dropped_first() {
+        let log = CaptureLog::new(settings(2));
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            record(&log, &payload(*id));
+        }
+
+        let kept: Vec<Option<Uuid>> = log
+            .captures(None)
+            .into_iter()
+            .map(|capture| capture.quote_id)
+            .collect();
+        assert_eq!(kept, vec![Some(ids[1]), Some(ids[2])]);
+        assert!(log.captures(Some(ids[0])).is_empty());
+    }
+
+    #[test]
+    fn capture_switches_itself_off() {
+        let mut log = CaptureLog::new(CaptureSettings {
+            duration: ConfigDuration::from_secs(60),
+            ..settings(10)
+        });
+        assert!(log.is_active());
+
+        log.started = Instant::now() - Duration::from_secs(61);
+        assert!(!log.is_active());
+
+        record(&log, &payload(Uuid::new_v4()));
+        assert!(log.captures(None).is_empty());
+    }
+
+    #[test]
+    fn disabled_capture_records_nothing() {
+        let log = CaptureLog::new(CaptureSettings::default());
+        assert!(!log.is_active());
+
+        record(&log, &payload(Uuid::new_v4()));
+        assert!(log.captures(None).is_empty());
+    }
+}
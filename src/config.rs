@@ -1,46 +1,202 @@
-use anyhow::Result;
-use config::{Config, ConfigError, File};
+use anyhow::{Result, anyhow, bail};
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use bip39::Mnemonic;
+use cdk::mint_url::MintUrl;
+use cdk::nuts::{CurrencyUnit, SecretKey};
+use config::{Config, ConfigError, Environment, File, Source};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing_subscriber::EnvFilter;
+
+use crate::capture::CaptureSettings;
+use crate::consolidation::{ConsolidationSettings, ConsolidationTarget};
+use crate::exchange_rate::{ExchangeRateProvider, ExchangeRateSettings};
+use crate::log_throttle::LogThrottleSettings;
+use crate::rate_limit::RateLimitSettings;
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::sweep::{LightningAddress, SweepSettings};
+pub use crate::types::{AmountCfg, ConfigDuration};
+use crate::types::{AmountEncoding, DisconnectPolicy, OverpaymentPolicy, parse_accepted_units};
+use crate::validation::ValidationRule;
 
 #[derive(Debug, Deserialize, Default, Serialize)]
 pub struct PosConfig {
     pub listen_host: String,
     pub listen_port: u16,
+    /// Unix socket path to listen on instead of `listen_host` and `listen_port`
+    #[serde(default)]
+    pub listen_socket: Option<PathBuf>,
     pub payment_url: String,
+    /// PEM certificate chain to serve HTTPS with, plain HTTP unless both this and `tls_key_path` are set
+    #[serde(default)]
+    pub tls_cert_path: Option<PathBuf>,
+    /// PEM private key of the certificate
+    #[serde(default)]
+    pub tls_key_path: Option<PathBuf>,
+    /// Accepted mint URLs in priority order
     pub accepted_mints: Vec<String>,
+    /// Maximum number of mints listed in a payment request, unlimited if unset
+    #[serde(default)]
+    pub max_mints_per_request: Option<usize>,
+    /// Encoded payment request length above which a warning is logged, 1000 if unset
+    #[serde(default)]
+    pub payment_request_warn_length: Option<usize>,
+    /// Units quotes may be created in, `["sat", "usd"]` if unset
+    #[serde(default)]
+    pub accepted_units: Option<Vec<String>>,
+    /// Longest quote memo accepted in characters, 256 if unset
+    #[serde(default)]
+    pub max_memo_length: Option<usize>,
+    /// Sampling of repeated payment failure logs
+    #[serde(default)]
+    pub log_throttle: LogThrottleSettings,
+    /// `complete` or `cancel` a payment whose client disconnected before the wallet receive
+    #[serde(default)]
+    pub disconnect_policy: DisconnectPolicy,
+    /// Validation rules that only log would-be rejections instead of enforcing them
+    #[serde(default)]
+    pub shadow_mode: Vec<ValidationRule>,
+    /// Include per-phase timings in quote and payment responses
+    #[serde(default)]
+    pub diagnostics: bool,
+    /// Enable the payment simulation endpoint for wallet integration testing
+    #[serde(default)]
+    pub sandbox: bool,
+    /// How long a new quote accepts payment for, in seconds or e.g. `"15m"`. Quotes never
+    /// expire if unset
+    #[serde(default)]
+    pub quote_expiry_seconds: Option<ConfigDuration>,
+    /// Most quotes open for payment at once, quote creation fails beyond it. Unlimited if unset
+    #[serde(default)]
+    pub max_open_quotes: Option<usize>,
+    /// Time between sweeps storing unpaid quotes past their expiry as expired, 60s if unset
+    #[serde(default)]
+    pub expiry_sweep_interval_seconds: Option<ConfigDuration>,
+    /// Time to wait on shutdown for open requests and payments in progress, 30s if unset
+    #[serde(default)]
+    pub shutdown_grace_period_seconds: Option<ConfigDuration>,
+    /// Temporary capture of redacted payment bodies for debugging wallet interop
+    #[serde(default)]
+    pub debug_capture: CaptureSettings,
+    /// Offset from UTC in minutes of the local timezone receipt numbers reset in
+    #[serde(default)]
+    pub receipt_utc_offset_minutes: i32,
+    /// `accept` or `reject` payments worth more than their quote
+    #[serde(default)]
+    pub overpayment_policy: OverpaymentPolicy,
+    /// Minor units a payment may exceed its quote by before `reject` applies
+    #[serde(default)]
+    pub overpayment_tolerance: u64,
+    /// `number` or `string` encoding of amounts in responses unless a request asks otherwise
+    #[serde(default)]
+    pub amount_encoding: AmountEncoding,
+    /// Accept payloads covering part of a quote, the quote is paid once they add up
+    #[serde(default)]
+    pub partial_payments: bool,
+    /// URL POSTed a JSON notification whenever a quote is paid
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Enable the endpoints that move funds out of the wallet
+    #[serde(default)]
+    pub wallet_routes: bool,
+    /// Keep serving the API at its unversioned paths next to `/v1`, true if unset
+    #[serde(default)]
+    pub legacy_routes: Option<bool>,
+    /// Key expected as `Authorization: Bearer <api_key>` on merchant and admin routes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Per client request limits on quote creation and payment submission
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    /// Ticker pricing quotes given in fiat
+    #[serde(default)]
+    pub exchange_rate: ExchangeRateSettings,
+    /// Merging of small received proofs once a wallet holds too many
+    #[serde(default)]
+    pub consolidation: ConsolidationSettings,
+    /// Scheduled sweep of wallet balances to a lightning address
+    #[serde(default)]
+    pub sweep: SweepSettings,
+    /// Relays wallets may send payments to over the NUT-18 Nostr transport
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nostr_relays: Vec<String>,
+    /// Secret key, hex or nsec, Nostr transport payments are addressed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nostr_key: Option<String>,
+    /// Ask wallets to lock proofs to a P2PK key derived from the wallet seed (NUT-11)
+    #[serde(default)]
+    pub p2pk_lock: bool,
+    /// Hex secret key to lock proofs to instead of the seed derived one, implies `p2pk_lock`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p2pk_key: Option<String>,
+    /// Refuse payloads with proofs not locked to the P2PK key
+    #[serde(default)]
+    pub require_p2pk: bool,
+    /// Origins allowed to call the API from a browser
+    #[serde(default)]
+    pub cors: CorsSettings,
+    /// Wallet mnemonic, the seed file in the work directory is used if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+}
+
+pub fn default_shutdown_grace_period_seconds() -> u64 {
+    30
 }
 
+/// Prefix of environment variables overriding config values, e.g.
+/// `CASHU_POS__POS__LISTEN_PORT=8080` sets `pos.listen_port`
+pub const ENV_PREFIX: &str = "CASHU_POS";
+
+/// Config values that are lists, given comma separated in environment variables
+const ENV_LIST_KEYS: [&str; 4] = [
+    "pos.accepted_mints",
+    "pos.accepted_units",
+    "pos.nostr_relays",
+    "pos.shadow_mode",
+];
+
 #[derive(Debug, Deserialize, Default, Serialize)]
 pub struct AppConfig {
     pub pos: PosConfig,
+    #[serde(default)]
+    pub logging: LoggingSettings,
 }
 
 impl AppConfig {
+    /// Load the config from defaults, overridden by the file at `config_file_name`
+    /// (`~/.cashu-pos/config.toml` if unset), overridden in turn by `CASHU_POS__` environment
+    /// variables
     pub fn new<P>(config_file_name: Option<P>) -> Result<Self, ConfigError>
     where
         P: Into<PathBuf>,
     {
-        let mut default_config_file_name = home::home_dir()
-            .ok_or(ConfigError::NotFound("Config Path".to_string()))?
-            .join(".cashu-pos");
-
-        // Create the directory if it doesn't exist
-        if !default_config_file_name.exists() {
-            std::fs::create_dir_all(&default_config_file_name).map_err(|e| {
-                ConfigError::Message(format!("Failed to create config directory: {}", e))
-            })?;
-        }
-
-        default_config_file_name.push("config.toml");
         let config_path: PathBuf = match config_file_name {
             Some(value) => value.into(),
-            None => default_config_file_name,
+            None => {
+                let default_dir = home::home_dir()
+                    .ok_or(ConfigError::NotFound("Config Path".to_string()))?
+                    .join(".cashu-pos");
+
+                // Create the directory if it doesn't exist
+                std::fs::create_dir_all(&default_dir).map_err(|e| {
+                    ConfigError::Message(format!("Failed to create config directory: {}", e))
+                })?;
+
+                default_dir.join("config.toml")
+            }
         };
 
         // Create example config if no config file exists
         if !config_path.exists() {
-            let example_path = config_path.parent().unwrap().join("example.config.toml");
+            let example_path = config_path
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join("example.config.toml");
             if !example_path.exists() {
                 let example_content = include_str!("../example.config.toml");
                 std::fs::write(&example_path, example_content).map_err(|e| {
@@ -55,6 +211,17 @@ impl AppConfig {
             }
         }
 
+        Self::layered(
+            File::with_name(&config_path.to_string_lossy()),
+            Self::environment(),
+        )
+    }
+
+    /// Merge the defaults, `file` and `environment`, each overriding the ones before
+    fn layered<F>(file: F, environment: Environment) -> Result<Self, ConfigError>
+    where
+        F: Source + Send + Sync + 'static,
+    {
         let default = &AppConfig::default();
 
         let builder = Config::builder();
@@ -62,10 +229,685 @@ impl AppConfig {
             // use defaults
             .add_source(Config::try_from(default)?)
             // override with file contents
-            .add_source(File::with_name(&config_path.to_string_lossy()))
+            .add_source(file)
+            // override with the environment
+            .add_source(environment)
+            .build()?;
+
+        Self::from_config(config)
+    }
+
+    /// Listen on `listen` instead of the configured address or socket, for `--listen` on the
+    /// command line
+    pub fn override_listen(&mut self, listen: SocketAddr) {
+        self.pos.listen_host = listen.ip().to_string();
+        self.pos.listen_port = listen.port();
+        self.pos.listen_socket = None;
+    }
+
+    /// Deserialize the merged sources, naming the key of any value that fails to parse
+    fn from_config(config: Config) -> Result<Self, ConfigError> {
+        serde_path_to_error::deserialize(config).map_err(|e| {
+            ConfigError::Message(format!("Invalid value for {}: {}", e.path(), e.inner()))
+        })
+    }
+
+    fn environment() -> Environment {
+        ENV_LIST_KEYS.iter().fold(
+            Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("__")
+                .separator("__")
+                .try_parsing(true)
+                .list_separator(","),
+            |environment, key| environment.with_list_parse_key(key),
+        )
+    }
+
+    /// Check the settings the server needs to start are present and well formed
+    pub fn validate(&self) -> Result<()> {
+        let pos = &self.pos;
+
+        if pos.listen_socket.is_some() {
+            // Unset fields keep the empty defaults, anything else was set in the file
+            if !pos.listen_host.is_empty() || pos.listen_port != 0 {
+                bail!(
+                    "pos.listen_socket cannot be combined with pos.listen_host and pos.listen_port, remove one or the other"
+                );
+            }
+
+            if pos.tls_cert_path.is_some() {
+                bail!("pos.listen_socket cannot be combined with TLS, terminate TLS at the proxy");
+            }
+        } else if SocketAddr::from_str(&format!("{}:{}", pos.listen_host, pos.listen_port)).is_err()
+        {
+            bail!(
+                "pos.listen_host and pos.listen_port do not form a valid socket address: {}:{}",
+                pos.listen_host,
+                pos.listen_port
+            );
+        }
+
+        if !pos.payment_url.starts_with("http://") && !pos.payment_url.starts_with("https://") {
+            bail!(
+                "pos.payment_url must be an http:// or https:// URL, got \"{}\"",
+                pos.payment_url
+            );
+        }
+
+        if pos.tls_cert_path.is_some() != pos.tls_key_path.is_some() {
+            bail!("pos.tls_cert_path and pos.tls_key_path must be set together");
+        }
+
+        if pos.accepted_mints.is_empty() {
+            bail!("pos.accepted_mints must list at least one mint");
+        }
+
+        for mint in pos.accepted_mints.iter() {
+            if let Err(e) = MintUrl::from_str(mint) {
+                bail!(
+                    "pos.accepted_mints contains an invalid mint url {}: {}",
+                    mint,
+                    e
+                );
+            }
+        }
+
+        if pos
+            .api_key
+            .as_ref()
+            .is_some_and(|key| key.trim().is_empty())
+        {
+            bail!("pos.api_key must not be empty when set");
+        }
+
+        if pos.wallet_routes && pos.api_key.is_none() {
+            bail!(
+                "pos.wallet_routes needs pos.api_key to be set, they move funds out of the wallet"
+            );
+        }
+
+        match (&pos.nostr_key, pos.nostr_relays.is_empty()) {
+            (Some(_), true) => {
+                bail!("pos.nostr_relays must list at least one relay when pos.nostr_key is set")
+            }
+            (None, false) => bail!("pos.nostr_key must be set when pos.nostr_relays is"),
+            _ => (),
+        }
+
+        if let Some(key) = &pos.nostr_key {
+            if let Err(e) = nostr_sdk::Keys::parse(key) {
+                bail!("pos.nostr_key is not a valid nostr secret key: {}", e);
+            }
+        }
+
+        if let Some(relay) = pos
+            .nostr_relays
+            .iter()
+            .find(|relay| !relay.starts_with("wss://") && !relay.starts_with("ws://"))
+        {
+            bail!(
+                "pos.nostr_relays must be ws:// or wss:// URLs, got \"{}\"",
+                relay
+            );
+        }
+
+        if let Some(Err(e)) = pos.p2pk_key.as_deref().map(SecretKey::from_hex) {
+            bail!("pos.p2pk_key is not a valid hex secret key: {}", e);
+        }
+
+        if pos.require_p2pk && !pos.p2pk_lock && pos.p2pk_key.is_none() {
+            bail!("pos.require_p2pk needs pos.p2pk_lock or pos.p2pk_key to be set");
+        }
+
+        if pos.exchange_rate.provider == Some(ExchangeRateProvider::Custom)
+            && (pos.exchange_rate.url.is_none() || pos.exchange_rate.price_pointer.is_none())
+        {
+            bail!("pos.exchange_rate needs url and price_pointer with the custom provider");
+        }
+
+        if pos.consolidation.interval_seconds.as_duration() < Duration::from_secs(1) {
+            bail!("pos.consolidation.interval_seconds must be at least 1s");
+        }
+
+        if pos.consolidation.split_target == ConsolidationTarget::Value(0) {
+            bail!("pos.consolidation.split_target value must be at least 1");
+        }
+
+        if let Some(address) = &pos.sweep.lightning_address {
+            LightningAddress::new(address)?;
+
+            if pos.sweep.interval_seconds.as_duration() < Duration::from_secs(1) {
+                bail!("pos.sweep.interval_seconds must be at least 1s");
+            }
+
+            if let Err(e) = pos.sweep.threshold.value_for(&CurrencyUnit::Sat) {
+                bail!("pos.sweep.threshold must be in sat: {}", e);
+            }
+        }
+
+        // Connections on a Unix socket carry no client address, so every request would go
+        // unlimited unless the proxy in front names the client
+        if pos.listen_socket.is_some()
+            && !pos.rate_limit.trust_proxy
+            && (pos.rate_limit.create_per_minute.is_some()
+                || pos.rate_limit.payment_per_minute.is_some())
+        {
+            bail!(
+                "pos.rate_limit limits need pos.rate_limit.trust_proxy = true with pos.listen_socket, clients on a Unix socket have no address to limit by"
+            );
+        }
+
+        if pos.rate_limit.create_per_minute == Some(0)
+            || pos.rate_limit.payment_per_minute == Some(0)
+        {
+            bail!(
+                "pos.rate_limit limits must be at least 1 when set, leave them unset for no limit"
+            );
+        }
+
+        if let Some(units) = &pos.accepted_units {
+            if units.is_empty() {
+                bail!("pos.accepted_units must list at least one unit when set");
+            }
+
+            if let Err(e) = parse_accepted_units(units) {
+                bail!("pos.accepted_units is invalid: {}", e);
+            }
+        }
+
+        if pos.max_mints_per_request == Some(0) {
+            bail!("pos.max_mints_per_request must be at least 1 when set");
+        }
+
+        if let Some(url) = pos
+            .webhook_url
+            .as_ref()
+            .filter(|url| !url.starts_with("http://") && !url.starts_with("https://"))
+        {
+            bail!(
+                "pos.webhook_url must be an http:// or https:// URL, got \"{}\"",
+                url
+            );
+        }
+
+        if pos
+            .quote_expiry_seconds
+            .is_some_and(|duration| duration.as_duration() < Duration::from_secs(1))
+        {
+            bail!("pos.quote_expiry_seconds must be at least 1s when set");
+        }
+
+        if pos
+            .expiry_sweep_interval_seconds
+            .is_some_and(|duration| duration.as_duration() < Duration::from_secs(1))
+        {
+            bail!("pos.expiry_sweep_interval_seconds must be at least 1s when set");
+        }
+
+        if pos
+            .shutdown_grace_period_seconds
+            .is_some_and(|duration| duration.as_duration() < Duration::from_secs(1))
+        {
+            bail!("pos.shutdown_grace_period_seconds must be at least 1s when set");
+        }
+
+        if pos.receipt_utc_offset_minutes.abs() >= 24 * 60 {
+            bail!(
+                "pos.receipt_utc_offset_minutes must be less than a day, got {}",
+                pos.receipt_utc_offset_minutes
+            );
+        }
+
+        if let Some(Err(e)) = pos
+            .mnemonic
+            .as_ref()
+            .map(|mnemonic| Mnemonic::from_str(mnemonic.trim()))
+        {
+            bail!("pos.mnemonic is not a valid BIP39 mnemonic: {}", e);
+        }
+
+        pos.cors.layer()?;
+
+        self.logging.filter()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsSettings {
+    /// Origins such as `https://shop.example.com`, or `["*"]` for any origin. Browsers
+    /// can't call the API from other origins if empty
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Let browsers send cookies and `Authorization` headers, not allowed with `*`
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+            allow_credentials: false,
+        }
+    }
+}
+
+impl CorsSettings {
+    /// Layer applying the policy, `None` if no origin is allowed
+    ///
+    /// Fails on origins or methods that are not well formed, rather than the layer
+    /// panicking on the first request.
+    pub fn layer(&self) -> Result<Option<CorsLayer>> {
+        if self.allowed_origins.is_empty() {
+            return Ok(None);
+        }
+
+        if self.allowed_origins.iter().any(|origin| origin == "*") {
+            if self.allowed_origins.len() > 1 {
+                bail!("pos.cors.allowed_origins must not list other origins alongside \"*\"");
+            }
+
+            if self.allow_credentials {
+                bail!("pos.cors.allow_credentials cannot be used with allowed_origins = [\"*\"]");
+            }
+
+            return Ok(Some(CorsLayer::permissive()));
+        }
+
+        let origins = self
+            .allowed_origins
+            .iter()
+            .map(|origin| parse_origin(origin))
+            .collect::<Result<Vec<_>>>()?;
+
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_str(&method.to_uppercase()).map_err(|_| {
+                    anyhow!(
+                        "pos.cors.allowed_methods contains an invalid method {}",
+                        method
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(
+            CorsLayer::new()
+                .allow_origin(AllowOrigin::list(origins))
+                .allow_methods(methods)
+                .allow_headers([
+                    header::CONTENT_TYPE,
+                    header::AUTHORIZATION,
+                    HeaderName::from_static(REQUEST_ID_HEADER),
+                ])
+                .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER)])
+                .allow_credentials(self.allow_credentials),
+        ))
+    }
+}
+
+/// Parse an origin, which browsers send as scheme, host and port only
+fn parse_origin(origin: &str) -> Result<HeaderValue> {
+    let invalid = |reason: &str| {
+        anyhow!(
+            "pos.cors.allowed_origins contains an invalid origin {}: {}",
+            origin,
+            reason
+        )
+    };
+
+    let url = url::Url::parse(origin).map_err(|e| invalid(&e.to_string()))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid("must be http:// or https://"));
+    }
+
+    if url.origin().ascii_serialization() != origin {
+        return Err(invalid(
+            "must be scheme and host only, without a path or trailing slash",
+        ));
+    }
+
+    HeaderValue::from_str(origin).map_err(|e| invalid(&e.to_string()))
+}
+
+/// Dependencies logging too much at debug level, kept at warn unless `level` names them
+const QUIET_TARGETS: [&str; 4] = ["sqlx", "hyper", "h2", "rustls"];
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the enclosing spans such as the quote
+    /// id and mint of a payment
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingSettings {
+    /// Filter directives such as `info` or `info,cashu_pos=debug`, `RUST_LOG` takes precedence
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            level: "debug".to_string(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl LoggingSettings {
+    /// Filter from `RUST_LOG` if it is set, otherwise from `level`
+    pub fn filter(&self) -> Result<EnvFilter> {
+        if let Ok(directives) = std::env::var(EnvFilter::DEFAULT_ENV) {
+            return EnvFilter::try_new(&directives)
+                .map_err(|e| anyhow!("RUST_LOG is not a valid log filter: {}", e));
+        }
+
+        let directives = QUIET_TARGETS
+            .iter()
+            .filter(|target| {
+                !self
+                    .level
+                    .split(',')
+                    .any(|directive| directive.trim().starts_with(*target))
+            })
+            .fold(self.level.clone(), |directives, target| {
+                format!("{},{}=warn", directives, target)
+            });
+
+        EnvFilter::try_new(&directives).map_err(|e| {
+            anyhow!(
+                "logging.level {} is not a valid log filter: {}",
+                self.level,
+                e
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::FileFormat;
+
+    use super::*;
+
+    const BASE: &str = r#"
+        [pos]
+        listen_host = "127.0.0.1"
+        listen_port = 8080
+        payment_url = "https://pos.example.com"
+        accepted_mints = ["https://mint.example.com"]
+    "#;
+
+    /// Config from the defaults overridden by `BASE` with `extra` appended to it
+    fn load(extra: &str) -> Result<AppConfig, ConfigError> {
+        let config = Config::builder()
+            .add_source(Config::try_from(&AppConfig::default())?)
+            .add_source(File::from_str(
+                &format!("{}\n{}", BASE, extra),
+                FileFormat::Toml,
+            ))
             .build()?;
-        let settings: AppConfig = config.try_deserialize()?;
 
-        Ok(settings)
+        AppConfig::from_config(config)
+    }
+
+    fn load_error(extra: &str) -> String {
+        load(extra).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_survive_the_config_round_trip() {
+        let config = load("").unwrap();
+
+        assert_eq!(config.pos.quote_expiry_seconds, None);
+        assert_eq!(config.pos.shutdown_grace_period_seconds, None);
+        assert_eq!(config.pos.sweep.threshold.value, 10_000);
+        assert_eq!(config.pos.sweep.threshold.unit, None);
+        assert_eq!(
+            config.pos.sweep.interval_seconds.as_duration(),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            config.pos.exchange_rate.cache_ttl_seconds.as_duration(),
+            Duration::from_secs(60)
+        );
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn durations_take_seconds_or_units() {
+        let config = load(
+            r#"
+            quote_expiry_seconds = 900
+            expiry_sweep_interval_seconds = "2m"
+            shutdown_grace_period_seconds = "1m30s"
+
+            [pos.sweep]
+            interval_seconds = "6h"
+
+            [pos.consolidation]
+            interval_seconds = 7200
+
+            [pos.exchange_rate]
+            cache_ttl_seconds = "90s"
+            "#,
+        )
+        .unwrap();
+
+        let secs = |duration: Option<ConfigDuration>| duration.map(|d| d.as_duration().as_secs());
+        assert_eq!(secs(config.pos.quote_expiry_seconds), Some(900));
+        assert_eq!(secs(config.pos.expiry_sweep_interval_seconds), Some(120));
+        assert_eq!(secs(config.pos.shutdown_grace_period_seconds), Some(90));
+        assert_eq!(
+            config.pos.sweep.interval_seconds.as_duration().as_secs(),
+            6 * 3600
+        );
+        assert_eq!(
+            config
+                .pos
+                .consolidation
+                .interval_seconds
+                .as_duration()
+                .as_secs(),
+            7200
+        );
+        assert_eq!(
+            config
+                .pos
+                .exchange_rate
+                .cache_ttl_seconds
+                .as_duration()
+                .as_secs(),
+            90
+        );
+    }
+
+    #[test]
+    fn sweep_threshold_takes_an_integer_or_an_amount() {
+        let plain = load("[pos.sweep]\nthreshold = 5000").unwrap();
+        assert_eq!(plain.pos.sweep.threshold.value, 5000);
+        assert_eq!(plain.pos.sweep.threshold.unit, None);
+
+        let with_unit = load("[pos.sweep]\nthreshold = \"5000 sat\"").unwrap();
+        assert_eq!(with_unit.pos.sweep.threshold.value, 5000);
+        assert_eq!(with_unit.pos.sweep.threshold.unit, Some(CurrencyUnit::Sat));
+    }
+
+    #[test]
+    fn malformed_values_name_their_key() {
+        let cases = [
+            (
+                "quote_expiry_seconds = \"15 minutes\"",
+                "pos.quote_expiry_seconds",
+            ),
+            (
+                "expiry_sweep_interval_seconds = -5",
+                "pos.expiry_sweep_interval_seconds",
+            ),
+            (
+                "shutdown_grace_period_seconds = \"1y\"",
+                "pos.shutdown_grace_period_seconds",
+            ),
+            ("[pos.sweep]\nthreshold = \"lots\"", "pos.sweep.threshold"),
+            (
+                "[pos.sweep]\nthreshold = \"5 sat extra\"",
+                "pos.sweep.threshold",
+            ),
+            (
+                "[pos.sweep]\ninterval_seconds = \"m\"",
+                "pos.sweep.interval_seconds",
+            ),
+            (
+                "[pos.consolidation]\ninterval_seconds = \"\"",
+                "pos.consolidation.interval_seconds",
+            ),
+            (
+                "[pos.exchange_rate]\ncache_ttl_seconds = \"1w\"",
+                "pos.exchange_rate.cache_ttl_seconds",
+            ),
+        ];
+
+        for (extra, key) in cases {
+            let error = load_error(extra);
+            assert!(error.contains(key), "{:?} gave {:?}", extra, error);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_sub_second_durations() {
+        for (extra, key) in [
+            (
+                "quote_expiry_seconds = \"500ms\"",
+                "pos.quote_expiry_seconds",
+            ),
+            (
+                "expiry_sweep_interval_seconds = 0",
+                "pos.expiry_sweep_interval_seconds",
+            ),
+            (
+                "shutdown_grace_period_seconds = \"0s\"",
+                "pos.shutdown_grace_period_seconds",
+            ),
+            (
+                "[pos.consolidation]\ninterval_seconds = \"999ms\"",
+                "pos.consolidation.interval_seconds",
+            ),
+        ] {
+            let error = load(extra).unwrap().validate().unwrap_err().to_string();
+            assert!(error.contains(key), "{:?} gave {:?}", extra, error);
+        }
+    }
+
+    /// `BASE` listening on a Unix socket instead of a TCP address
+    fn socket_config(extra: &str) -> AppConfig {
+        let mut config = load(extra).unwrap();
+        config.pos.listen_host = String::new();
+        config.pos.listen_port = 0;
+        config.pos.listen_socket = Some(PathBuf::from("/run/cashu-pos.sock"));
+        config
+    }
+
+    #[test]
+    fn validate_requires_trust_proxy_for_rate_limits_on_a_socket() {
+        socket_config("").validate().unwrap();
+
+        let error = socket_config("[pos.rate_limit]\ncreate_per_minute = 10")
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("pos.rate_limit.trust_proxy"), "{}", error);
+
+        socket_config("[pos.rate_limit]\npayment_per_minute = 10\ntrust_proxy = true")
+            .validate()
+            .unwrap();
+
+        // Over TCP the connection address is enough
+        load("[pos.rate_limit]\ncreate_per_minute = 10")
+            .unwrap()
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_sweep_threshold_in_another_unit() {
+        let config = load(
+            r#"
+            [pos.sweep]
+            lightning_address = "tips@example.com"
+            threshold = "5000 usd"
+            "#,
+        )
+        .unwrap();
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("pos.sweep.threshold"), "{}", error);
+    }
+
+    /// Config from the defaults, `BASE` as the file and `vars` as the environment
+    fn load_layered(vars: &[(&str, &str)]) -> AppConfig {
+        let environment = AppConfig::environment().source(Some(
+            vars.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        ));
+
+        AppConfig::layered(File::from_str(BASE, FileFormat::Toml), environment).unwrap()
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let config = load_layered(&[]);
+
+        assert_eq!(config.pos.listen_port, 8080);
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(config.pos.cors.allowed_methods, ["GET", "POST", "DELETE"]);
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let config = load_layered(&[
+            ("CASHU_POS__POS__LISTEN_PORT", "9090"),
+            (
+                "CASHU_POS__POS__ACCEPTED_MINTS",
+                "https://mint1.example.com,https://mint2.example.com",
+            ),
+            ("CASHU_POS__POS__QUOTE_EXPIRY_SECONDS", "15m"),
+        ]);
+
+        assert_eq!(config.pos.listen_host, "127.0.0.1");
+        assert_eq!(config.pos.listen_port, 9090);
+        assert_eq!(config.pos.accepted_mints.len(), 2);
+        assert_eq!(
+            config.pos.quote_expiry_seconds.map(|e| e.as_duration()),
+            Some(Duration::from_secs(900))
+        );
+    }
+
+    #[test]
+    fn command_line_listen_overrides_the_environment() {
+        let mut config = load_layered(&[
+            ("CASHU_POS__POS__LISTEN_PORT", "9090"),
+            ("CASHU_POS__POS__LISTEN_SOCKET", "/run/cashu-pos/pos.sock"),
+        ]);
+        assert!(config.pos.listen_socket.is_some());
+
+        config.override_listen("0.0.0.0:7000".parse().unwrap());
+
+        assert_eq!(config.pos.listen_host, "0.0.0.0");
+        assert_eq!(config.pos.listen_port, 7000);
+        assert_eq!(config.pos.listen_socket, None);
     }
 }
@@ -0,0 +1,207 @@
+//! Merging of small received proofs
+//!
+//! Every payment adds the payer's proofs to the wallet, so a busy day leaves
+//! thousands of small ones that slow down every later wallet operation. Wallets
+//! holding more proofs than the threshold swap them all for a fresh set in the
+//! configured denominations.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use cdk::amount::{Amount, SplitTarget};
+use cdk::mint_url::MintUrl;
+use cdk::nuts::CurrencyUnit;
+use cdk::wallet::Wallet;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::CashuPos;
+use crate::types::ConfigDuration;
+
+/// Denominations consolidated proofs are split into
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsolidationTarget {
+    /// As few proofs as possible, one per power of two in the balance
+    #[default]
+    Minimal,
+    /// Proofs adding up to this value each, for wallets that pay out similar amounts
+    Value(u64),
+}
+
+impl From<ConsolidationTarget> for SplitTarget {
+    fn from(target: ConsolidationTarget) -> Self {
+        match target {
+            ConsolidationTarget::Minimal => SplitTarget::None,
+            ConsolidationTarget::Value(value) => SplitTarget::Value(Amount::from(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsolidationSettings {
+    /// Consolidate periodically in the background, `POST /admin/consolidate` works regardless
+    pub enabled: bool,
+    /// Time between background runs, in seconds or e.g. `"1h"`
+    pub interval_seconds: ConfigDuration,
+    /// Proof count above which a wallet is consolidated
+    pub proof_threshold: usize,
+    pub split_target: ConsolidationTarget,
+}
+
+impl Default for ConsolidationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_seconds: ConfigDuration::from_secs(3600),
+            proof_threshold: 200,
+            split_target: ConsolidationTarget::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsolidationStatus {
+    /// The wallet held no more proofs than the threshold
+    Skipped,
+    Consolidated,
+    /// The swap failed, e.g. because the mint is offline, the proofs are left as they were
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletConsolidation {
+    pub mint: MintUrl,
+    pub unit: CurrencyUnit,
+    pub status: ConsolidationStatus,
+    pub proofs_before: usize,
+    pub proofs_after: usize,
+    /// Proofs removed by the swap, `proofs_before - proofs_after`
+    pub merged: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Consolidate every wallet holding more proofs than the threshold, one mint at a time
+///
+/// Only one run happens at a time, a second caller waits for the first or for a sweep,
+/// withdrawal or send in progress to finish.
+pub async fn consolidate(
+    pos: &CashuPos,
+    settings: &ConsolidationSettings,
+) -> Vec<WalletConsolidation> {
+    let _running = pos.consolidation.lock().await;
+
+    let mut report = Vec::new();
+    for wallet in pos.wallet.get_wallets().await {
+        report.push(consolidate_wallet(&wallet, settings).await);
+    }
+
+    let merged: usize = report.iter().map(|wallet| wallet.merged).sum();
+    if merged > 0 {
+        tracing::info!("Consolidation merged {} proofs", merged);
+    }
+
+    report
+}
+
+async fn consolidate_wallet(
+    wallet: &Wallet,
+    settings: &ConsolidationSettings,
+) -> WalletConsolidation {
+    let mut consolidation = WalletConsolidation {
+        mint: wallet.mint_url.clone(),
+        unit: wallet.unit.clone(),
+        status: ConsolidationStatus::Failed,
+        proofs_before: 0,
+        proofs_after: 0,
+        merged: 0,
+        error: None,
+    };
+
+    let proofs = match wallet.get_unspent_proofs().await {
+        Ok(proofs) => proofs,
+        Err(e) => {
+            consolidation.error = Some(format!("Could not read wallet proofs: {}", e));
+            return consolidation;
+        }
+    };
+
+    consolidation.proofs_before = proofs.len();
+    consolidation.proofs_after = proofs.len();
+
+    if proofs.len() <= settings.proof_threshold {
+        consolidation.status = ConsolidationStatus::Skipped;
+        return consolidation;
+    }
+
+    // Without an amount everything is returned as change split by the target
+    match wallet
+        .swap(None, settings.split_target.into(), proofs, None, false)
+        .await
+    {
+        Ok(_) => {
+            let proofs_after = match wallet.get_unspent_proofs().await {
+                Ok(proofs) => proofs.len(),
+                Err(e) => {
+                    tracing::warn!("Could not count proofs of {}: {}", wallet.mint_url, e);
+                    consolidation.proofs_before
+                }
+            };
+
+            consolidation.status = ConsolidationStatus::Consolidated;
+            consolidation.proofs_after = proofs_after;
+            consolidation.merged = consolidation.proofs_before.saturating_sub(proofs_after);
+
+            tracing::info!(
+                "Consolidated {} {} proofs of {} into {}",
+                consolidation.proofs_before,
+                wallet.unit,
+                wallet.mint_url,
+                proofs_after
+            );
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Could not consolidate {} proofs of {}: {}",
+                wallet.unit,
+                wallet.mint_url,
+                e
+            );
+            consolidation.error = Some(e.to_string());
+        }
+    }
+
+    consolidation
+}
+
+/// Consolidate every `interval_seconds` until `shutdown` is cancelled
+pub fn spawn_consolidation(
+    pos: Arc<CashuPos>,
+    settings: ConsolidationSettings,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(
+            settings
+                .interval_seconds
+                .as_duration()
+                .max(Duration::from_secs(1)),
+        );
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {
+                    consolidate(&pos, &settings).await;
+                }
+            }
+        }
+
+        tracing::debug!("Proof consolidation stopped");
+    })
+}
@@ -1,95 +1,661 @@
-use std::{path::PathBuf, sync::Arc};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use anyhow::{Result, anyhow};
-use redb::{Database, ReadableTable, TableDefinition};
+use async_trait::async_trait;
 use uuid::Uuid;
 
-use crate::types::{QuoteInfo, QuoteState};
+use crate::types::{
+    QuoteFilter, QuoteInfo, QuoteState, Receipt, ReceivedPayment, SentToken, Sweep, Withdrawal,
+};
 
-// <Y, QuoteInfo>
-const QUOTES_TABLE: TableDefinition<&[u8], &str> = TableDefinition::new("quotes");
+/// Failure of a [`QuoteStore`] operation
+#[derive(Debug)]
+pub enum DbError {
+    QuoteNotFound(Uuid),
+    /// A quote was not in the state a transition expected
+    StateConflict {
+        actual: QuoteState,
+    },
+    /// The storage backend failed, e.g. on I/O or a corrupt record
+    Backend(String),
+    /// The database was written by a newer version of the server
+    UnsupportedSchemaVersion {
+        found: u64,
+        supported: u64,
+    },
+}
 
-#[derive(Clone)]
-pub struct Db {
-    db: Arc<Database>,
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QuoteNotFound(id) => write!(f, "Unknown quote {}", id),
+            Self::StateConflict { actual } => write!(f, "Quote is {:?}", actual),
+            Self::Backend(msg) => write!(f, "Storage error: {}", msg),
+            Self::UnsupportedSchemaVersion { found, supported } => write!(
+                f,
+                "Database schema version {} is newer than the supported version {}",
+                found, supported
+            ),
+        }
+    }
 }
 
-impl Db {
-    pub fn new(path: PathBuf) -> Result<Self> {
-        let db = Database::create(path)?;
+impl std::error::Error for DbError {}
+
+macro_rules! backend_error_from {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for DbError {
+                fn from(err: $error) -> Self {
+                    Self::Backend(err.to_string())
+                }
+            }
+        )*
+    };
+}
+
+backend_error_from!(serde_json::Error, uuid::Error);
+
+#[cfg(feature = "store-redb")]
+mod redb;
+#[cfg(feature = "store-sqlite")]
+mod sqlite;
+
+#[cfg(feature = "store-redb")]
+pub use self::redb::{Db, SCHEMA_VERSION};
+#[cfg(feature = "store-sqlite")]
+pub use self::sqlite::SqliteDb;
+
+/// Storage of quotes and the records kept alongside them
+///
+/// [`Db`] stores them in redb, [`SqliteDb`] in SQLite and [`MemoryDb`] in memory. Implementations
+/// must make each method atomic, in particular [`QuoteStore::transition_quote_state`] and
+/// [`QuoteStore::record_payment`] check and update a quote as one step, since the
+/// server relies on them to stop a quote being paid twice.
+#[async_trait]
+pub trait QuoteStore: Send + Sync {
+    /// Check the store is reachable and usable
+    async fn check(&self) -> Result<(), DbError>;
+
+    /// Store a quote, pointing its reference, if any, at it
+    async fn add_quote(&self, quote_info: &QuoteInfo) -> Result<(), DbError>;
+
+    /// Add several quotes at once, either all or none are stored
+    async fn add_quotes(&self, quotes: &[QuoteInfo]) -> Result<(), DbError>;
+
+    async fn get_quote(&self, quote_id: Uuid) -> Result<QuoteInfo, DbError>;
+
+    /// Latest quote created with `reference`
+    async fn get_quote_by_reference(&self, reference: &str) -> Result<Option<QuoteInfo>, DbError>;
+
+    /// Up to `limit` quotes matching `filter` at unix time `now` following the quote `after`,
+    /// and the cursor for the next page if there are more
+    ///
+    /// Quotes are listed oldest first by `created_at`, those created in the same second by
+    /// id. Quotes stored before `created_at` was recorded come first. Fails with
+    /// [`DbError::QuoteNotFound`] if there is no quote `after`.
+    async fn list_quotes(
+        &self,
+        after: Option<Uuid>,
+        limit: usize,
+        filter: &QuoteFilter,
+        now: u64,
+    ) -> Result<(Vec<QuoteInfo>, Option<Uuid>), DbError>;
+
+    async fn update_quote_state(
+        &self,
+        quote_id: Uuid,
+        quote_state: QuoteState,
+    ) -> Result<QuoteInfo, DbError>;
+
+    /// Move a quote from `expected` to `new` state, failing with
+    /// [`DbError::StateConflict`] if the quote is in any other state
+    ///
+    /// Of several concurrent transitions out of the same state exactly one succeeds.
+    async fn transition_quote_state(
+        &self,
+        quote_id: Uuid,
+        expected: QuoteState,
+        new: QuoteState,
+    ) -> Result<QuoteInfo, DbError>;
+
+    /// Number of quotes that can still be paid at unix time `now`
+    async fn count_open_quotes(&self, now: u64) -> Result<usize, DbError>;
 
-        let write_txn = db.begin_write()?;
-        {
-            // Open all tables to init a new db
-            let _ = write_txn.open_table(QUOTES_TABLE)?;
+    /// Move up to `limit` `Unpaid` quotes past their expiry at unix time `now` to
+    /// `Expired`, returning how many were moved
+    async fn expire_quotes(&self, now: u64, limit: usize) -> Result<usize, DbError>;
+
+    /// Add `payment` to a `Pending` quote and keep it as an audit record
+    ///
+    /// The quote becomes `Paid` once its payments cover the quote amount, otherwise it is
+    /// `PartiallyPaid`. A quote becoming `Paid` gets the next receipt number for
+    /// `receipt_date`, so numbers within a day have no gaps.
+    async fn record_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+        receipt_date: String,
+    ) -> Result<QuoteInfo, DbError>;
+
+    /// Keep `payment` of a `Pending` quote whose payment could not be recorded and move the
+    /// quote to `Unsettled`
+    ///
+    /// Used once the proofs were redeemed, so the quote neither stays `Pending` nor takes
+    /// another payment before the merchant resolves it.
+    async fn hold_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+    ) -> Result<QuoteInfo, DbError>;
+
+    /// Payloads redeemed for a quote, oldest first
+    async fn get_payments(&self, quote_id: Uuid) -> Result<Vec<ReceivedPayment>, DbError>;
+
+    async fn add_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), DbError>;
+
+    async fn add_sent_token(&self, sent_token: &SentToken) -> Result<(), DbError>;
+
+    /// Every token sent from the wallet, newest first
+    async fn list_sent_tokens(&self) -> Result<Vec<SentToken>, DbError>;
+
+    async fn add_sweep(&self, sweep: &Sweep) -> Result<(), DbError>;
+
+    /// Every sweep attempt, newest first
+    async fn list_sweeps(&self) -> Result<Vec<Sweep>, DbError>;
+}
+
+#[derive(Default)]
+struct MemoryState {
+    quotes: HashMap<Uuid, QuoteInfo>,
+    references: HashMap<String, Uuid>,
+    payments: HashMap<Uuid, Vec<ReceivedPayment>>,
+    receipt_counters: HashMap<String, u64>,
+    withdrawals: HashMap<Uuid, Withdrawal>,
+    sent_tokens: HashMap<Uuid, SentToken>,
+    sweeps: HashMap<Uuid, Sweep>,
+}
+
+/// [`QuoteStore`] kept in memory, for tests and demos that shouldn't touch the filesystem
+///
+/// Every operation holds the lock for its whole duration, giving the same atomic
+/// check-and-update behaviour as the stores on disk. Everything is lost when it is dropped.
+#[derive(Default, Clone)]
+pub struct MemoryDb {
+    state: Arc<RwLock<MemoryState>>,
+}
+
+impl MemoryDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, MemoryState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, MemoryState> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update_quote<F>(&self, quote_id: Uuid, update: F) -> Result<QuoteInfo, DbError>
+    where
+        F: FnOnce(&mut QuoteInfo, &mut MemoryState) -> Result<(), DbError>,
+    {
+        let mut state = self.write();
+
+        let mut quote = state
+            .quotes
+            .get(&quote_id)
+            .cloned()
+            .ok_or(DbError::QuoteNotFound(quote_id))?;
+
+        // Changes are made to a copy so a failed update leaves the stored quote untouched
+        update(&mut quote, &mut *state)?;
+        state.quotes.insert(quote_id, quote.clone());
+
+        Ok(quote)
+    }
+}
+
+#[async_trait]
+impl QuoteStore for MemoryDb {
+    async fn check(&self) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    async fn add_quote(&self, quote_info: &QuoteInfo) -> Result<(), DbError> {
+        let mut state = self.write();
+
+        if let Some(reference) = &quote_info.reference {
+            state.references.insert(reference.clone(), quote_info.id);
+        }
+        state.quotes.insert(quote_info.id, quote_info.clone());
+
+        Ok(())
+    }
+
+    async fn add_quotes(&self, quotes: &[QuoteInfo]) -> Result<(), DbError> {
+        let mut state = self.write();
+
+        for quote_info in quotes {
+            state.quotes.insert(quote_info.id, quote_info.clone());
         }
 
-        write_txn.commit()?;
+        Ok(())
+    }
 
-        Ok(Self { db: Arc::new(db) })
+    async fn get_quote(&self, quote_id: Uuid) -> Result<QuoteInfo, DbError> {
+        self.read()
+            .quotes
+            .get(&quote_id)
+            .cloned()
+            .ok_or(DbError::QuoteNotFound(quote_id))
     }
 
-    pub fn add_quote(&self, quote_info: &QuoteInfo) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
+    async fn get_quote_by_reference(&self, reference: &str) -> Result<Option<QuoteInfo>, DbError> {
+        let state = self.read();
+
+        Ok(state
+            .references
+            .get(reference)
+            .and_then(|id| state.quotes.get(id))
+            .cloned())
+    }
+
+    async fn list_quotes(
+        &self,
+        after: Option<Uuid>,
+        limit: usize,
+        filter: &QuoteFilter,
+        now: u64,
+    ) -> Result<(Vec<QuoteInfo>, Option<Uuid>), DbError> {
+        let state = self.read();
+
+        // Same order as the created at index of [`Db`], so cursors behave alike on every store
+        let order = |quote: &QuoteInfo| (quote.created_at.unwrap_or_default(), quote.id);
 
-        {
-            let mut quote_table = write_txn.open_table(QUOTES_TABLE)?;
+        let after = match after {
+            Some(id) => Some(order(
+                state.quotes.get(&id).ok_or(DbError::QuoteNotFound(id))?,
+            )),
+            None => None,
+        };
 
-            let _ = quote_table.insert(
-                quote_info.id.into_bytes().as_slice(),
-                serde_json::to_string(quote_info)?.as_str(),
-            );
+        let mut quotes: Vec<&QuoteInfo> = state
+            .quotes
+            .values()
+            .filter(|quote| after.is_none_or(|after| order(quote) > after))
+            .filter(|quote| filter.matches(quote, now))
+            .collect();
+        quotes.sort_by_key(|quote| order(quote));
+
+        let next_cursor = match quotes.len() > limit {
+            true => limit
+                .checked_sub(1)
+                .and_then(|last| quotes.get(last))
+                .map(|quote| quote.id),
+            false => None,
+        };
+
+        Ok((
+            quotes.into_iter().take(limit).cloned().collect(),
+            next_cursor,
+        ))
+    }
+
+    async fn update_quote_state(
+        &self,
+        quote_id: Uuid,
+        quote_state: QuoteState,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, _| {
+            quote.state = quote_state;
+            Ok(())
+        })
+    }
+
+    async fn transition_quote_state(
+        &self,
+        quote_id: Uuid,
+        expected: QuoteState,
+        new: QuoteState,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, _| {
+            ensure_state(quote, expected)?;
+            quote.state = new;
+            Ok(())
+        })
+    }
+
+    async fn count_open_quotes(&self, now: u64) -> Result<usize, DbError> {
+        Ok(self
+            .read()
+            .quotes
+            .values()
+            .filter(|quote| is_open(quote, now))
+            .count())
+    }
+
+    async fn expire_quotes(&self, now: u64, limit: usize) -> Result<usize, DbError> {
+        let mut state = self.write();
+
+        let mut expired = 0;
+        for quote in state.quotes.values_mut() {
+            if expired >= limit {
+                break;
+            }
+
+            if is_stale(quote, now) {
+                quote.state = QuoteState::Expired;
+                expired += 1;
+            }
         }
 
-        write_txn.commit()?;
+        Ok(expired)
+    }
+
+    async fn record_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+        receipt_date: String,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, state| {
+            apply_payment(quote, &payment, receipt_date, |date| {
+                let counter = state.receipt_counters.entry(date.to_string()).or_default();
+                *counter += 1;
+
+                Ok(*counter)
+            })?;
+
+            state.payments.entry(quote_id).or_default().push(payment);
+
+            Ok(())
+        })
+    }
+
+    async fn hold_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, state| {
+            ensure_state(quote, QuoteState::Pending)?;
+            quote.state = QuoteState::Unsettled;
+
+            state.payments.entry(quote_id).or_default().push(payment);
+
+            Ok(())
+        })
+    }
+
+    async fn get_payments(&self, quote_id: Uuid) -> Result<Vec<ReceivedPayment>, DbError> {
+        Ok(self
+            .read()
+            .payments
+            .get(&quote_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn add_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), DbError> {
+        self.write()
+            .withdrawals
+            .insert(withdrawal.id, withdrawal.clone());
 
         Ok(())
     }
 
-    pub fn get_quote(&self, quote_id: Uuid) -> Result<QuoteInfo> {
-        let read_txn = self.db.begin_read()?;
+    async fn add_sent_token(&self, sent_token: &SentToken) -> Result<(), DbError> {
+        self.write()
+            .sent_tokens
+            .insert(sent_token.id, sent_token.clone());
 
-        let quote_table = read_txn.open_table(QUOTES_TABLE)?;
-        let quote_value = quote_table
-            .get(quote_id.into_bytes().as_slice())?
-            .ok_or(anyhow!("Unknown quote"))?;
+        Ok(())
+    }
 
-        let quote_value = quote_value.value();
-        let quote: QuoteInfo = serde_json::from_str(quote_value)?;
+    async fn list_sent_tokens(&self) -> Result<Vec<SentToken>, DbError> {
+        let mut sent_tokens: Vec<SentToken> = self.read().sent_tokens.values().cloned().collect();
+        sent_tokens.sort_by(|a, b| b.created_at.cmp(&a.created_at));
 
-        Ok(quote)
+        Ok(sent_tokens)
+    }
+
+    async fn add_sweep(&self, sweep: &Sweep) -> Result<(), DbError> {
+        self.write().sweeps.insert(sweep.id, sweep.clone());
+
+        Ok(())
+    }
+
+    async fn list_sweeps(&self) -> Result<Vec<Sweep>, DbError> {
+        let mut sweeps: Vec<Sweep> = self.read().sweeps.values().cloned().collect();
+        sweeps.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(sweeps)
+    }
+}
+
+/// Add `payment` to a `Pending` quote, numbering its receipt with `next_receipt_number`
+/// if the quote becomes paid
+///
+/// Shared by the stores so they agree on when a quote counts as paid.
+fn apply_payment<F>(
+    quote: &mut QuoteInfo,
+    payment: &ReceivedPayment,
+    receipt_date: String,
+    next_receipt_number: F,
+) -> Result<(), DbError>
+where
+    F: FnOnce(&str) -> Result<u64, DbError>,
+{
+    ensure_state(quote, QuoteState::Pending)?;
+
+    // A multi-unit quote is settled in the unit of its first payment
+    if !quote.settle_in(&payment.unit) {
+        return Err(DbError::Backend(format!(
+            "Quote {} cannot be paid in {}",
+            quote.id, payment.unit
+        )));
     }
 
-    pub fn update_quote_state(&self, quote_id: Uuid, quote_state: QuoteState) -> Result<QuoteInfo> {
-        let write_txn = self.db.begin_write()?;
+    let paid_amount = quote
+        .paid_amount
+        .unwrap_or_default()
+        .saturating_add(payment.amount);
+    quote.paid_amount = Some(paid_amount);
+    quote.payment_fingerprint = Some(payment.payment_fingerprint.clone());
 
-        let current_quote;
+    if paid_amount < quote.amount.value {
+        quote.state = QuoteState::PartiallyPaid;
+        return Ok(());
+    }
+
+    if quote.receipt.is_none() {
+        let number = next_receipt_number(&receipt_date)?;
+
+        quote.receipt = Some(Receipt {
+            date: receipt_date,
+            number,
+        });
+    }
+
+    quote.state = QuoteState::Paid;
+    quote.paid_at = Some(payment.received_at);
+    Ok(())
+}
+
+/// Whether `quote` is still stored as `Unpaid` although it has expired by `now`
+fn is_stale(quote: &QuoteInfo, now: u64) -> bool {
+    quote.state == QuoteState::Unpaid && quote.state_at(now) == QuoteState::Expired
+}
+
+/// Whether `quote` may still be paid at `now`
+fn is_open(quote: &QuoteInfo, now: u64) -> bool {
+    matches!(
+        quote.state_at(now),
+        QuoteState::Unpaid | QuoteState::Pending | QuoteState::PartiallyPaid
+    )
+}
+
+fn ensure_state(quote: &QuoteInfo, expected: QuoteState) -> Result<(), DbError> {
+    match quote.state == expected {
+        true => Ok(()),
+        false => Err(DbError::StateConflict {
+            actual: quote.state,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) fn quote_created_at(
+        id: Uuid,
+        created_at: Option<u64>,
+        state: QuoteState,
+    ) -> QuoteInfo {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "amount": 100,
+            "unit": "sat",
+            "state": state,
+            "created_at": created_at,
+        }))
+        .unwrap()
+    }
+
+    /// Store quotes whose ids sort in the reverse of their creation order and page through
+    /// them two at a time
+    pub(super) async fn assert_lists_in_creation_order(store: &dyn QuoteStore) {
+        let mut ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        ids.sort();
+        ids.reverse();
 
-        {
-            let mut quote: QuoteInfo;
-            let mut quote_table = write_txn.open_table(QUOTES_TABLE)?;
-            {
-                let quote_value = quote_table
-                    .get(quote_id.into_bytes().as_slice())?
-                    .ok_or(anyhow!("Unknown quote"))?;
+        // The last two share a second and fall back to id order
+        let created = [Some(100), Some(200), Some(300), Some(400), Some(400)];
+        for (id, created_at) in ids.iter().zip(created) {
+            store
+                .add_quote(&quote_created_at(*id, created_at, QuoteState::Unpaid))
+                .await
+                .unwrap();
+        }
+        // Stored before creation times were recorded
+        let legacy = quote_created_at(Uuid::new_v4(), None, QuoteState::Paid);
+        store.add_quotes(&[legacy.clone()]).await.unwrap();
+
+        let mut expected = vec![legacy.id, ids[0], ids[1], ids[2]];
+        let mut same_second = vec![ids[3], ids[4]];
+        same_second.sort();
+        expected.extend(same_second);
 
-                let quote_value = quote_value.value();
+        let mut listed = Vec::new();
+        let mut after = None;
+        loop {
+            let (quotes, next) = store
+                .list_quotes(after, 2, &QuoteFilter::default(), 1_000)
+                .await
+                .unwrap();
+            assert!(quotes.len() <= 2);
+            listed.extend(quotes.iter().map(|quote| quote.id));
 
-                quote = serde_json::from_str(quote_value)?;
+            match next {
+                Some(next) => after = Some(next),
+                None => break,
             }
+        }
+        assert_eq!(listed, expected);
 
-            current_quote = quote.clone();
+        let filter = QuoteFilter {
+            state: Some(QuoteState::Unpaid),
+            ..Default::default()
+        };
+        let (quotes, next) = store.list_quotes(None, 10, &filter, 1_000).await.unwrap();
+        assert_eq!(quotes.len(), 5);
+        assert_eq!(next, None);
 
-            quote.state = quote_state;
+        assert!(matches!(
+            store
+                .list_quotes(Some(Uuid::new_v4()), 10, &filter, 1_000)
+                .await,
+            Err(DbError::QuoteNotFound(_))
+        ));
+    }
 
-            quote_table.insert(
-                quote_id.into_bytes().as_slice(),
-                serde_json::to_string(&quote)?.as_str(),
-            )?;
+    /// Pay quotes concurrently on either side of local midnight, dating each receipt from its
+    /// payment time like the payment handler does, and check every day is numbered 1, 2, 3...
+    /// without gaps or duplicates
+    pub(super) async fn assert_gapless_receipts_across_midnight(store: Arc<dyn QuoteStore>) {
+        // Local midnight starting 2026-03-01 at UTC+2
+        const UTC_OFFSET_MINUTES: i32 = 120;
+        const MIDNIGHT: u64 = 1_772_316_000;
+        const PER_DAY: u64 = 20;
+
+        let mut quotes = Vec::new();
+        for i in 0..2 * PER_DAY {
+            let quote = quote_created_at(Uuid::new_v4(), Some(i), QuoteState::Pending);
+            store.add_quote(&quote).await.unwrap();
+            quotes.push((quote.id, MIDNIGHT - PER_DAY + i));
         }
 
-        write_txn.commit()?;
+        let payments = quotes.into_iter().map(|(id, paid_at)| {
+            let store = Arc::clone(&store);
+            tokio::spawn(async move {
+                let payment: ReceivedPayment = serde_json::from_value(serde_json::json!({
+                    "mint": "https://mint.example.com",
+                    "unit": "sat",
+                    "amount": 100,
+                    "proofs": [],
+                    "payment_fingerprint": id.to_string(),
+                    "received_at": paid_at,
+                }))
+                .unwrap();
+                let receipt_date = crate::types::receipt_date(paid_at, UTC_OFFSET_MINUTES);
+
+                store.record_payment(id, payment, receipt_date).await
+            })
+        });
+
+        let mut numbers: HashMap<String, Vec<u64>> = HashMap::new();
+        for paid in futures::future::join_all(payments).await {
+            let quote = paid.unwrap().unwrap();
+            assert_eq!(quote.state, QuoteState::Paid);
+
+            let receipt = quote.receipt.unwrap();
+            numbers
+                .entry(receipt.date)
+                .or_default()
+                .push(receipt.number);
+        }
+
+        assert_eq!(numbers.len(), 2);
+        for date in ["2026-02-28", "2026-03-01"] {
+            let day = numbers.get_mut(date).unwrap();
+            day.sort();
+            assert_eq!(*day, (1..=PER_DAY).collect::<Vec<_>>(), "{}", date);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn memory_db_numbers_receipts_without_gaps() {
+        assert_gapless_receipts_across_midnight(Arc::new(MemoryDb::new())).await;
+    }
+
+    #[tokio::test]
+    async fn memory_db_lists_quotes_in_creation_order() {
+        assert_lists_in_creation_order(&MemoryDb::new()).await;
+    }
 
-        Ok(current_quote)
+    #[tokio::test]
+    async fn lists_empty_store() {
+        let (quotes, next) = MemoryDb::new()
+            .list_quotes(None, 10, &QuoteFilter::default(), 0)
+            .await
+            .unwrap();
+        assert!(quotes.is_empty());
+        assert_eq!(next, None);
     }
 }
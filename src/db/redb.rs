@@ -0,0 +1,752 @@
+//! [`QuoteStore`] in a redb database file, the store of the `cashu-pos` binary
+
+use std::ops::Bound;
+use std::{path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use redb::{Database, ReadableTable, TableDefinition, WriteTransaction};
+use uuid::Uuid;
+
+use super::{DbError, QuoteStore, apply_payment, ensure_state, is_open, is_stale};
+use crate::types::{
+    QuoteFilter, QuoteInfo, QuoteState, ReceivedPayment, SentToken, Sweep, Withdrawal,
+};
+
+// <Y, QuoteInfo>
+const QUOTES_TABLE: TableDefinition<&[u8], &str> = TableDefinition::new("quotes");
+// <Receipt date, Last receipt number issued that day>
+const RECEIPT_COUNTERS_TABLE: TableDefinition<&str, u64> = TableDefinition::new("receipt_counters");
+// <Quote id, Payloads redeemed for the quote>
+const PAYMENTS_TABLE: TableDefinition<&[u8], &str> = TableDefinition::new("payments");
+// <External reference, Id of the latest quote created with it>
+const QUOTE_REFERENCES_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("quote_references");
+// <Withdrawal id, Withdrawal>
+const WITHDRAWALS_TABLE: TableDefinition<&[u8], &str> = TableDefinition::new("withdrawals");
+// <Sent token id, SentToken>
+const SENT_TOKENS_TABLE: TableDefinition<&[u8], &str> = TableDefinition::new("sent_tokens");
+// <Sweep id, Sweep>
+const SWEEPS_TABLE: TableDefinition<&[u8], &str> = TableDefinition::new("sweeps");
+// <(Created at, Quote id), ()>, the order quotes are listed in
+const QUOTES_BY_CREATED_TABLE: TableDefinition<(u64, &[u8]), ()> =
+    TableDefinition::new("quotes_by_created");
+// <Key, Value>
+const METADATA_TABLE: TableDefinition<&str, u64> = TableDefinition::new("metadata");
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Migration `i` upgrades a database from schema version `i` to `i + 1`
+///
+/// Databases written before the schema was versioned are version 0. Append new
+/// migrations here, never reorder or remove existing ones.
+const MIGRATIONS: &[fn(&WriteTransaction) -> Result<(), DbError>] =
+    &[backfill_paid_amount, index_created_at];
+
+/// Schema version written by this build
+pub const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64;
+
+backend_error_from!(
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError,
+);
+
+#[derive(Clone)]
+pub struct Db {
+    db: Arc<Database>,
+}
+
+impl Db {
+    pub fn new(path: PathBuf) -> Result<Self, DbError> {
+        let db = Database::create(path)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            // Open all tables to init a new db
+            let _ = write_txn.open_table(QUOTES_TABLE)?;
+            let _ = write_txn.open_table(RECEIPT_COUNTERS_TABLE)?;
+            let _ = write_txn.open_table(PAYMENTS_TABLE)?;
+            let _ = write_txn.open_table(QUOTE_REFERENCES_TABLE)?;
+            let _ = write_txn.open_table(WITHDRAWALS_TABLE)?;
+            let _ = write_txn.open_table(SENT_TOKENS_TABLE)?;
+            let _ = write_txn.open_table(SWEEPS_TABLE)?;
+            let _ = write_txn.open_table(METADATA_TABLE)?;
+            let _ = write_txn.open_table(QUOTES_BY_CREATED_TABLE)?;
+        }
+
+        write_txn.commit()?;
+
+        let db = Self { db: Arc::new(db) };
+        db.migrate()?;
+
+        Ok(db)
+    }
+
+    /// Bring the database up to [`SCHEMA_VERSION`]
+    ///
+    /// All pending migrations run in one transaction, so a failure leaves the
+    /// database at its previous version. Fails without changes if the database is
+    /// newer than this build supports.
+    pub fn migrate(&self) -> Result<(), DbError> {
+        let write_txn = self.db.begin_write()?;
+
+        {
+            let mut metadata_table = write_txn.open_table(METADATA_TABLE)?;
+
+            let version = metadata_table
+                .get(SCHEMA_VERSION_KEY)?
+                .map(|version| version.value())
+                .unwrap_or_default();
+
+            if version > SCHEMA_VERSION {
+                return Err(DbError::UnsupportedSchemaVersion {
+                    found: version,
+                    supported: SCHEMA_VERSION,
+                });
+            }
+
+            if version == SCHEMA_VERSION {
+                return Ok(());
+            }
+
+            for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+                tracing::info!(
+                    "Migrating database schema from version {} to {}",
+                    from,
+                    from + 1
+                );
+                migration(&write_txn)?;
+            }
+
+            metadata_table.insert(SCHEMA_VERSION_KEY, SCHEMA_VERSION)?;
+        }
+
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Apply `update` to a stored quote, returning the updated quote
+    ///
+    /// `update` runs inside the write transaction so it can touch other tables atomically.
+    fn update_quote<F>(&self, quote_id: Uuid, update: F) -> Result<QuoteInfo, DbError>
+    where
+        F: FnOnce(&mut QuoteInfo, &WriteTransaction) -> Result<(), DbError>,
+    {
+        let write_txn = self.db.begin_write()?;
+
+        let updated_quote;
+
+        {
+            let mut quote: QuoteInfo;
+            let mut quote_table = write_txn.open_table(QUOTES_TABLE)?;
+            {
+                let quote_value = quote_table
+                    .get(quote_id.into_bytes().as_slice())?
+                    .ok_or(DbError::QuoteNotFound(quote_id))?;
+
+                let quote_value = quote_value.value();
+
+                quote = serde_json::from_str(quote_value)?;
+            }
+
+            update(&mut quote, &write_txn)?;
+
+            quote_table.insert(
+                quote_id.into_bytes().as_slice(),
+                serde_json::to_string(&quote)?.as_str(),
+            )?;
+
+            updated_quote = quote;
+        }
+
+        write_txn.commit()?;
+
+        Ok(updated_quote)
+    }
+}
+
+#[async_trait]
+impl QuoteStore for Db {
+    /// Open a read transaction to check the database is usable
+    async fn check(&self) -> Result<(), DbError> {
+        let read_txn = self.db.begin_read()?;
+        let _ = read_txn.open_table(QUOTES_TABLE)?;
+
+        Ok(())
+    }
+
+    async fn add_quote(&self, quote_info: &QuoteInfo) -> Result<(), DbError> {
+        let write_txn = self.db.begin_write()?;
+
+        {
+            let mut quote_table = write_txn.open_table(QUOTES_TABLE)?;
+
+            let _ = quote_table.insert(
+                quote_info.id.into_bytes().as_slice(),
+                serde_json::to_string(quote_info)?.as_str(),
+            );
+
+            let mut created_table = write_txn.open_table(QUOTES_BY_CREATED_TABLE)?;
+            created_table.insert(created_key(quote_info), ())?;
+
+            if let Some(reference) = &quote_info.reference {
+                let mut reference_table = write_txn.open_table(QUOTE_REFERENCES_TABLE)?;
+                reference_table
+                    .insert(reference.as_str(), quote_info.id.into_bytes().as_slice())?;
+            }
+        }
+
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn add_quotes(&self, quotes: &[QuoteInfo]) -> Result<(), DbError> {
+        let write_txn = self.db.begin_write()?;
+
+        {
+            let mut quote_table = write_txn.open_table(QUOTES_TABLE)?;
+            let mut created_table = write_txn.open_table(QUOTES_BY_CREATED_TABLE)?;
+
+            for quote_info in quotes {
+                quote_table.insert(
+                    quote_info.id.into_bytes().as_slice(),
+                    serde_json::to_string(quote_info)?.as_str(),
+                )?;
+                created_table.insert(created_key(quote_info), ())?;
+            }
+        }
+
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn add_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), DbError> {
+        let write_txn = self.db.begin_write()?;
+
+        {
+            let mut withdrawal_table = write_txn.open_table(WITHDRAWALS_TABLE)?;
+
+            withdrawal_table.insert(
+                withdrawal.id.into_bytes().as_slice(),
+                serde_json::to_string(withdrawal)?.as_str(),
+            )?;
+        }
+
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn add_sent_token(&self, sent_token: &SentToken) -> Result<(), DbError> {
+        let write_txn = self.db.begin_write()?;
+
+        {
+            let mut sent_token_table = write_txn.open_table(SENT_TOKENS_TABLE)?;
+
+            sent_token_table.insert(
+                sent_token.id.into_bytes().as_slice(),
+                serde_json::to_string(sent_token)?.as_str(),
+            )?;
+        }
+
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn list_sent_tokens(&self) -> Result<Vec<SentToken>, DbError> {
+        let read_txn = self.db.begin_read()?;
+        let sent_token_table = read_txn.open_table(SENT_TOKENS_TABLE)?;
+
+        let mut sent_tokens = sent_token_table
+            .iter()?
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(serde_json::from_str::<SentToken>(value.value())?)
+            })
+            .collect::<Result<Vec<_>, DbError>>()?;
+
+        sent_tokens.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(sent_tokens)
+    }
+
+    async fn add_sweep(&self, sweep: &Sweep) -> Result<(), DbError> {
+        let write_txn = self.db.begin_write()?;
+
+        {
+            let mut sweep_table = write_txn.open_table(SWEEPS_TABLE)?;
+
+            sweep_table.insert(
+                sweep.id.into_bytes().as_slice(),
+                serde_json::to_string(sweep)?.as_str(),
+            )?;
+        }
+
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    async fn list_sweeps(&self) -> Result<Vec<Sweep>, DbError> {
+        let read_txn = self.db.begin_read()?;
+        let sweep_table = read_txn.open_table(SWEEPS_TABLE)?;
+
+        let mut sweeps = sweep_table
+            .iter()?
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(serde_json::from_str::<Sweep>(value.value())?)
+            })
+            .collect::<Result<Vec<_>, DbError>>()?;
+
+        sweeps.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(sweeps)
+    }
+
+    async fn get_quote(&self, quote_id: Uuid) -> Result<QuoteInfo, DbError> {
+        let read_txn = self.db.begin_read()?;
+
+        let quote_table = read_txn.open_table(QUOTES_TABLE)?;
+        let quote_value = quote_table
+            .get(quote_id.into_bytes().as_slice())?
+            .ok_or(DbError::QuoteNotFound(quote_id))?;
+
+        let quote_value = quote_value.value();
+        let quote: QuoteInfo = serde_json::from_str(quote_value)?;
+
+        Ok(quote)
+    }
+
+    async fn get_payments(&self, quote_id: Uuid) -> Result<Vec<ReceivedPayment>, DbError> {
+        let read_txn = self.db.begin_read()?;
+        let payment_table = read_txn.open_table(PAYMENTS_TABLE)?;
+
+        match payment_table.get(quote_id.into_bytes().as_slice())? {
+            Some(payments) => Ok(serde_json::from_str(payments.value())?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn get_quote_by_reference(&self, reference: &str) -> Result<Option<QuoteInfo>, DbError> {
+        let id = {
+            let read_txn = self.db.begin_read()?;
+            let reference_table = read_txn.open_table(QUOTE_REFERENCES_TABLE)?;
+
+            match reference_table.get(reference)? {
+                Some(id) => Uuid::from_slice(id.value())?,
+                None => return Ok(None),
+            }
+        };
+
+        self.get_quote(id).await.map(Some)
+    }
+
+    /// Quotes are walked in order through the created at index and filtered while iterating,
+    /// so a page may take a scan of every later quote.
+    async fn list_quotes(
+        &self,
+        after: Option<Uuid>,
+        limit: usize,
+        filter: &QuoteFilter,
+        now: u64,
+    ) -> Result<(Vec<QuoteInfo>, Option<Uuid>), DbError> {
+        let read_txn = self.db.begin_read()?;
+        let quote_table = read_txn.open_table(QUOTES_TABLE)?;
+        let created_table = read_txn.open_table(QUOTES_BY_CREATED_TABLE)?;
+
+        let after = match after {
+            Some(id) => {
+                let quote_value = quote_table
+                    .get(id.into_bytes().as_slice())?
+                    .ok_or(DbError::QuoteNotFound(id))?;
+                let quote = serde_json::from_str::<QuoteInfo>(quote_value.value())?;
+
+                Some((quote.created_at.unwrap_or_default(), id.into_bytes()))
+            }
+            None => None,
+        };
+        let start = match &after {
+            Some((created_at, id)) => Bound::Excluded((*created_at, id.as_slice())),
+            None => Bound::Unbounded,
+        };
+
+        let mut quotes = Vec::with_capacity(limit);
+
+        for entry in created_table.range::<(u64, &[u8])>((start, Bound::Unbounded))? {
+            let (key, _) = entry?;
+            let (_, id) = key.value();
+
+            let Some(quote_value) = quote_table.get(id)? else {
+                continue;
+            };
+            let quote = serde_json::from_str::<QuoteInfo>(quote_value.value())?;
+
+            if !filter.matches(&quote, now) {
+                continue;
+            }
+
+            quotes.push(quote);
+
+            // Read one past the page to know whether another page follows
+            if quotes.len() > limit {
+                break;
+            }
+        }
+
+        let next_cursor = match quotes.len() > limit {
+            true => {
+                quotes.truncate(limit);
+                quotes.last().map(|quote| quote.id)
+            }
+            false => None,
+        };
+
+        Ok((quotes, next_cursor))
+    }
+
+    async fn update_quote_state(
+        &self,
+        quote_id: Uuid,
+        quote_state: QuoteState,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, _| {
+            quote.state = quote_state;
+            Ok(())
+        })
+    }
+
+    /// The check and the update happen in one write transaction.
+    async fn transition_quote_state(
+        &self,
+        quote_id: Uuid,
+        expected: QuoteState,
+        new: QuoteState,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, _| {
+            ensure_state(quote, expected)?;
+            quote.state = new;
+            Ok(())
+        })
+    }
+
+    async fn count_open_quotes(&self, now: u64) -> Result<usize, DbError> {
+        let read_txn = self.db.begin_read()?;
+        let quote_table = read_txn.open_table(QUOTES_TABLE)?;
+
+        let mut open = 0;
+        for entry in quote_table.iter()? {
+            let (_, quote_value) = entry?;
+            let quote = serde_json::from_str::<QuoteInfo>(quote_value.value())?;
+
+            if is_open(&quote, now) {
+                open += 1;
+            }
+        }
+
+        Ok(open)
+    }
+
+    async fn expire_quotes(&self, now: u64, limit: usize) -> Result<usize, DbError> {
+        let write_txn = self.db.begin_write()?;
+
+        let expired = {
+            let mut quote_table = write_txn.open_table(QUOTES_TABLE)?;
+
+            let mut expired = Vec::new();
+            for entry in quote_table.iter()? {
+                if expired.len() >= limit {
+                    break;
+                }
+
+                let (_, quote_value) = entry?;
+                let quote = serde_json::from_str::<QuoteInfo>(quote_value.value())?;
+
+                if is_stale(&quote, now) {
+                    expired.push(quote);
+                }
+            }
+
+            for quote in &mut expired {
+                quote.state = QuoteState::Expired;
+                quote_table.insert(
+                    quote.id.into_bytes().as_slice(),
+                    serde_json::to_string(quote)?.as_str(),
+                )?;
+            }
+
+            expired.len()
+        };
+
+        write_txn.commit()?;
+
+        Ok(expired)
+    }
+
+    /// The receipt number is drawn in the same write transaction as the quote update.
+    async fn record_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+        receipt_date: String,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, write_txn| {
+            apply_payment(quote, &payment, receipt_date, |date| {
+                let mut counters = write_txn.open_table(RECEIPT_COUNTERS_TABLE)?;
+                let number = counters
+                    .get(date)?
+                    .map(|last| last.value())
+                    .unwrap_or_default()
+                    + 1;
+                counters.insert(date, number)?;
+
+                Ok(number)
+            })?;
+
+            append_payment(write_txn, quote_id, payment)
+        })
+    }
+
+    async fn hold_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, write_txn| {
+            ensure_state(quote, QuoteState::Pending)?;
+            quote.state = QuoteState::Unsettled;
+
+            append_payment(write_txn, quote_id, payment)
+        })
+    }
+}
+
+/// Add `payment` to the payments recorded for a quote
+fn append_payment(
+    write_txn: &WriteTransaction,
+    quote_id: Uuid,
+    payment: ReceivedPayment,
+) -> Result<(), DbError> {
+    let mut payment_table = write_txn.open_table(PAYMENTS_TABLE)?;
+    let mut payments = match payment_table.get(quote_id.into_bytes().as_slice())? {
+        Some(payments) => serde_json::from_str::<Vec<ReceivedPayment>>(payments.value())?,
+        None => Vec::new(),
+    };
+    payments.push(payment);
+
+    payment_table.insert(
+        quote_id.into_bytes().as_slice(),
+        serde_json::to_string(&payments)?.as_str(),
+    )?;
+
+    Ok(())
+}
+
+/// Schema 1 to 2: index quotes by creation time for listing them in that order
+fn index_created_at(write_txn: &WriteTransaction) -> Result<(), DbError> {
+    let quote_table = write_txn.open_table(QUOTES_TABLE)?;
+    let mut created_table = write_txn.open_table(QUOTES_BY_CREATED_TABLE)?;
+
+    let mut indexed = 0;
+    for entry in quote_table.iter()? {
+        let (_, value) = entry?;
+        let quote: QuoteInfo = serde_json::from_str(value.value())?;
+
+        created_table.insert(created_key(&quote), ())?;
+        indexed += 1;
+    }
+
+    tracing::info!("Indexed creation time of {} quotes", indexed);
+
+    Ok(())
+}
+
+/// Key of a quote in [`QUOTES_BY_CREATED_TABLE`], quotes without a creation time sort first
+fn created_key(quote: &QuoteInfo) -> (u64, &[u8]) {
+    (
+        quote.created_at.unwrap_or_default(),
+        quote.id.as_bytes().as_slice(),
+    )
+}
+
+/// Schema 0 to 1: set `paid_amount` on quotes paid before it was recorded
+///
+/// Such quotes report their whole amount as remaining, a paid quote received
+/// at least its amount so that is what gets filled in.
+fn backfill_paid_amount(write_txn: &WriteTransaction) -> Result<(), DbError> {
+    let mut quote_table = write_txn.open_table(QUOTES_TABLE)?;
+
+    let mut backfilled = Vec::new();
+    for entry in quote_table.iter()? {
+        let (_, value) = entry?;
+        let mut quote: QuoteInfo = serde_json::from_str(value.value())?;
+
+        if quote.state == QuoteState::Paid && quote.paid_amount.is_none() {
+            quote.paid_amount = Some(quote.amount.value);
+            backfilled.push(quote);
+        }
+    }
+
+    for quote in &backfilled {
+        quote_table.insert(
+            quote.id.into_bytes().as_slice(),
+            serde_json::to_string(quote)?.as_str(),
+        )?;
+    }
+
+    tracing::info!("Backfilled paid amount of {} quotes", backfilled.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryDb;
+    use crate::db::tests::{
+        assert_gapless_receipts_across_midnight, assert_lists_in_creation_order,
+    };
+
+    const PAID_QUOTE_ID: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    const UNPAID_QUOTE_ID: &str = "9a1f3c2e-5b7d-4e8a-b6c4-2d0f1e3a5b7c";
+
+    /// Database as written before the schema was versioned, without a metadata table
+    /// and with a paid quote lacking `paid_amount`
+    fn write_v0_fixture(path: &std::path::Path) {
+        let db = Database::create(path).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut quote_table = write_txn.open_table(QUOTES_TABLE).unwrap();
+
+            for (id, state) in [(PAID_QUOTE_ID, "Paid"), (UNPAID_QUOTE_ID, "Unpaid")] {
+                let id = Uuid::parse_str(id).unwrap();
+                let value = format!(
+                    r#"{{"id":"{}","amount":100,"unit":"sat","state":"{}"}}"#,
+                    id, state
+                );
+                quote_table
+                    .insert(id.into_bytes().as_slice(), value.as_str())
+                    .unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+    }
+
+    fn schema_version(db: &Db) -> Option<u64> {
+        let read_txn = db.db.begin_read().unwrap();
+        let metadata_table = read_txn.open_table(METADATA_TABLE).unwrap();
+
+        metadata_table
+            .get(SCHEMA_VERSION_KEY)
+            .unwrap()
+            .map(|version| version.value())
+    }
+
+    #[tokio::test]
+    async fn migrates_v0_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cashu-pos.redb");
+        write_v0_fixture(&path);
+
+        let db = Db::new(path).unwrap();
+
+        assert_eq!(schema_version(&db), Some(SCHEMA_VERSION));
+
+        let paid = db
+            .get_quote(Uuid::parse_str(PAID_QUOTE_ID).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(paid.paid_amount, Some(100));
+
+        let unpaid = db
+            .get_quote(Uuid::parse_str(UNPAID_QUOTE_ID).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(unpaid.paid_amount, None);
+
+        // Quotes written before the created at index are listed through it
+        let (quotes, _) = db
+            .list_quotes(None, 10, &QuoteFilter::default(), 0)
+            .await
+            .unwrap();
+        assert_eq!(quotes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn migrate_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cashu-pos.redb");
+        write_v0_fixture(&path);
+
+        let db = Db::new(path.clone()).unwrap();
+        db.migrate().unwrap();
+        drop(db);
+
+        let db = Db::new(path).unwrap();
+        assert_eq!(schema_version(&db), Some(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cashu-pos.redb");
+
+        {
+            let db = Database::create(&path).unwrap();
+            let write_txn = db.begin_write().unwrap();
+            {
+                let mut metadata_table = write_txn.open_table(METADATA_TABLE).unwrap();
+                metadata_table
+                    .insert(SCHEMA_VERSION_KEY, SCHEMA_VERSION + 1)
+                    .unwrap();
+            }
+            write_txn.commit().unwrap();
+        }
+
+        match Db::new(path) {
+            Err(DbError::UnsupportedSchemaVersion { found, supported }) => {
+                assert_eq!(found, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            Err(err) => panic!("expected a schema version error, got {}", err),
+            Ok(_) => panic!("expected a schema version error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn db_lists_quotes_in_creation_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::new(dir.path().join("cashu-pos.redb")).unwrap();
+
+        assert_lists_in_creation_order(&db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn db_numbers_receipts_without_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::new(dir.path().join("cashu-pos.redb")).unwrap();
+
+        assert_gapless_receipts_across_midnight(Arc::new(db)).await;
+    }
+
+    #[tokio::test]
+    async fn lists_empty_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::new(dir.path().join("cashu-pos.redb")).unwrap();
+
+        for store in [&db as &dyn QuoteStore, &MemoryDb::new()] {
+            let (quotes, next) = store
+                .list_quotes(None, 10, &QuoteFilter::default(), 0)
+                .await
+                .unwrap();
+            assert!(quotes.is_empty());
+            assert_eq!(next, None);
+        }
+    }
+}
@@ -0,0 +1,669 @@
+//! [`QuoteStore`] in a SQLite database file, for embedders already running on SQLite
+//!
+//! Records are kept as JSON like in the redb store, with the quote's creation time
+//! and id as columns so quotes can be listed in order through an index.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior, params};
+use uuid::Uuid;
+
+use super::{DbError, QuoteStore, apply_payment, ensure_state, is_open, is_stale};
+use crate::types::{
+    QuoteFilter, QuoteInfo, QuoteState, ReceivedPayment, SentToken, Sweep, Withdrawal,
+};
+
+/// Migration `i` upgrades a database from schema version `i` to `i + 1`, tracked in
+/// `PRAGMA user_version`
+///
+/// Append new migrations here, never reorder or remove existing ones.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE quotes (
+        id BLOB PRIMARY KEY,
+        created_at INTEGER NOT NULL,
+        quote TEXT NOT NULL
+    );
+    CREATE INDEX quotes_by_created ON quotes (created_at, id);
+    CREATE TABLE quote_references (
+        reference TEXT PRIMARY KEY,
+        quote_id BLOB NOT NULL
+    );
+    CREATE TABLE payments (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        quote_id BLOB NOT NULL,
+        payment TEXT NOT NULL
+    );
+    CREATE INDEX payments_by_quote ON payments (quote_id, seq);
+    CREATE TABLE receipt_counters (
+        date TEXT PRIMARY KEY,
+        number INTEGER NOT NULL
+    );
+    CREATE TABLE withdrawals (id BLOB PRIMARY KEY, withdrawal TEXT NOT NULL);
+    CREATE TABLE sent_tokens (id BLOB PRIMARY KEY, sent_token TEXT NOT NULL);
+    CREATE TABLE sweeps (id BLOB PRIMARY KEY, sweep TEXT NOT NULL);
+"#];
+
+/// Schema version written by this build
+const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// How long a write waits for another process holding the database lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+backend_error_from!(rusqlite::Error);
+
+#[derive(Clone)]
+pub struct SqliteDb {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteDb {
+    pub fn new(path: PathBuf) -> Result<Self, DbError> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        let db = Self {
+            conn: Arc::new(Mutex::new(conn)),
+        };
+        db.migrate()?;
+
+        Ok(db)
+    }
+
+    /// Bring the database up to the schema version of this build
+    ///
+    /// All pending migrations run in one transaction, so a failure leaves the
+    /// database at its previous version. Fails without changes if the database is
+    /// newer than this build supports.
+    pub fn migrate(&self) -> Result<(), DbError> {
+        let mut conn = self.lock();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let version = tx.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? as u64;
+
+        if version > SCHEMA_VERSION {
+            return Err(DbError::UnsupportedSchemaVersion {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+
+        if version == SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            tracing::info!(
+                "Migrating database schema from version {} to {}",
+                from,
+                from + 1
+            );
+            tx.execute_batch(migration)?;
+        }
+
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION as i64)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// The connection is shared, so every operation runs with it to itself
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply `update` to a stored quote, returning the updated quote
+    ///
+    /// `update` runs inside the write transaction so it can touch other tables atomically.
+    fn update_quote<F>(&self, quote_id: Uuid, update: F) -> Result<QuoteInfo, DbError>
+    where
+        F: FnOnce(&mut QuoteInfo, &Transaction) -> Result<(), DbError>,
+    {
+        let mut conn = self.lock();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let mut quote = read_quote(&tx, quote_id)?.ok_or(DbError::QuoteNotFound(quote_id))?;
+
+        // A failed update drops the transaction, rolling back anything it wrote
+        update(&mut quote, &tx)?;
+        write_quote(&tx, &quote)?;
+
+        tx.commit()?;
+
+        Ok(quote)
+    }
+
+    /// Every record of `table`, deserialized from its `column`
+    fn read_all<T>(&self, table: &str, column: &str) -> Result<Vec<T>, DbError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM {}", column, table))?;
+        let mut rows = stmt.query([])?;
+
+        let mut records = Vec::new();
+        while let Some(row) = rows.next()? {
+            records.push(serde_json::from_str(&row.get::<_, String>(0)?)?);
+        }
+
+        Ok(records)
+    }
+
+    fn insert_record<T>(
+        &self,
+        table: &str,
+        column: &str,
+        id: Uuid,
+        record: &T,
+    ) -> Result<(), DbError>
+    where
+        T: serde::Serialize,
+    {
+        self.lock().execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (id, {}) VALUES (?1, ?2)",
+                table, column
+            ),
+            params![id.as_bytes().as_slice(), serde_json::to_string(record)?],
+        )?;
+
+        Ok(())
+    }
+}
+
+fn read_quote(conn: &Connection, quote_id: Uuid) -> Result<Option<QuoteInfo>, DbError> {
+    let quote = conn
+        .query_row(
+            "SELECT quote FROM quotes WHERE id = ?1",
+            params![quote_id.as_bytes().as_slice()],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+
+    match quote {
+        Some(quote) => Ok(Some(serde_json::from_str(&quote)?)),
+        None => Ok(None),
+    }
+}
+
+/// Store `quote`, quotes without a creation time are indexed as created at 0 so they list first
+fn write_quote(conn: &Connection, quote: &QuoteInfo) -> Result<(), DbError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO quotes (id, created_at, quote) VALUES (?1, ?2, ?3)",
+        params![
+            quote.id.as_bytes().as_slice(),
+            quote.created_at.unwrap_or_default() as i64,
+            serde_json::to_string(quote)?
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Add `payment` to the payments recorded for a quote
+fn append_payment(
+    tx: &Transaction,
+    quote_id: Uuid,
+    payment: &ReceivedPayment,
+) -> Result<(), DbError> {
+    tx.execute(
+        "INSERT INTO payments (quote_id, payment) VALUES (?1, ?2)",
+        params![
+            quote_id.as_bytes().as_slice(),
+            serde_json::to_string(payment)?
+        ],
+    )?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl QuoteStore for SqliteDb {
+    async fn check(&self) -> Result<(), DbError> {
+        self.lock()
+            .query_row("SELECT 1 FROM quotes LIMIT 1", [], |_| Ok(()))
+            .optional()?;
+
+        Ok(())
+    }
+
+    async fn add_quote(&self, quote_info: &QuoteInfo) -> Result<(), DbError> {
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+
+        write_quote(&tx, quote_info)?;
+
+        if let Some(reference) = &quote_info.reference {
+            tx.execute(
+                "INSERT OR REPLACE INTO quote_references (reference, quote_id) VALUES (?1, ?2)",
+                params![reference, quote_info.id.as_bytes().as_slice()],
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    async fn add_quotes(&self, quotes: &[QuoteInfo]) -> Result<(), DbError> {
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+
+        for quote_info in quotes {
+            write_quote(&tx, quote_info)?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    async fn get_quote(&self, quote_id: Uuid) -> Result<QuoteInfo, DbError> {
+        read_quote(&self.lock(), quote_id)?.ok_or(DbError::QuoteNotFound(quote_id))
+    }
+
+    async fn get_quote_by_reference(&self, reference: &str) -> Result<Option<QuoteInfo>, DbError> {
+        let quote = self
+            .lock()
+            .query_row(
+                "SELECT quotes.quote FROM quote_references
+                 JOIN quotes ON quotes.id = quote_references.quote_id
+                 WHERE quote_references.reference = ?1",
+                params![reference],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+
+        match quote {
+            Some(quote) => Ok(Some(serde_json::from_str(&quote)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Quotes are walked in order through the created at index and filtered while iterating,
+    /// so a page may take a scan of every later quote.
+    async fn list_quotes(
+        &self,
+        after: Option<Uuid>,
+        limit: usize,
+        filter: &QuoteFilter,
+        now: u64,
+    ) -> Result<(Vec<QuoteInfo>, Option<Uuid>), DbError> {
+        let conn = self.lock();
+
+        let after = match after {
+            Some(id) => {
+                let quote = read_quote(&conn, id)?.ok_or(DbError::QuoteNotFound(id))?;
+                Some((quote.created_at.unwrap_or_default() as i64, id))
+            }
+            None => None,
+        };
+
+        let mut stmt;
+        let mut rows = match &after {
+            Some((created_at, id)) => {
+                stmt = conn.prepare(
+                    "SELECT quote FROM quotes WHERE (created_at, id) > (?1, ?2)
+                     ORDER BY created_at, id",
+                )?;
+                stmt.query(params![created_at, id.as_bytes().as_slice()])?
+            }
+            None => {
+                stmt = conn.prepare("SELECT quote FROM quotes ORDER BY created_at, id")?;
+                stmt.query([])?
+            }
+        };
+
+        let mut quotes = Vec::with_capacity(limit);
+
+        while let Some(row) = rows.next()? {
+            let quote: QuoteInfo = serde_json::from_str(&row.get::<_, String>(0)?)?;
+
+            if !filter.matches(&quote, now) {
+                continue;
+            }
+
+            quotes.push(quote);
+
+            // Read one past the page to know whether another page follows
+            if quotes.len() > limit {
+                break;
+            }
+        }
+
+        let next_cursor = match quotes.len() > limit {
+            true => {
+                quotes.truncate(limit);
+                quotes.last().map(|quote| quote.id)
+            }
+            false => None,
+        };
+
+        Ok((quotes, next_cursor))
+    }
+
+    async fn update_quote_state(
+        &self,
+        quote_id: Uuid,
+        quote_state: QuoteState,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, _| {
+            quote.state = quote_state;
+            Ok(())
+        })
+    }
+
+    /// The check and the update happen in one write transaction.
+    async fn transition_quote_state(
+        &self,
+        quote_id: Uuid,
+        expected: QuoteState,
+        new: QuoteState,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, _| {
+            ensure_state(quote, expected)?;
+            quote.state = new;
+            Ok(())
+        })
+    }
+
+    async fn count_open_quotes(&self, now: u64) -> Result<usize, DbError> {
+        let quotes: Vec<QuoteInfo> = self.read_all("quotes", "quote")?;
+
+        Ok(quotes.iter().filter(|quote| is_open(quote, now)).count())
+    }
+
+    async fn expire_quotes(&self, now: u64, limit: usize) -> Result<usize, DbError> {
+        let mut conn = self.lock();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let mut expired = Vec::new();
+        {
+            let mut stmt = tx.prepare("SELECT quote FROM quotes")?;
+            let mut rows = stmt.query([])?;
+
+            while let Some(row) = rows.next()? {
+                if expired.len() >= limit {
+                    break;
+                }
+
+                let quote: QuoteInfo = serde_json::from_str(&row.get::<_, String>(0)?)?;
+
+                if is_stale(&quote, now) {
+                    expired.push(quote);
+                }
+            }
+        }
+
+        for quote in &mut expired {
+            quote.state = QuoteState::Expired;
+            write_quote(&tx, quote)?;
+        }
+
+        tx.commit()?;
+
+        Ok(expired.len())
+    }
+
+    /// The receipt number is drawn in the same write transaction as the quote update.
+    async fn record_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+        receipt_date: String,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, tx| {
+            apply_payment(quote, &payment, receipt_date, |date| {
+                let number = tx.query_row(
+                    "INSERT INTO receipt_counters (date, number) VALUES (?1, 1)
+                     ON CONFLICT (date) DO UPDATE SET number = number + 1
+                     RETURNING number",
+                    params![date],
+                    |row| row.get::<_, i64>(0),
+                )?;
+
+                Ok(number as u64)
+            })?;
+
+            append_payment(tx, quote_id, &payment)
+        })
+    }
+
+    async fn hold_payment(
+        &self,
+        quote_id: Uuid,
+        payment: ReceivedPayment,
+    ) -> Result<QuoteInfo, DbError> {
+        self.update_quote(quote_id, |quote, tx| {
+            ensure_state(quote, QuoteState::Pending)?;
+            quote.state = QuoteState::Unsettled;
+
+            append_payment(tx, quote_id, &payment)
+        })
+    }
+
+    async fn get_payments(&self, quote_id: Uuid) -> Result<Vec<ReceivedPayment>, DbError> {
+        let conn = self.lock();
+        let mut stmt =
+            conn.prepare("SELECT payment FROM payments WHERE quote_id = ?1 ORDER BY seq")?;
+        let mut rows = stmt.query(params![quote_id.as_bytes().as_slice()])?;
+
+        let mut payments = Vec::new();
+        while let Some(row) = rows.next()? {
+            payments.push(serde_json::from_str(&row.get::<_, String>(0)?)?);
+        }
+
+        Ok(payments)
+    }
+
+    async fn add_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), DbError> {
+        self.insert_record("withdrawals", "withdrawal", withdrawal.id, withdrawal)
+    }
+
+    async fn add_sent_token(&self, sent_token: &SentToken) -> Result<(), DbError> {
+        self.insert_record("sent_tokens", "sent_token", sent_token.id, sent_token)
+    }
+
+    async fn list_sent_tokens(&self) -> Result<Vec<SentToken>, DbError> {
+        let mut sent_tokens: Vec<SentToken> = self.read_all("sent_tokens", "sent_token")?;
+        sent_tokens.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(sent_tokens)
+    }
+
+    async fn add_sweep(&self, sweep: &Sweep) -> Result<(), DbError> {
+        self.insert_record("sweeps", "sweep", sweep.id, sweep)
+    }
+
+    async fn list_sweeps(&self) -> Result<Vec<Sweep>, DbError> {
+        let mut sweeps: Vec<Sweep> = self.read_all("sweeps", "sweep")?;
+        sweeps.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(sweeps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::{
+        assert_gapless_receipts_across_midnight, assert_lists_in_creation_order, quote_created_at,
+    };
+
+    fn open(dir: &tempfile::TempDir) -> SqliteDb {
+        SqliteDb::new(dir.path().join("cashu-pos.sqlite")).unwrap()
+    }
+
+    fn payment(amount: u64) -> ReceivedPayment {
+        serde_json::from_value(serde_json::json!({
+            "mint": "https://mint.example.com",
+            "unit": "sat",
+            "amount": amount,
+            "proofs": [],
+            "payment_fingerprint": format!("fingerprint-{}", amount),
+            "received_at": 1_000,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn lists_quotes_in_creation_order() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_lists_in_creation_order(&open(&dir)).await;
+    }
+
+    #[test]
+    fn migrate_is_idempotent_and_rejects_newer_schemas() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cashu-pos.sqlite");
+
+        let db = SqliteDb::new(path.clone()).unwrap();
+        db.migrate().unwrap();
+        drop(db);
+        SqliteDb::new(path.clone()).unwrap();
+
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION as i64 + 1)
+            .unwrap();
+
+        match SqliteDb::new(path) {
+            Err(DbError::UnsupportedSchemaVersion { found, supported }) => {
+                assert_eq!(found, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            Err(err) => panic!("expected a schema version error, got {}", err),
+            Ok(_) => panic!("expected a schema version error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn records_partial_payments_and_numbers_receipts() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+
+        let first = quote_created_at(Uuid::new_v4(), Some(100), QuoteState::Pending);
+        let second = quote_created_at(Uuid::new_v4(), Some(200), QuoteState::Pending);
+        db.add_quotes(&[first.clone(), second.clone()])
+            .await
+            .unwrap();
+
+        let quote = db
+            .record_payment(first.id, payment(40), "2026-10-15".to_string())
+            .await
+            .unwrap();
+        assert_eq!(quote.state, QuoteState::PartiallyPaid);
+        assert_eq!(quote.receipt, None);
+
+        // Only a pending quote takes a payment
+        assert!(matches!(
+            db.record_payment(first.id, payment(60), "2026-10-15".to_string())
+                .await,
+            Err(DbError::StateConflict {
+                actual: QuoteState::PartiallyPaid
+            })
+        ));
+
+        db.update_quote_state(first.id, QuoteState::Pending)
+            .await
+            .unwrap();
+        let quote = db
+            .record_payment(first.id, payment(60), "2026-10-15".to_string())
+            .await
+            .unwrap();
+        assert_eq!(quote.state, QuoteState::Paid);
+        assert_eq!(quote.paid_amount, Some(100));
+        assert_eq!(
+            quote.receipt.as_ref().map(|receipt| receipt.number),
+            Some(1)
+        );
+
+        let quote = db
+            .record_payment(second.id, payment(100), "2026-10-15".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            quote.receipt.as_ref().map(|receipt| receipt.number),
+            Some(2)
+        );
+
+        let amounts: Vec<u64> = db
+            .get_payments(first.id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|payment| payment.amount)
+            .collect();
+        assert_eq!(amounts, [40, 60]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn numbers_receipts_without_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_gapless_receipts_across_midnight(Arc::new(open(&dir))).await;
+    }
+
+    #[tokio::test]
+    async fn transitions_and_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+
+        let quote = quote_created_at(Uuid::new_v4(), Some(100), QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        db.transition_quote_state(quote.id, QuoteState::Unpaid, QuoteState::Pending)
+            .await
+            .unwrap();
+        assert!(matches!(
+            db.transition_quote_state(quote.id, QuoteState::Unpaid, QuoteState::Pending)
+                .await,
+            Err(DbError::StateConflict {
+                actual: QuoteState::Pending
+            })
+        ));
+        assert!(matches!(
+            db.get_quote(Uuid::new_v4()).await,
+            Err(DbError::QuoteNotFound(_))
+        ));
+
+        let mut expiring = quote_created_at(Uuid::new_v4(), Some(100), QuoteState::Unpaid);
+        expiring.expires_at = Some(150);
+        db.add_quote(&expiring).await.unwrap();
+
+        assert_eq!(db.count_open_quotes(100).await.unwrap(), 2);
+        assert_eq!(db.expire_quotes(200, 10).await.unwrap(), 1);
+        assert_eq!(
+            db.get_quote(expiring.id).await.unwrap().state,
+            QuoteState::Expired
+        );
+        assert_eq!(db.count_open_quotes(200).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn finds_the_latest_quote_of_a_reference() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+
+        for _ in 0..2 {
+            let mut quote = quote_created_at(Uuid::new_v4(), Some(100), QuoteState::Unpaid);
+            quote.reference = Some("order-1".to_string());
+            db.add_quote(&quote).await.unwrap();
+
+            let found = db.get_quote_by_reference("order-1").await.unwrap();
+            assert_eq!(found.map(|found| found.id), Some(quote.id));
+        }
+
+        assert!(
+            db.get_quote_by_reference("order-2")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        db.check().await.unwrap();
+    }
+}
@@ -1,47 +1,115 @@
 use std::fmt;
 
+use axum::Json;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use cdk::mint_url::MintUrl;
 use cdk::nuts::CurrencyUnit;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::types::QuoteState;
+use crate::types::{PosAmount, QuoteState};
 
 #[derive(Debug)]
 pub enum PosError {
+    Unauthorized,
     InvalidUuid(String),
+    InvalidAmount(String),
+    InvalidQueryParameter(String),
+    /// A required request parameter or field was not given
+    MissingParameter(String),
     QuoteNotFound(Uuid),
+    ReferenceNotFound(String),
     InvalidChannelSize {
         size: u64,
         min: u64,
         max: u64,
     },
+    InvalidQuoteCount {
+        count: u64,
+        max: u64,
+    },
     UnsupportedMint(MintUrl),
+    WalletNotFound {
+        mint: MintUrl,
+        unit: CurrencyUnit,
+    },
+    /// A payment came from an accepted mint in a unit the POS holds no wallet for
+    MintUnitNotSupported {
+        mint: MintUrl,
+        unit: CurrencyUnit,
+    },
     UnsupportedCurrencyUnit {
         given: String,
         allowed: Vec<CurrencyUnit>,
+        suggestion: Option<CurrencyUnit>,
+        hint: Option<String>,
     },
     InvalidQuoteState {
         id: Uuid,
         state: QuoteState,
     },
+    QuoteExpired(Uuid),
     InsufficientPayment {
-        expected: u64,
-        received: u64,
+        expected: PosAmount,
+        received: PosAmount,
+    },
+    Overpayment {
+        expected: PosAmount,
+        received: PosAmount,
+    },
+    UnitMismatch {
+        expected: CurrencyUnit,
+        received: CurrencyUnit,
     },
+    InsufficientBalance {
+        available: PosAmount,
+        required: PosAmount,
+    },
+    InvalidHtlcPreimage,
+    InvalidWebhookUrl(String),
+    MemoTooLong {
+        length: usize,
+        max: usize,
+    },
+    MissingHtlcPreimage(Uuid),
+    P2pkRequired(Uuid),
+    ProofsAlreadyReceived(Uuid),
+    ProofsAlreadySpent(Uuid),
     DatabaseError(String),
     ChannelOpenError(String),
     WalletError(String),
+    MeltError(String),
     ProofVerificationError(String),
+    MintUnreachable(String),
+    ClientDisconnected(Uuid),
+    RateLimited {
+        /// Seconds until the client may retry
+        retry_after: u64,
+    },
+    TooManyOpenQuotes {
+        open: usize,
+        max: usize,
+    },
+    ExchangeRateUnavailable(String),
     InternalError(String),
 }
 
 impl fmt::Display for PosError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Unauthorized => write!(f, "Missing or invalid API key"),
             Self::InvalidUuid(id) => write!(f, "Invalid UUID format: {}", id),
+            Self::InvalidAmount(msg) => write!(f, "Invalid amount: {}", msg),
+            Self::InvalidQueryParameter(msg) => write!(f, "Invalid query parameter: {}", msg),
+            Self::MissingParameter(name) => write!(f, "Missing parameter: {}", name),
             Self::QuoteNotFound(id) => write!(f, "Quote not found: {}", id),
+            Self::ReferenceNotFound(reference) => {
+                write!(f, "No quote with reference: {}", reference)
+            }
             Self::InvalidChannelSize { size, min, max } => {
                 write!(
                     f,
@@ -49,20 +117,48 @@ impl fmt::Display for PosError {
                     size, min, max
                 )
             }
+            Self::InvalidQuoteCount { count, max } => {
+                write!(f, "Quote count {} outside allowed range (1-{})", count, max)
+            }
             Self::UnsupportedMint(mint) => write!(f, "Unsupported mint: {}", mint),
-            Self::UnsupportedCurrencyUnit { given, allowed } => write!(
-                f,
-                "Unsupported currency unit: {}. Allowed units are: {}",
+            Self::WalletNotFound { mint, unit } => {
+                write!(f, "No wallet for mint {} with unit {}", mint, unit)
+            }
+            Self::MintUnitNotSupported { mint, unit } => {
+                write!(
+                    f,
+                    "Payments from mint {} in {} are not supported",
+                    mint, unit
+                )
+            }
+            Self::UnsupportedCurrencyUnit {
                 given,
-                allowed
-                    .iter()
-                    .map(|u| u.to_string())
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            ),
+                allowed,
+                suggestion,
+                hint,
+            } => {
+                write!(
+                    f,
+                    "Unsupported currency unit: {}. Allowed units are: {}",
+                    given,
+                    allowed
+                        .iter()
+                        .map(|u| u.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ". Did you mean {}?", suggestion)?;
+                }
+                if let Some(hint) = hint {
+                    write!(f, " {}", hint)?;
+                }
+                Ok(())
+            }
             Self::InvalidQuoteState { id, state } => {
                 write!(f, "Quote {} has invalid state: {:?}", id, state)
             }
+            Self::QuoteExpired(id) => write!(f, "Quote {} has expired", id),
             Self::InsufficientPayment { expected, received } => {
                 write!(
                     f,
@@ -70,35 +166,268 @@ impl fmt::Display for PosError {
                     expected, received
                 )
             }
+            Self::InsufficientBalance {
+                available,
+                required,
+            } => write!(
+                f,
+                "Insufficient balance: {} available, {} required",
+                available, required
+            ),
+            Self::Overpayment { expected, received } => write!(
+                f,
+                "Overpayment: expected {}, received {}",
+                expected, received
+            ),
+            Self::UnitMismatch { expected, received } => write!(
+                f,
+                "Payment unit mismatch: expected {}, received proofs in {}",
+                expected, received
+            ),
+            Self::InvalidHtlcPreimage => write!(f, "HTLC preimage must be 32 bytes of hex"),
+            Self::InvalidWebhookUrl(url) => {
+                write!(f, "Webhook URL must be an http:// or https:// URL: {}", url)
+            }
+            Self::MemoTooLong { length, max } => {
+                write!(
+                    f,
+                    "Memo is {} characters long, at most {} allowed",
+                    length, max
+                )
+            }
+            Self::MissingHtlcPreimage(id) => write!(
+                f,
+                "Payment for quote {} contains HTLC-locked proofs but no preimage was set",
+                id
+            ),
+            Self::P2pkRequired(id) => write!(
+                f,
+                "Payment for quote {} must only contain proofs locked to the POS key",
+                id
+            ),
+            Self::ProofsAlreadyReceived(id) => write!(
+                f,
+                "Payment for quote {} contains proofs already received in an earlier payment",
+                id
+            ),
+            Self::ProofsAlreadySpent(id) => write!(
+                f,
+                "Payment for quote {} contains proofs the mint reports as already spent",
+                id
+            ),
             Self::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             Self::ChannelOpenError(msg) => write!(f, "Failed to open channel: {}", msg),
             Self::WalletError(msg) => write!(f, "Wallet error: {}", msg),
+            Self::MeltError(msg) => write!(f, "Melt failed: {}", msg),
             Self::ProofVerificationError(msg) => write!(f, "Proof verification error: {}", msg),
+            Self::ClientDisconnected(id) => {
+                write!(f, "Client disconnected before payment of {} started", id)
+            }
+            Self::RateLimited { retry_after } => {
+                write!(f, "Too many requests, retry in {} seconds", retry_after)
+            }
+            Self::TooManyOpenQuotes { open, max } => write!(
+                f,
+                "{} quotes are open for payment, at most {} allowed",
+                open, max
+            ),
+            Self::ExchangeRateUnavailable(msg) => {
+                write!(f, "Exchange rate unavailable: {}", msg)
+            }
             Self::InternalError(msg) => write!(f, "Internal server error: {}", msg),
         }
     }
 }
 
-impl IntoResponse for PosError {
-    fn into_response(self) -> Response {
-        let status = match &self {
+impl PosError {
+    /// Stable machine-readable identifier of the error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unauthorized => "UNAUTHORIZED",
+            Self::InvalidUuid(_) => "INVALID_UUID",
+            Self::InvalidAmount(_) => "INVALID_AMOUNT",
+            Self::InvalidQueryParameter(_) => "INVALID_QUERY_PARAMETER",
+            Self::MissingParameter(_) => "MISSING_PARAMETER",
+            Self::QuoteNotFound(_) => "QUOTE_NOT_FOUND",
+            Self::ReferenceNotFound(_) => "REFERENCE_NOT_FOUND",
+            Self::InvalidChannelSize { .. } => "INVALID_CHANNEL_SIZE",
+            Self::InvalidQuoteCount { .. } => "INVALID_QUOTE_COUNT",
+            Self::UnsupportedMint(_) => "UNSUPPORTED_MINT",
+            Self::WalletNotFound { .. } => "WALLET_NOT_FOUND",
+            Self::MintUnitNotSupported { .. } => "MINT_UNIT_NOT_SUPPORTED",
+            Self::UnsupportedCurrencyUnit { .. } => "UNSUPPORTED_CURRENCY_UNIT",
+            Self::InvalidQuoteState { .. } => "INVALID_QUOTE_STATE",
+            Self::QuoteExpired(_) => "QUOTE_EXPIRED",
+            Self::InsufficientPayment { .. } => "INSUFFICIENT_PAYMENT",
+            Self::Overpayment { .. } => "OVERPAYMENT",
+            Self::UnitMismatch { .. } => "UNIT_MISMATCH",
+            Self::InsufficientBalance { .. } => "INSUFFICIENT_BALANCE",
+            Self::InvalidHtlcPreimage => "INVALID_HTLC_PREIMAGE",
+            Self::InvalidWebhookUrl(_) => "INVALID_WEBHOOK_URL",
+            Self::MemoTooLong { .. } => "MEMO_TOO_LONG",
+            Self::MissingHtlcPreimage(_) => "MISSING_HTLC_PREIMAGE",
+            Self::P2pkRequired(_) => "P2PK_REQUIRED",
+            Self::ProofsAlreadyReceived(_) => "PROOFS_ALREADY_RECEIVED",
+            Self::ProofsAlreadySpent(_) => "PROOFS_ALREADY_SPENT",
+            Self::DatabaseError(_) => "DATABASE_ERROR",
+            Self::ChannelOpenError(_) => "CHANNEL_OPEN_ERROR",
+            Self::WalletError(_) => "WALLET_ERROR",
+            Self::MeltError(_) => "MELT_ERROR",
+            Self::ProofVerificationError(_) => "PROOF_VERIFICATION_ERROR",
+            Self::MintUnreachable(_) => "MINT_UNREACHABLE",
+            Self::ClientDisconnected(_) => "CLIENT_DISCONNECTED",
+            Self::RateLimited { .. } => "RATE_LIMITED",
+            Self::TooManyOpenQuotes { .. } => "TOO_MANY_OPEN_QUOTES",
+            Self::ExchangeRateUnavailable(_) => "EXCHANGE_RATE_UNAVAILABLE",
+            Self::InternalError(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
             Self::InvalidUuid(_)
+            | Self::InvalidAmount(_)
+            | Self::InvalidQueryParameter(_)
+            | Self::MissingParameter(_)
             | Self::InvalidChannelSize { .. }
+            | Self::InvalidQuoteCount { .. }
             | Self::UnsupportedMint(_)
+            | Self::MintUnitNotSupported { .. }
             | Self::UnsupportedCurrencyUnit { .. }
             | Self::InvalidQuoteState { .. }
-            | Self::InsufficientPayment { .. } => StatusCode::BAD_REQUEST,
+            | Self::InsufficientPayment { .. }
+            | Self::Overpayment { .. }
+            | Self::UnitMismatch { .. }
+            | Self::InsufficientBalance { .. }
+            | Self::InvalidHtlcPreimage
+            | Self::InvalidWebhookUrl(_)
+            | Self::MemoTooLong { .. }
+            | Self::MissingHtlcPreimage(_)
+            | Self::P2pkRequired(_)
+            | Self::ProofsAlreadyReceived(_)
+            | Self::ProofVerificationError(_) => StatusCode::BAD_REQUEST,
+
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+
+            Self::QuoteNotFound(_) | Self::ReferenceNotFound(_) | Self::WalletNotFound { .. } => {
+                StatusCode::NOT_FOUND
+            }
+
+            Self::QuoteExpired(_) => StatusCode::GONE,
+
+            Self::ClientDisconnected(_) => StatusCode::REQUEST_TIMEOUT,
+
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+
+            Self::ExchangeRateUnavailable(_) | Self::TooManyOpenQuotes { .. } => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+
+            // The proofs were already redeemed, most likely by an earlier payment of the payer
+            Self::ProofsAlreadySpent(_) => StatusCode::CONFLICT,
 
-            Self::QuoteNotFound(_) => StatusCode::NOT_FOUND,
+            // The mint or the lightning network behind it failed, not this server
+            Self::MeltError(_) | Self::MintUnreachable(_) => StatusCode::BAD_GATEWAY,
 
             Self::DatabaseError(_)
             | Self::ChannelOpenError(_)
             | Self::WalletError(_)
-            | Self::ProofVerificationError(_)
             | Self::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Fields of the error a client may act on, `None` if the message says it all
+    pub fn detail(&self) -> Option<serde_json::Value> {
+        let detail = match self {
+            Self::InvalidUuid(id) => json!({ "id": id }),
+            Self::MissingParameter(name) => json!({ "parameter": name }),
+            Self::QuoteNotFound(id)
+            | Self::QuoteExpired(id)
+            | Self::MissingHtlcPreimage(id)
+            | Self::P2pkRequired(id)
+            | Self::ProofsAlreadyReceived(id)
+            | Self::ProofsAlreadySpent(id)
+            | Self::ClientDisconnected(id) => json!({ "id": id }),
+            Self::ReferenceNotFound(reference) => json!({ "reference": reference }),
+            Self::InvalidChannelSize { size, min, max } => {
+                json!({ "size": size, "min": min, "max": max })
+            }
+            Self::InvalidQuoteCount { count, max } => json!({ "count": count, "max": max }),
+            Self::UnsupportedMint(mint) => json!({ "mint": mint }),
+            Self::WalletNotFound { mint, unit } | Self::MintUnitNotSupported { mint, unit } => {
+                json!({ "mint": mint, "unit": unit })
+            }
+            Self::UnsupportedCurrencyUnit {
+                given,
+                allowed,
+                suggestion,
+                hint,
+            } => json!({
+                "given": given,
+                "allowed": allowed,
+                "suggestion": suggestion,
+                "hint": hint,
+            }),
+            Self::InvalidQuoteState { id, state } => json!({ "id": id, "state": state }),
+            Self::InsufficientPayment { expected, received }
+            | Self::Overpayment { expected, received } => json!({
+                "expected": expected.value,
+                "received": received.value,
+                "unit": expected.unit,
+            }),
+            Self::UnitMismatch { expected, received } => {
+                json!({ "expected": expected, "received": received })
+            }
+            Self::InsufficientBalance {
+                available,
+                required,
+            } => json!({
+                "available": available.value,
+                "required": required.value,
+                "unit": required.unit,
+            }),
+            Self::MemoTooLong { length, max } => json!({ "length": length, "max": max }),
+            Self::RateLimited { retry_after } => json!({ "retry_after": retry_after }),
+            Self::TooManyOpenQuotes { open, max } => json!({ "open": open, "max": max }),
+            _ => return None,
         };
 
+        Some(detail)
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            detail: self.detail(),
+        }
+    }
+
+    /// Response for the error without the error log `into_response` emits
+    pub fn into_unlogged_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+/// JSON body of every error response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ErrorBody {
+    /// Stable identifier of the error kind, see [`PosError::code`]
+    #[cfg_attr(feature = "openapi", schema(example = "QUOTE_NOT_FOUND"))]
+    pub code: String,
+    /// Human readable description, not meant to be parsed
+    pub message: String,
+    /// Values behind the error, e.g. `expected`, `received` and `unit` of an insufficient
+    /// payment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub detail: Option<serde_json::Value>,
+}
+
+impl IntoResponse for PosError {
+    fn into_response(self) -> Response {
         tracing::error!("POS error: {}", self);
-        (status, self.to_string()).into_response()
+        self.into_unlogged_response()
     }
 }
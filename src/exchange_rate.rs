@@ -0,0 +1,213 @@
+//! Bitcoin exchange rates for pricing quotes in fiat
+//!
+//! Rates come from a ticker API and are cached per currency for a configurable
+//! time, so a burst of quotes asks the ticker once rather than once per quote.
+//! Fetching them needs the `exchange-rate` feature.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use cdk::nuts::CurrencyUnit;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::types::ConfigDuration;
+
+#[cfg(feature = "exchange-rate")]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Currencies quotes can be priced in
+pub const FIAT_CURRENCIES: [CurrencyUnit; 2] = [CurrencyUnit::Usd, CurrencyUnit::Eur];
+
+/// Source of the price of one bitcoin in a fiat currency
+#[async_trait]
+pub trait ExchangeRate: Send + Sync {
+    /// Price of one bitcoin in major units of `currency`
+    async fn btc_price(&self, currency: &CurrencyUnit) -> Result<f64>;
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExchangeRateProvider {
+    /// Coinbase spot price
+    Coinbase,
+    /// Kraken last trade price
+    Kraken,
+    /// Any JSON ticker, set with `url` and `price_pointer`
+    Custom,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExchangeRateSettings {
+    /// Ticker used to price quotes given in fiat, fiat pricing is off if unset
+    pub provider: Option<ExchangeRateProvider>,
+    /// Ticker URL of the `custom` provider, `{currency}` is replaced by the currency code
+    pub url: Option<String>,
+    /// JSON pointer to the price in the `custom` ticker's response, may contain `{currency}`
+    pub price_pointer: Option<String>,
+    /// How long a fetched rate is reused for, in seconds or e.g. `"2m"`
+    pub cache_ttl_seconds: ConfigDuration,
+}
+
+impl Default for ExchangeRateSettings {
+    fn default() -> Self {
+        Self {
+            provider: None,
+            url: None,
+            price_pointer: None,
+            cache_ttl_seconds: ConfigDuration::from_secs(60),
+        }
+    }
+}
+
+impl ExchangeRateSettings {
+    /// Ticker URL and price pointer of the configured provider
+    fn endpoint(&self) -> Result<Option<(String, String)>> {
+        let endpoint = match self.provider {
+            None => return Ok(None),
+            Some(ExchangeRateProvider::Coinbase) => (
+                "https://api.coinbase.com/v2/prices/BTC-{currency}/spot".to_string(),
+                "/data/amount".to_string(),
+            ),
+            Some(ExchangeRateProvider::Kraken) => (
+                "https://api.kraken.com/0/public/Ticker?pair=XBT{currency}".to_string(),
+                "/result/XXBTZ{currency}/c/0".to_string(),
+            ),
+            Some(ExchangeRateProvider::Custom) => match (&self.url, &self.price_pointer) {
+                (Some(url), Some(pointer)) => (url.clone(), pointer.clone()),
+                _ => bail!("The custom exchange rate provider needs both url and price_pointer"),
+            },
+        };
+
+        Ok(Some(endpoint))
+    }
+}
+
+/// Exchange rate source described by `settings`, `None` when fiat pricing is off
+pub fn from_settings(settings: &ExchangeRateSettings) -> Result<Option<Arc<dyn ExchangeRate>>> {
+    let Some((url, price_pointer)) = settings.endpoint()? else {
+        return Ok(None);
+    };
+
+    http_exchange_rate(url, price_pointer, settings.cache_ttl_seconds.as_duration()).map(Some)
+}
+
+#[cfg(feature = "exchange-rate")]
+fn http_exchange_rate(
+    url: String,
+    price_pointer: String,
+    ttl: Duration,
+) -> Result<Arc<dyn ExchangeRate>> {
+    Ok(Arc::new(CachedExchangeRate::new(
+        HttpExchangeRate::new(url, price_pointer),
+        ttl,
+    )))
+}
+
+#[cfg(not(feature = "exchange-rate"))]
+fn http_exchange_rate(
+    _url: String,
+    _price_pointer: String,
+    _ttl: Duration,
+) -> Result<Arc<dyn ExchangeRate>> {
+    bail!("exchange_rate.provider needs the exchange-rate feature")
+}
+
+/// Reads the price from a JSON ticker API
+#[cfg(feature = "exchange-rate")]
+pub struct HttpExchangeRate {
+    client: reqwest::Client,
+    url: String,
+    price_pointer: String,
+}
+
+#[cfg(feature = "exchange-rate")]
+impl HttpExchangeRate {
+    /// `{currency}` in `url` and `price_pointer` is replaced by the upper case currency code
+    pub fn new(url: String, price_pointer: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            price_pointer,
+        }
+    }
+}
+
+#[cfg(feature = "exchange-rate")]
+#[async_trait]
+impl ExchangeRate for HttpExchangeRate {
+    async fn btc_price(&self, currency: &CurrencyUnit) -> Result<f64> {
+        let code = currency.to_string().to_uppercase();
+        let url = self.url.replace("{currency}", &code);
+        let pointer = self.price_pointer.replace("{currency}", &code);
+
+        let body: serde_json::Value = self
+            .client
+            .get(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // Tickers write prices as strings as often as numbers
+        let price = match body.pointer(&pointer) {
+            Some(serde_json::Value::String(price)) => price.parse::<f64>().ok(),
+            Some(serde_json::Value::Number(price)) => price.as_f64(),
+            _ => None,
+        };
+
+        price
+            .filter(|price| price.is_finite() && *price > 0.0)
+            .ok_or(anyhow::anyhow!(
+                "No valid price at {} in response of {}",
+                pointer,
+                url
+            ))
+    }
+}
+
+/// Reuses the rates of another source for `ttl`
+pub struct CachedExchangeRate<T> {
+    inner: T,
+    ttl: Duration,
+    rates: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl<T> CachedExchangeRate<T> {
+    pub fn new(inner: T, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            rates: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: ExchangeRate> ExchangeRate for CachedExchangeRate<T> {
+    async fn btc_price(&self, currency: &CurrencyUnit) -> Result<f64> {
+        // Held across the fetch so concurrent quotes wait for one request instead of each
+        // sending their own
+        let mut rates = self.rates.lock().await;
+        let key = currency.to_string();
+
+        if let Some((price, _)) = rates
+            .get(&key)
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < self.ttl)
+        {
+            return Ok(*price);
+        }
+
+        let price = self.inner.btc_price(currency).await?;
+        tracing::debug!("Fetched bitcoin price of {} {}", price, key);
+        rates.insert(key, (price, Instant::now()));
+
+        Ok(price)
+    }
+}
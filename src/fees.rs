@@ -0,0 +1,85 @@
+//! Input fee estimates for incoming payment payloads
+
+/// Heuristic used for every estimate, returned alongside it so callers know what was assumed
+pub const FEE_ESTIMATE_ASSUMPTIONS: &str = "Payload is assumed to hold one proof per power-of-two denomination of the amount (one per set bit), each charged the active keyset input fee for the unit";
+
+/// Number of proofs a payload of `amount` is assumed to contain
+pub fn estimated_proof_count(amount: u64) -> u64 {
+    amount.count_ones() as u64
+}
+
+/// Fee for spending `proof_count` proofs at `input_fee_ppk`, rounded up as mints do
+pub fn input_fee(proof_count: u64, input_fee_ppk: u64) -> u64 {
+    proof_count.saturating_mul(input_fee_ppk).div_ceil(1000)
+}
+
+/// Fee for spending proofs whose keysets charge `input_fee_ppks`, summed then rounded up as
+/// mints do
+pub fn proofs_fee(input_fee_ppks: impl IntoIterator<Item = u64>) -> u64 {
+    input_fee_ppks
+        .into_iter()
+        .fold(0, u64::saturating_add)
+        .div_ceil(1000)
+}
+
+/// Amount to ask for so that `net` is left once the input fees of the payload are paid
+pub fn gross_amount(net: u64, input_fee_ppk: u64) -> u64 {
+    let mut gross = net;
+
+    // The fee depends on the proof count of the gross amount, so step until it settles
+    for _ in 0..64 {
+        let next = net.saturating_add(input_fee(estimated_proof_count(gross), input_fee_ppk));
+        if next <= gross {
+            break;
+        }
+        gross = next;
+    }
+
+    gross
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_fee_rounds_up() {
+        assert_eq!(input_fee(3, 0), 0);
+        assert_eq!(input_fee(3, 100), 1);
+        assert_eq!(input_fee(10, 100), 1);
+        assert_eq!(input_fee(11, 100), 2);
+        assert_eq!(input_fee(3, 1000), 3);
+        assert_eq!(input_fee(u64::MAX, 1000), u64::MAX.div_ceil(1000));
+    }
+
+    #[test]
+    fn proofs_fee_sums_before_rounding() {
+        assert_eq!(proofs_fee([]), 0);
+        assert_eq!(proofs_fee([100, 100, 100]), 1);
+        // Proofs from keysets with different fees are rounded once, not per keyset
+        assert_eq!(proofs_fee([400, 700]), 2);
+        assert_eq!(proofs_fee([500, 500]), 1);
+    }
+
+    #[test]
+    fn gross_amount_at_several_rates() {
+        // 100 is 64 + 32 + 4, three proofs
+        assert_eq!(estimated_proof_count(100), 3);
+
+        assert_eq!(gross_amount(100, 0), 100);
+        assert_eq!(gross_amount(100, 100), 101);
+        assert_eq!(gross_amount(100, 1000), 105);
+        assert_eq!(gross_amount(0, 1000), 0);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn gross_amount_leaves_the_net_amount(net in 0u64..1 << 40, input_fee_ppk in 0u64..5000) {
+            let gross = gross_amount(net, input_fee_ppk);
+            let fee = input_fee(estimated_proof_count(gross), input_fee_ppk);
+
+            proptest::prop_assert!(gross >= net);
+            proptest::prop_assert!(gross - fee >= net);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Encryption at rest of the HTLC preimages agreed for quotes
+//!
+//! Whoever holds a preimage can redeem the proofs locked to its hash, so quotes store
+//! it encrypted with a key derived from the wallet seed and it is only decrypted right
+//! before the wallet receives the proofs.
+
+use std::fmt;
+
+use anyhow::anyhow;
+use cdk::util::hex;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::error::PosError;
+
+/// Marks a stored preimage as `nonce || ciphertext` in hex rather than the plaintext
+/// stored before preimages were encrypted
+const ENCRYPTED_PREFIX: &str = "xchacha20poly1305:";
+
+const NONCE_LENGTH: usize = 24;
+
+/// Key HTLC preimages are encrypted with before they are stored
+#[derive(Clone)]
+pub struct HtlcPreimageKey(Key);
+
+impl HtlcPreimageKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key.into())
+    }
+
+    pub fn from_hex(key: &str) -> anyhow::Result<Self> {
+        let key: [u8; 32] = hex::decode(key)
+            .map_err(|e| anyhow!("Invalid HTLC preimage key: {}", e))?
+            .try_into()
+            .map_err(|_| anyhow!("HTLC preimage key must be 32 bytes"))?;
+
+        Ok(Self::new(key))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// Encrypt `preimage` for storage with a fresh random nonce
+    pub fn encrypt(&self, preimage: &str) -> Result<String, PosError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(&self.0)
+            .encrypt(&nonce, preimage.as_bytes())
+            .map_err(|e| {
+                PosError::InternalError(format!("Failed to encrypt HTLC preimage: {}", e))
+            })?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, hex::encode(sealed)))
+    }
+
+    /// Preimage of a stored value written by [`HtlcPreimageKey::encrypt`]
+    ///
+    /// Values stored in plaintext before preimages were encrypted are returned as they are.
+    pub fn decrypt(&self, stored: &str) -> Result<String, PosError> {
+        let Some(sealed) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+
+        let undecryptable = |reason: String| {
+            PosError::InternalError(format!("Cannot decrypt HTLC preimage: {}", reason))
+        };
+
+        let sealed = hex::decode(sealed).map_err(|e| undecryptable(e.to_string()))?;
+        if sealed.len() < NONCE_LENGTH {
+            return Err(undecryptable("too short".to_string()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+        let preimage = XChaCha20Poly1305::new(&self.0)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| undecryptable("wrong key or corrupted value".to_string()))?;
+
+        String::from_utf8(preimage).map_err(|e| undecryptable(e.to_string()))
+    }
+}
+
+impl fmt::Debug for HtlcPreimageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HtlcPreimageKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREIMAGE: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+    #[test]
+    fn encrypted_preimage_round_trips() {
+        let key = HtlcPreimageKey::new([7; 32]);
+        let stored = key.encrypt(PREIMAGE).unwrap();
+
+        assert!(stored.starts_with(ENCRYPTED_PREFIX));
+        assert!(!stored.contains(PREIMAGE));
+        assert_eq!(key.decrypt(&stored).unwrap(), PREIMAGE);
+
+        // Each encryption takes a fresh nonce
+        assert_ne!(key.encrypt(PREIMAGE).unwrap(), stored);
+    }
+
+    #[test]
+    fn other_keys_and_tampered_values_fail() {
+        let key = HtlcPreimageKey::new([7; 32]);
+        let stored = key.encrypt(PREIMAGE).unwrap();
+
+        assert!(HtlcPreimageKey::new([8; 32]).decrypt(&stored).is_err());
+
+        let mut tampered = stored.clone();
+        let last = tampered.pop().unwrap();
+        tampered.push(if last == '0' { '1' } else { '0' });
+        assert!(key.decrypt(&tampered).is_err());
+
+        assert!(key.decrypt(ENCRYPTED_PREFIX).is_err());
+    }
+
+    #[test]
+    fn plaintext_from_before_encryption_is_read_as_is() {
+        let key = HtlcPreimageKey::new([7; 32]);
+        assert_eq!(key.decrypt(PREIMAGE).unwrap(), PREIMAGE);
+    }
+
+    #[test]
+    fn key_round_trips_through_hex() {
+        let key = HtlcPreimageKey::new([7; 32]);
+        let stored = key.encrypt(PREIMAGE).unwrap();
+
+        let parsed = HtlcPreimageKey::from_hex(&key.to_hex()).unwrap();
+        assert_eq!(parsed.decrypt(&stored).unwrap(), PREIMAGE);
+        assert!(HtlcPreimageKey::from_hex("abcd").is_err());
+        assert_eq!(format!("{:?}", key), "HtlcPreimageKey(..)");
+    }
+}
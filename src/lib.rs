@@ -1,19 +1,246 @@
-use cdk::wallet::MultiMintWallet;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
 
+use anyhow::anyhow;
+use cdk::amount::Amount;
+use cdk::mint_url::MintUrl;
+use cdk::nuts::{CurrencyUnit, Id, Keys, Proofs};
+use cdk::wallet::types::WalletKey;
+use cdk::wallet::{MultiMintWallet, Wallet};
+use tokio_util::task::TaskTracker;
+
+pub mod auth;
+pub mod capture;
+#[cfg(feature = "server-bin")]
 pub mod config;
+pub mod consolidation;
 pub mod db;
 pub mod error;
+pub mod exchange_rate;
+pub mod fees;
+pub mod htlc;
+pub mod log_throttle;
+pub mod maintenance;
+#[cfg(feature = "notifications-nostr")]
+pub mod nostr;
+#[cfg(feature = "openapi")]
+pub mod openapi;
 pub mod pos_server;
+pub mod rate_limit;
+pub mod request_id;
+#[cfg(feature = "server-bin")]
+pub mod seed;
+#[cfg(feature = "server-bin")]
+pub mod setup;
+pub mod sweep;
+pub mod timings;
 pub mod types;
+pub mod validation;
+#[cfg(feature = "notifications-webhook")]
+pub mod webhook;
 
-pub use pos_server::create_cashu_pos_router;
+pub use pos_server::{create_cashu_pos_router, create_reloadable_cashu_pos_router};
 
 pub struct CashuPos {
     wallet: MultiMintWallet,
+    /// Public keys of the keysets seen so far, which never change once published
+    keyset_keys: RwLock<HashMap<Id, Keys>>,
+    /// Held while proofs are consolidated, swept, withdrawn or sent so none of them
+    /// spend proofs another is using
+    consolidation: tokio::sync::Mutex<()>,
+    /// Payments being processed, which must finish before the process exits
+    payments: TaskTracker,
 }
 
 impl CashuPos {
     pub fn new(wallet: MultiMintWallet) -> anyhow::Result<Self> {
-        Ok(Self { wallet })
+        Ok(Self {
+            wallet,
+            keyset_keys: RwLock::new(HashMap::new()),
+            consolidation: tokio::sync::Mutex::new(()),
+            payments: TaskTracker::new(),
+        })
+    }
+
+    /// Wait up to `grace_period` for payments in progress to finish, returning how many
+    /// are still running
+    ///
+    /// Call once no more payments can arrive, i.e. after the server stopped accepting
+    /// requests.
+    pub async fn drain_payments(&self, grace_period: Duration) -> usize {
+        self.payments.close();
+
+        if !self.payments.is_empty() {
+            tracing::info!(
+                "Waiting for {} payments in progress to finish",
+                self.payments.len()
+            );
+        }
+
+        let _ = tokio::time::timeout(grace_period, self.payments.wait()).await;
+
+        self.payments.len()
+    }
+
+    /// Keys of a keyset, cached after the first lookup through `wallet`
+    async fn keyset_keys(&self, wallet: &Wallet, keyset_id: Id) -> anyhow::Result<Keys> {
+        let cached = self
+            .keyset_keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&keyset_id)
+            .cloned();
+
+        if let Some(keys) = cached {
+            return Ok(keys);
+        }
+
+        let keys = wallet.get_keyset_keys(keyset_id).await?;
+
+        self.keyset_keys
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(keyset_id, keys.clone());
+
+        Ok(keys)
+    }
+
+    /// Check the DLEQ proofs (NUT-12) carried by `proofs` against the keys of their keysets,
+    /// returning how many proofs were verified
+    ///
+    /// Proofs without DLEQ data, or whose keyset keys cannot be fetched, are skipped and left
+    /// for the mint to check on receive.
+    pub async fn verify_dleq(&self, wallet: &Wallet, proofs: &Proofs) -> anyhow::Result<usize> {
+        let mut verified = 0;
+
+        for proof in proofs.iter().filter(|proof| proof.dleq.is_some()) {
+            let keys = match self.keyset_keys(wallet, proof.keyset_id).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    tracing::debug!(
+                        "Skipping DLEQ check, keys of keyset {} unavailable: {}",
+                        proof.keyset_id,
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            let mint_pubkey = keys.amount_key(proof.amount).ok_or(anyhow!(
+                "Keyset {} has no key for amount {}",
+                proof.keyset_id,
+                proof.amount
+            ))?;
+
+            proof.verify_dleq(mint_pubkey).map_err(|e| {
+                anyhow!(
+                    "Invalid DLEQ proof for {} in keyset {}: {}",
+                    proof.amount,
+                    proof.keyset_id,
+                    e
+                )
+            })?;
+
+            verified += 1;
+        }
+
+        Ok(verified)
+    }
+
+    /// Add a wallet, e.g. for a mint accepted after a config reload, unless one exists for
+    /// its mint and unit
+    pub async fn add_wallet(&self, wallet: Wallet) {
+        let key = WalletKey::new(wallet.mint_url.clone(), wallet.unit.clone());
+
+        if self.wallet.get_wallet(&key).await.is_none() {
+            self.wallet.add_wallet(wallet).await;
+        }
+    }
+
+    /// Balance of every wallet as `(mint, unit, balance)`
+    ///
+    /// Counts the unspent proofs stored locally, so it works while a mint is unreachable.
+    pub async fn balances(&self) -> anyhow::Result<Vec<(MintUrl, CurrencyUnit, Amount)>> {
+        let mut balances = Vec::new();
+
+        for wallet in self.wallet.get_wallets().await {
+            let balance = wallet.total_balance().await?;
+            balances.push((wallet.mint_url.clone(), wallet.unit.clone(), balance));
+        }
+
+        Ok(balances)
+    }
+
+    /// Balance of the wallet for a mint and unit, `None` if there is no such wallet
+    pub async fn balance(
+        &self,
+        mint_url: &MintUrl,
+        unit: &CurrencyUnit,
+    ) -> anyhow::Result<Option<Amount>> {
+        let wallet = self
+            .wallet
+            .get_wallet(&WalletKey::new(mint_url.clone(), unit.clone()))
+            .await;
+
+        match wallet {
+            Some(wallet) => Ok(Some(wallet.total_balance().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch the info of a mint to check it is reachable, giving up after `timeout`
+    ///
+    /// Any wallet of the mint is used since the info doesn't depend on the unit.
+    pub async fn check_mint(&self, mint_url: &MintUrl, timeout: Duration) -> anyhow::Result<()> {
+        let wallet = self
+            .wallet
+            .get_wallets()
+            .await
+            .into_iter()
+            .find(|wallet| wallet.mint_url == *mint_url)
+            .ok_or(anyhow!("No wallet created for {}", mint_url))?;
+
+        tokio::time::timeout(timeout, wallet.get_mint_info())
+            .await
+            .map_err(|_| anyhow!("No response within {:?}", timeout))??;
+
+        Ok(())
+    }
+
+    /// Input fee in parts per thousand per proof of the active keyset for a mint and unit
+    ///
+    /// Uses the keysets cached in the wallet database and only asks the mint when none are cached.
+    pub async fn input_fee_ppk(
+        &self,
+        mint_url: &MintUrl,
+        unit: &CurrencyUnit,
+    ) -> anyhow::Result<u64> {
+        let wallet = self
+            .wallet
+            .get_wallet(&WalletKey::new(mint_url.clone(), unit.clone()))
+            .await
+            .ok_or(anyhow!(
+                "Wallet not created for {} with unit {}",
+                mint_url,
+                unit
+            ))?;
+
+        let cached_keyset = wallet
+            .localstore
+            .get_mint_keysets(mint_url.clone())
+            .await?
+            .and_then(|keysets| {
+                keysets
+                    .into_iter()
+                    .find(|keyset| keyset.active && keyset.unit == *unit)
+            });
+
+        let input_fee_ppk = match cached_keyset {
+            Some(keyset) => keyset.input_fee_ppk,
+            None => wallet.get_active_mint_keyset().await?.input_fee_ppk,
+        };
+
+        Ok(input_fee_ppk)
     }
 }
@@ -0,0 +1,310 @@
+//! Throttling of repeated payment failure logs
+//!
+//! A wallet retrying a broken payload every second would otherwise fill the
+//! logs with identical errors. The first occurrence of a (quote, error code)
+//! pair is always logged, later repeats are sampled and summarised. Every
+//! occurrence is still counted per error code, whether it was logged or not.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::types::ConfigDuration;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogThrottleSettings {
+    /// Log one in every `sample_rate` repeats of the same failure
+    pub sample_rate: u64,
+    /// How often a summary of suppressed repeats is emitted
+    pub summary_interval: ConfigDuration,
+    /// Maximum number of distinct failures tracked at once
+    pub max_entries: usize,
+}
+
+impl Default for LogThrottleSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 100,
+            summary_interval: ConfigDuration::from_secs(60),
+            max_entries: 1000,
+        }
+    }
+}
+
+type ThrottleKey = (Option<Uuid>, &'static str);
+
+#[derive(Debug)]
+struct ThrottleEntry {
+    /// Occurrences since the entry was created
+    total: u64,
+    /// Occurrences not logged since the last summary
+    suppressed: u64,
+    last_seen: Instant,
+}
+
+#[derive(Debug)]
+struct ThrottleState {
+    entries: HashMap<ThrottleKey, ThrottleEntry>,
+    /// Occurrences of each error code since the throttle was created
+    occurrences: HashMap<&'static str, u64>,
+    last_summary: Instant,
+}
+
+#[derive(Debug)]
+pub struct LogThrottle {
+    settings: LogThrottleSettings,
+    state: Mutex<ThrottleState>,
+}
+
+impl LogThrottle {
+    pub fn new(settings: LogThrottleSettings) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(ThrottleState {
+                entries: HashMap::new(),
+                occurrences: HashMap::new(),
+                last_summary: Instant::now(),
+            }),
+        }
+    }
+
+    /// Throttle whose summaries are also emitted every `summary_interval` by a background
+    /// task, so repeats suppressed just before the failures stop are still reported
+    ///
+    /// The task ends once the throttle is dropped.
+    pub fn spawn(settings: LogThrottleSettings) -> Arc<Self> {
+        let throttle = Arc::new(Self::new(settings));
+        let weak = Arc::downgrade(&throttle);
+        let period = settings
+            .summary_interval
+            .as_duration()
+            .max(Duration::from_millis(1));
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+
+                let Some(throttle) = weak.upgrade() else {
+                    break;
+                };
+                throttle.flush();
+            }
+        });
+
+        throttle
+    }
+
+    /// Emit the summary if `summary_interval` has passed since the previous one
+    pub fn flush(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if now.duration_since(state.last_summary) >= self.settings.summary_interval.as_duration() {
+            Self::emit_summary(&mut state, now);
+        }
+    }
+
+    /// Occurrences of each error code recorded so far, logged or not
+    pub fn occurrences(&self) -> HashMap<&'static str, u64> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .occurrences
+            .clone()
+    }
+
+    /// Record a failure and return whether it should be logged in full
+    pub fn should_log(&self, quote_id: Option<Uuid>, code: &'static str) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if now.duration_since(state.last_summary) >= self.settings.summary_interval.as_duration() {
+            Self::emit_summary(&mut state, now);
+        }
+
+        *state.occurrences.entry(code).or_default() += 1;
+
+        if let Some(entry) = state.entries.get_mut(&(quote_id, code)) {
+            entry.total += 1;
+            entry.last_seen = now;
+
+            if entry.total % self.settings.sample_rate.max(1) == 0 {
+                return true;
+            }
+
+            entry.suppressed += 1;
+            return false;
+        }
+
+        if state.entries.len() >= self.settings.max_entries {
+            // Make room by forgetting the failure that was seen least recently
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_seen)
+                .map(|(key, _)| *key);
+
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        state.entries.insert(
+            (quote_id, code),
+            ThrottleEntry {
+                total: 1,
+                suppressed: 0,
+                last_seen: now,
+            },
+        );
+
+        true
+    }
+
+    /// Log suppressed counts and drop failures not seen since the previous summary
+    fn emit_summary(state: &mut ThrottleState, now: Instant) {
+        let since = state.last_summary;
+
+        for ((quote_id, code), entry) in state.entries.iter_mut() {
+            if entry.suppressed > 0 {
+                tracing::warn!(
+                    "Suppressed {} repeated {} failures for quote {} ({} total)",
+                    entry.suppressed,
+                    code,
+                    quote_id.map_or("unknown".to_string(), |id| id.to_string()),
+                    entry.total
+                );
+                entry.suppressed = 0;
+            }
+        }
+
+        state.entries.retain(|_, entry| entry.last_seen >= since);
+        state.last_summary = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(sample_rate: u64, summary_interval: ConfigDuration) -> LogThrottleSettings {
+        LogThrottleSettings {
+            sample_rate,
+            summary_interval,
+            max_entries: 2,
+        }
+    }
+
+    fn suppressed(throttle: &LogThrottle, key: ThrottleKey) -> Option<u64> {
+        let state = throttle.state.lock().unwrap();
+        state.entries.get(&key).map(|entry| entry.suppressed)
+    }
+
+    #[test]
+    fn logs_first_failure_then_samples_repeats() {
+        let throttle = LogThrottle::new(settings(3, ConfigDuration::from_secs(3600)));
+        let quote = Some(Uuid::new_v4());
+
+        let logged: Vec<bool> = (0..7)
+            .map(|_| throttle.should_log(quote, "PROOF_VERIFICATION_FAILED"))
+            .collect();
+
+        assert_eq!(logged, [true, false, true, false, false, true, false]);
+        assert_eq!(
+            suppressed(&throttle, (quote, "PROOF_VERIFICATION_FAILED")),
+            Some(4)
+        );
+
+        // A different code or quote is a new failure and logged in full
+        assert!(throttle.should_log(quote, "QUOTE_EXPIRED"));
+        assert!(throttle.should_log(None, "PROOF_VERIFICATION_FAILED"));
+    }
+
+    #[test]
+    fn counts_every_occurrence() {
+        let throttle = LogThrottle::new(settings(100, ConfigDuration::from_secs(3600)));
+
+        for _ in 0..5 {
+            throttle.should_log(None, "QUOTE_EXPIRED");
+        }
+        // More distinct failures than `max_entries` evict entries but keep their counts
+        for _ in 0..3 {
+            throttle.should_log(Some(Uuid::new_v4()), "PROOF_VERIFICATION_FAILED");
+        }
+
+        let occurrences = throttle.occurrences();
+        assert_eq!(occurrences.get("QUOTE_EXPIRED"), Some(&5));
+        assert_eq!(occurrences.get("PROOF_VERIFICATION_FAILED"), Some(&3));
+        assert_eq!(throttle.state.lock().unwrap().entries.len(), 2);
+    }
+
+    #[test]
+    fn flush_waits_for_the_summary_interval() {
+        let throttle = LogThrottle::new(settings(100, ConfigDuration::from_secs(3600)));
+
+        throttle.should_log(None, "QUOTE_EXPIRED");
+        throttle.should_log(None, "QUOTE_EXPIRED");
+        throttle.flush();
+
+        assert_eq!(suppressed(&throttle, (None, "QUOTE_EXPIRED")), Some(1));
+    }
+
+    #[test]
+    fn flush_resets_suppressed_and_drops_stale_failures() {
+        let throttle = LogThrottle::new(settings(100, ConfigDuration::from_secs(60)));
+        let key = (None, "QUOTE_EXPIRED");
+        let backdate = |by: Duration| {
+            let mut state = throttle.state.lock().unwrap();
+            state.last_summary -= by;
+            if let Some(entry) = state.entries.get_mut(&key) {
+                entry.last_seen -= by;
+            }
+        };
+
+        throttle.should_log(key.0, key.1);
+        throttle.should_log(key.0, key.1);
+        throttle.state.lock().unwrap().last_summary -= Duration::from_secs(120);
+        throttle.flush();
+
+        // Seen since the previous summary, so kept with its repeats reported
+        assert_eq!(suppressed(&throttle, key), Some(0));
+
+        backdate(Duration::from_secs(120));
+        throttle.flush();
+
+        assert_eq!(suppressed(&throttle, key), None);
+        assert_eq!(throttle.occurrences().get("QUOTE_EXPIRED"), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn timer_flushes_without_new_failures() {
+        let throttle = LogThrottle::spawn(settings(100, ConfigDuration(Duration::from_millis(20))));
+
+        throttle.should_log(None, "QUOTE_EXPIRED");
+        throttle.should_log(None, "QUOTE_EXPIRED");
+        assert_eq!(suppressed(&throttle, (None, "QUOTE_EXPIRED")), Some(1));
+
+        // Two summaries, the first reporting the repeat and the second dropping the entry
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(suppressed(&throttle, (None, "QUOTE_EXPIRED")), None);
+    }
+
+    #[tokio::test]
+    async fn timer_stops_with_the_throttle() {
+        let throttle = LogThrottle::spawn(settings(100, ConfigDuration(Duration::from_millis(5))));
+        let weak = Arc::downgrade(&throttle);
+        drop(throttle);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,69 @@
+//! Periodic cleanup of the quote store
+//!
+//! Unpaid quotes already report `Expired` once past their expiry, the sweep
+//! stores that state so listings and reports stop counting them as open.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use cdk::util::unix_time;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::db::QuoteStore;
+
+/// Quotes expired per write transaction, keeping each sweep step short
+const EXPIRY_BATCH_SIZE: usize = 500;
+
+pub fn default_expiry_sweep_interval_seconds() -> u64 {
+    60
+}
+
+/// Expire stale quotes every `interval` until `shutdown` is cancelled
+///
+/// A sweep in progress finishes its current batch before the task stops, await
+/// the returned handle after cancelling to wait for it.
+pub fn spawn_maintenance(
+    db: Arc<dyn QuoteStore>,
+    interval: Duration,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => expire_stale_quotes(db.as_ref(), &shutdown).await,
+            }
+        }
+
+        tracing::debug!("Quote maintenance stopped");
+    })
+}
+
+async fn expire_stale_quotes(db: &dyn QuoteStore, shutdown: &CancellationToken) {
+    let now = unix_time();
+    let mut total = 0;
+
+    loop {
+        match db.expire_quotes(now, EXPIRY_BATCH_SIZE).await {
+            Ok(expired) => {
+                total += expired;
+
+                if expired < EXPIRY_BATCH_SIZE || shutdown.is_cancelled() {
+                    break;
+                }
+            }
+            Err(e) => {
+                tracing::error!("Could not expire stale quotes: {}", e);
+                break;
+            }
+        }
+    }
+
+    if total > 0 {
+        tracing::info!("Expired {} stale quotes", total);
+    }
+}
@@ -0,0 +1,123 @@
+//! NUT-18 Nostr transport
+//!
+//! Wallets gift wrap (NIP-59) the payment payload to the POS key and publish it on
+//! the relays listed in the request's nprofile. The listener unwraps those events
+//! and hands the payloads to the same processing as `POST /payment`, whose atomic
+//! quote transition keeps a payload delivered over both transports from being
+//! redeemed twice.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Context;
+use cdk::nuts::{PaymentRequestPayload, Transport, TransportType};
+use nostr_sdk::nips::nip19::{Nip19Profile, ToBech32};
+use nostr_sdk::nips::nip59::UnwrappedGift;
+use nostr_sdk::{Client, Filter, Keys, Kind, RelayPoolNotification};
+use tokio::sync::broadcast::error::RecvError;
+
+pub struct NostrTransport {
+    client: Client,
+    keys: Keys,
+    /// Bech32 nprofile of the POS key and its relays, the transport target
+    target: String,
+}
+
+impl NostrTransport {
+    /// Connect to `relays` with the secret key `secret_key`, given as hex or nsec
+    pub async fn connect(secret_key: &str, relays: &[String]) -> anyhow::Result<Self> {
+        let keys = Keys::parse(secret_key).context("Invalid nostr key")?;
+        let target =
+            Nip19Profile::new(keys.public_key(), relays.iter().map(String::as_str))?.to_bech32()?;
+
+        let client = Client::new(keys.clone());
+        for relay in relays {
+            client
+                .add_relay(relay.as_str())
+                .await
+                .with_context(|| format!("Invalid nostr relay {}", relay))?;
+        }
+        client.connect().await;
+
+        tracing::info!(
+            "Accepting payments over nostr for {} on {} relays",
+            keys.public_key(),
+            relays.len()
+        );
+
+        Ok(Self {
+            client,
+            keys,
+            target,
+        })
+    }
+
+    /// Transport advertised in payment requests, asking for NIP-17 direct messages
+    pub fn transport(&self) -> Transport {
+        Transport {
+            _type: TransportType::Nostr,
+            target: self.target.clone(),
+            tags: Some(vec![vec!["n".to_string(), "17".to_string()]]),
+        }
+    }
+
+    /// Receive payloads sent to the POS key until the relay pool shuts down, spawning
+    /// `on_payload` for each so a slow payment does not hold up the others
+    pub async fn listen<F, Fut>(self: Arc<Self>, on_payload: F)
+    where
+        F: Fn(PaymentRequestPayload) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let filter = Filter::new()
+            .pubkey(self.keys.public_key())
+            .kind(Kind::GiftWrap);
+
+        if let Err(e) = self.client.subscribe(vec![filter], None).await {
+            tracing::error!("Could not subscribe to nostr payments: {}", e);
+            return;
+        }
+
+        let mut notifications = self.client.notifications();
+
+        loop {
+            let event = match notifications.recv().await {
+                Ok(RelayPoolNotification::Event { event, .. }) => event,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Missed {} nostr notifications", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if event.kind != Kind::GiftWrap {
+                continue;
+            }
+
+            let payload = match self.client.unwrap_gift_wrap(&event).await {
+                Ok(UnwrappedGift { rumor, .. }) => {
+                    serde_json::from_str::<PaymentRequestPayload>(&rumor.content)
+                }
+                Err(e) => {
+                    tracing::debug!("Could not unwrap nostr event {}: {}", event.id, e);
+                    continue;
+                }
+            };
+
+            match payload {
+                Ok(payload) => {
+                    tracing::debug!("Received nostr payment for quote {:?}", payload.id);
+                    tokio::spawn(on_payload(payload));
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Ignoring nostr message that is not a payment payload: {}",
+                        e
+                    )
+                }
+            }
+        }
+
+        tracing::warn!("Nostr payment listener stopped");
+    }
+}
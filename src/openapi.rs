@@ -0,0 +1,165 @@
+//! OpenAPI description of the HTTP API
+//!
+//! Served at `GET /v1/openapi.json` for generating typed clients, and browsable at
+//! `/v1/swagger-ui` with the `swagger-ui` feature. It covers the routes payers and
+//! checkout pages use: quote creation and status, payment requests, payments and
+//! health checks.
+
+use axum::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::pos_server;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Cashu POS",
+        description = "Point of sale backend receiving Cashu ecash payments. Errors are answered with an `ErrorBody` whose `code` is stable."
+    ),
+    paths(
+        pos_server::get_channel_quote,
+        pos_server::post_channel_quote,
+        pos_server::get_quote_state,
+        pos_server::get_quote_payment_request,
+        pos_server::post_receive_payment,
+        pos_server::get_health,
+        pos_server::get_ready,
+    ),
+    servers((url = "/v1")),
+    modifiers(&ApiKeyAuth),
+    tags(
+        (name = "quotes", description = "Creating quotes and following their state"),
+        (name = "payments", description = "Payloads sent by wallets to pay a quote"),
+        (name = "health", description = "Liveness and readiness checks"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Bearer `api_key` scheme of the merchant routes
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+pub async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI at `/swagger-ui` showing the spec from `/v1/openapi.json`
+#[cfg(feature = "swagger-ui")]
+pub fn swagger_ui<S>() -> axum::Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    utoipa_swagger_ui::SwaggerUi::new("/swagger-ui")
+        .config(utoipa_swagger_ui::Config::from("/v1/openapi.json"))
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::error::PosError;
+
+    fn spec() -> Value {
+        serde_json::from_str(&ApiDoc::openapi().to_json().unwrap()).unwrap()
+    }
+
+    /// `(name, in, required)` of the parameters of `method` on `path`
+    fn parameters(spec: &Value, path: &str, method: &str) -> Vec<(String, String, bool)> {
+        spec["paths"][path][method]["parameters"]
+            .as_array()
+            .unwrap_or_else(|| panic!("{} {} has no parameters", method, path))
+            .iter()
+            .map(|param| {
+                (
+                    param["name"].as_str().unwrap().to_string(),
+                    param["in"].as_str().unwrap().to_string(),
+                    param["required"].as_bool().unwrap_or(false),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn create_takes_optional_query_parameters() {
+        let params = parameters(&spec(), "/create", "get");
+
+        for name in ["amount", "unit", "fiat_amount", "memo", "mints", "amounts"] {
+            assert!(
+                params
+                    .iter()
+                    .any(|(param, location, required)| param == name
+                        && location == "query"
+                        && !required),
+                "{} missing from {:?}",
+                name,
+                params
+            );
+        }
+        assert!(params.iter().all(|(_, location, _)| location == "query"));
+    }
+
+    #[test]
+    fn check_takes_the_quote_id_in_the_path() {
+        let params = parameters(&spec(), "/check/{id}", "get");
+
+        assert!(params.contains(&("id".to_string(), "path".to_string(), true)));
+    }
+
+    #[test]
+    fn errors_are_described_by_error_body() {
+        let spec = spec();
+        assert!(spec["components"]["schemas"]["ErrorBody"].is_object());
+        assert!(spec["components"]["securitySchemes"]["api_key"].is_object());
+
+        let responses = &spec["paths"]["/payment"]["post"]["responses"];
+        for status in ["400", "404", "409", "410", "429", "500", "502"] {
+            assert_eq!(
+                responses[status]["content"]["application/json"]["schema"]["$ref"],
+                "#/components/schemas/ErrorBody",
+                "status {}",
+                status
+            );
+        }
+        assert!(responses["200"].is_object());
+
+        // Every status the payment route can answer with is documented
+        let id = uuid::Uuid::new_v4();
+        for err in [
+            PosError::QuoteNotFound(id),
+            PosError::QuoteExpired(id),
+            PosError::ProofVerificationError("invalid signature".to_string()),
+            PosError::ProofsAlreadySpent(id),
+            PosError::MintUnreachable("timed out".to_string()),
+            PosError::WalletError("localstore".to_string()),
+        ] {
+            assert!(
+                responses[err.status().as_str()].is_object(),
+                "{} answers with undocumented {}",
+                err.code(),
+                err.status()
+            );
+        }
+
+        let responses = &spec["paths"]["/check/{id}"]["get"]["responses"];
+        for status in ["400", "404", "500"] {
+            assert_eq!(
+                responses[status]["content"]["application/json"]["schema"]["$ref"],
+                "#/components/schemas/ErrorBody",
+                "status {}",
+                status
+            );
+        }
+    }
+}
@@ -1,262 +1,4702 @@
-use axum::routing::{get, post};
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, LINK};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
 use axum::{Router, extract::Json, extract::State};
 use cdk::amount::{Amount, SplitTarget};
-use cdk::nuts::{CurrencyUnit, PaymentRequest, PaymentRequestPayload, Transport, TransportType};
+use cdk::mint_url::MintUrl;
+use cdk::nuts::nut10::{Kind, Secret as Nut10Secret};
+use cdk::nuts::nut18::Nut10SecretRequest;
+use cdk::nuts::{
+    CurrencyUnit, Id, PaymentRequest, PaymentRequestPayload, Proofs, PublicKey, SecretKey,
+    State as ProofSpendState, Transport, TransportType,
+};
+use cdk::util::unix_time;
+use cdk::wallet::Wallet;
 use cdk::wallet::types::WalletKey;
+use cdk::wallet::{SendKind, SendMemo};
+use chrono::DateTime;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::CashuPos;
-use crate::db::Db;
+use crate::auth;
+use crate::capture::{Capture, CaptureLog};
+use crate::consolidation::{self, WalletConsolidation};
+use crate::db::{DbError, QuoteStore};
+#[cfg(feature = "openapi")]
+use crate::error::ErrorBody;
 use crate::error::PosError;
-use crate::types::{CashuPosInfo, QuoteInfo, QuoteState};
+use crate::exchange_rate::{self, ExchangeRate, FIAT_CURRENCIES};
+use crate::fees;
+use crate::htlc::HtlcPreimageKey;
+use crate::log_throttle::LogThrottle;
+#[cfg(feature = "notifications-nostr")]
+use crate::nostr::NostrTransport;
+#[cfg(feature = "openapi")]
+use crate::openapi;
+use crate::rate_limit::{self, RateLimiter};
+use crate::request_id;
+use crate::timings::PhaseTimer;
+#[cfg(feature = "openapi")]
+use crate::types::AmountValue;
+use crate::types::{
+    AmountEncoding, AmountFormat, BulkQuoteRequest, CashuPosInfo, ChannelQuoteRequest,
+    DisconnectPolicy, FiatPrice, MintListMode, OverpaymentPolicy, PosAmount, QuoteFilter,
+    QuoteInfo, QuoteState, QuoteTimeField, Receipt, ReceivedPayment, ReceivedProof, SentToken,
+    Sweep, Withdrawal, deserialize_amount, deserialize_optional_amount, parse_amount,
+    parse_unit_lenient, receipt_date, serialize_amount, serialize_optional_amount, unit_decimals,
+};
+use crate::validation::{self, ShadowRejections, ValidationRule};
+#[cfg(feature = "notifications-webhook")]
+use crate::webhook::{QuotePaidEvent, WebhookSender};
 
 /// Cashu Pos State
 #[derive(Clone)]
 pub struct CashuPosState {
     node: Arc<CashuPos>,
     payment_url: String,
-    db: Db,
-    cashu_pos_info: CashuPosInfo,
+    db: Arc<dyn QuoteStore>,
+    /// Replaced as a whole on config reload, see [`ReloadHandle`]
+    cashu_pos_info: Arc<RwLock<Arc<CashuPosInfo>>>,
+    log_throttle: Arc<LogThrottle>,
+    /// Would-be rejections of the rules in `shadow_mode`
+    shadow_rejections: Arc<ShadowRejections>,
+    capture: Arc<CaptureLog>,
+    #[cfg(feature = "notifications-webhook")]
+    webhooks: WebhookSender,
+    quote_updates: broadcast::Sender<QuoteStateResponse>,
+    #[cfg(feature = "notifications-nostr")]
+    nostr: Option<Arc<NostrTransport>>,
+    /// Key payment requests ask proofs to be locked to, used to sign for them on receive
+    p2pk_key: Option<SecretKey>,
+    /// Key HTLC preimages of quotes are stored encrypted with
+    htlc_preimage_key: Option<HtlcPreimageKey>,
+    /// Prices quotes given in fiat, unset when no provider is configured
+    exchange_rate: Option<Arc<dyn ExchangeRate>>,
 }
 
+impl CashuPosState {
+    /// Current settings, a snapshot a concurrent reload doesn't change
+    fn pos_info(&self) -> Arc<CashuPosInfo> {
+        Arc::clone(
+            &self
+                .cashu_pos_info
+                .read()
+                .unwrap_or_else(|e| e.into_inner()),
+        )
+    }
+}
+
+/// Applies configuration changes to a running router
+///
+/// Only settings that are safe to change between requests are taken from a new config,
+/// see [`ReloadHandle::reload`].
+#[derive(Clone)]
+pub struct ReloadHandle {
+    pos_info: Arc<RwLock<Arc<CashuPosInfo>>>,
+    create_limiter: Arc<RateLimiter>,
+    payment_limiter: Arc<RateLimiter>,
+}
+
+impl ReloadHandle {
+    /// Take the accepted mints, mint list length, memo length, quote expiry, overpayment
+    /// handling, webhook URL and rate limits from `new`, returning the names of those
+    /// that changed
+    ///
+    /// Requests already running finish with the settings they started with.
+    pub fn reload(&self, new: &CashuPosInfo) -> Vec<&'static str> {
+        let mut pos_info = self.pos_info.write().unwrap_or_else(|e| e.into_inner());
+        let mut updated = CashuPosInfo::clone(&pos_info);
+        let mut changed = Vec::new();
+
+        macro_rules! reload_field {
+            ($($field:ident).+, $name:literal) => {
+                if updated.$($field).+ != new.$($field).+ {
+                    updated.$($field).+ = new.$($field).+.clone();
+                    changed.push($name);
+                }
+            };
+        }
+
+        reload_field!(accepted_mints, "accepted_mints");
+        reload_field!(max_mints_per_request, "max_mints_per_request");
+        reload_field!(max_memo_length, "max_memo_length");
+        reload_field!(quote_expiry_seconds, "quote_expiry_seconds");
+        reload_field!(overpayment_policy, "overpayment_policy");
+        reload_field!(overpayment_tolerance, "overpayment_tolerance");
+        reload_field!(webhook_url, "webhook_url");
+        reload_field!(rate_limit.create_per_minute, "rate_limit.create_per_minute");
+        reload_field!(
+            rate_limit.payment_per_minute,
+            "rate_limit.payment_per_minute"
+        );
+
+        self.create_limiter
+            .set_per_minute(updated.rate_limit.create_per_minute);
+        self.payment_limiter
+            .set_per_minute(updated.rate_limit.payment_per_minute);
+
+        *pos_info = Arc::new(updated);
+
+        changed
+    }
+}
+
+/// Path prefix of the current API version
+pub const API_PREFIX: &str = "/v1";
+
+/// Quote updates buffered for slow WebSocket subscribers before they start missing some
+const QUOTE_UPDATES_CAPACITY: usize = 256;
+
+/// Build the POS router storing quotes in `db`, e.g. a [`crate::db::Db`]
 pub async fn create_cashu_pos_router(
     node: Arc<CashuPos>,
     pos_info: CashuPosInfo,
     payment_url: String,
-    db: Db,
+    db: Arc<dyn QuoteStore>,
 ) -> anyhow::Result<Router> {
+    let (router, _) = create_reloadable_cashu_pos_router(node, pos_info, payment_url, db).await?;
+
+    Ok(router)
+}
+
+/// Build the POS router like [`create_cashu_pos_router`], with a handle to change its
+/// settings while it runs
+pub async fn create_reloadable_cashu_pos_router(
+    node: Arc<CashuPos>,
+    pos_info: CashuPosInfo,
+    payment_url: String,
+    db: Arc<dyn QuoteStore>,
+) -> anyhow::Result<(Router, ReloadHandle)> {
+    #[cfg(feature = "notifications-nostr")]
+    let nostr = match (&pos_info.nostr_key, pos_info.nostr_relays.is_empty()) {
+        (Some(key), false) => Some(Arc::new(
+            NostrTransport::connect(key, &pos_info.nostr_relays).await?,
+        )),
+        _ => None,
+    };
+
+    #[cfg(not(feature = "notifications-nostr"))]
+    if pos_info.nostr_key.is_some() {
+        anyhow::bail!("nostr_key needs the notifications-nostr feature");
+    }
+
+    #[cfg(not(feature = "notifications-webhook"))]
+    if pos_info.webhook_url.is_some() {
+        anyhow::bail!("webhook_url needs the notifications-webhook feature");
+    }
+
+    let p2pk_key = pos_info
+        .p2pk_key
+        .as_deref()
+        .map(SecretKey::from_hex)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid P2PK key: {}", e))?;
+
+    if let Some(key) = &p2pk_key {
+        tracing::info!("Payment requests lock proofs to {}", key.public_key());
+    }
+
+    let htlc_preimage_key = pos_info
+        .htlc_preimage_key
+        .as_deref()
+        .map(HtlcPreimageKey::from_hex)
+        .transpose()?;
+
+    let exchange_rate = exchange_rate::from_settings(&pos_info.exchange_rate)?;
+
     let state = CashuPosState {
         node,
-        cashu_pos_info: pos_info,
-        payment_url,
+        log_throttle: LogThrottle::spawn(pos_info.log_throttle),
+        shadow_rejections: Arc::new(ShadowRejections::default()),
+        capture: Arc::new(CaptureLog::new(pos_info.debug_capture)),
+        #[cfg(feature = "notifications-webhook")]
+        webhooks: WebhookSender::new(),
+        quote_updates: broadcast::channel(QUOTE_UPDATES_CAPACITY).0,
+        cashu_pos_info: Arc::new(RwLock::new(Arc::new(pos_info))),
+        payment_url: versioned_payment_url(&payment_url)?,
         db,
+        #[cfg(feature = "notifications-nostr")]
+        nostr,
+        p2pk_key,
+        htlc_preimage_key,
+        exchange_rate,
+    };
+
+    #[cfg(feature = "notifications-nostr")]
+    if let Some(nostr) = &state.nostr {
+        let listener_state = state.clone();
+        tokio::spawn(
+            Arc::clone(nostr)
+                .listen(move |payload| handle_nostr_payment(listener_state.clone(), payload)),
+        );
+    }
+
+    let sandbox = state.pos_info().sandbox;
+    let debug_capture = state.pos_info().debug_capture.enabled;
+    let wallet_routes = state.pos_info().wallet_routes;
+    let legacy_routes = state.pos_info().legacy_routes;
+
+    let rate_limit = state.pos_info().rate_limit;
+    let payment_limiter = Arc::new(RateLimiter::new(rate_limit.payment_per_minute, &rate_limit));
+    let create_limiter = Arc::new(RateLimiter::new(rate_limit.create_per_minute, &rate_limit));
+
+    let reload = ReloadHandle {
+        pos_info: Arc::clone(&state.cashu_pos_info),
+        create_limiter: Arc::clone(&create_limiter),
+        payment_limiter: Arc::clone(&payment_limiter),
     };
 
-    let router = Router::new()
-        .route("/create", get(get_channel_quote))
-        .route("/payment", post(post_receive_payment))
+    // Some wallets PUT the payload or append a slash to the transport target
+    let payment_routes = rate_limited(
+        Router::new()
+            .route(
+                "/payment",
+                post(post_receive_payment).put(post_receive_payment),
+            )
+            .route(
+                "/payment/",
+                post(post_receive_payment).put(post_receive_payment),
+            ),
+        payment_limiter,
+    );
+
+    let create_routes = rate_limited(
+        Router::new()
+            .route("/create", get(get_channel_quote).post(post_channel_quote))
+            .route("/quotes/bulk", post(post_bulk_quotes)),
+        create_limiter,
+    );
+
+    // Routes payers and monitoring reach without credentials
+    let public = Router::new()
+        .merge(payment_routes)
+        .route("/health", get(get_health))
+        .route("/ready", get(get_ready))
+        .route("/ws", get(get_ws))
         .route("/check/{id}", get(get_quote_state))
+        .route("/quote/{id}/request", get(get_quote_payment_request));
+
+    #[cfg(feature = "openapi")]
+    let public = public.route("/openapi.json", get(openapi::get_openapi));
+
+    #[cfg(feature = "swagger-ui")]
+    let public = public.merge(openapi::swagger_ui());
+
+    let public = match sandbox {
+        true => public.route("/payment/simulate", post(post_simulate_payment)),
+        false => public,
+    };
+
+    // Merchant routes, behind the API key when one is configured
+    let mut protected = Router::new()
+        .merge(create_routes)
+        .route("/fees", get(get_fee_estimate))
+        .route("/quote/{id}", delete(cancel_quote))
+        .route("/quote/{id}/cancel", post(cancel_quote))
+        .route(
+            "/quote/by-reference/{reference}",
+            get(get_quote_by_reference),
+        );
+
+    // Routes listing the books, showing balances or moving funds, only served behind a key
+    let mut admin = Router::new()
+        .route("/balance", get(get_balances))
+        .route("/balance/{mint}", get(get_balance))
+        .route("/quotes", get(get_quotes))
+        .route("/quote/{id}/payment", get(get_quote_payments))
+        .route("/admin/reconcile", post(post_reconcile))
+        .route("/admin/consolidate", post(post_consolidate))
+        .route("/admin/sweeps", get(get_sweeps))
+        .route("/admin/shadow", get(get_shadow_rejections));
+
+    if debug_capture {
+        admin = admin.route("/admin/captures", get(get_captures));
+    }
+
+    if wallet_routes {
+        admin = admin
+            .route("/withdraw", post(post_withdraw))
+            .route("/send", get(get_sent_tokens).post(post_send));
+    }
+
+    match state.pos_info().api_key.clone() {
+        Some(api_key) => {
+            protected = protected.merge(admin).layer(middleware::from_fn_with_state(
+                Arc::new(api_key),
+                auth::require_api_key,
+            ));
+        }
+        None => tracing::warn!(
+            "No api_key set, merchant routes are unauthenticated and the listing, balance, wallet and admin routes are not served"
+        ),
+    }
+
+    let api = public.merge(protected);
+    let mut router = Router::new().nest(API_PREFIX, api.clone());
+
+    if legacy_routes {
+        router = router.merge(api.layer(middleware::from_fn(deprecated_route)));
+    }
+
+    // Outermost so rejected requests get an id too
+    let router = router
+        .layer(middleware::from_fn(request_id::trace_request))
         .with_state(state);
 
-    Ok(router)
+    Ok((router, reload))
+}
+
+/// `payment_url` pointing at the payment route of the current API version
+///
+/// A URL of the unversioned `/payment` route gets the version inserted before it, any other
+/// URL is taken as the base the API is served under.
+fn versioned_payment_url(payment_url: &str) -> anyhow::Result<String> {
+    let mut url = url::Url::parse(payment_url)
+        .map_err(|e| anyhow::anyhow!("Invalid payment_url {}: {}", payment_url, e))?;
+
+    let path = url.path().trim_end_matches('/');
+    let base = path.strip_suffix("/payment").unwrap_or(path);
+    let base = base.strip_suffix(API_PREFIX).unwrap_or(base);
+    url.set_path(&format!("{}{}/payment", base, API_PREFIX));
+
+    Ok(url.to_string())
+}
+
+/// Mark responses of the unversioned routes as deprecated, pointing at their `/v1` path
+async fn deprecated_route(request: axum::extract::Request, next: middleware::Next) -> Response {
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        API_PREFIX,
+        request.uri().path()
+    );
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(LINK, link);
+    }
+
+    response
+}
+
+/// Limit `routes` with `limiter`, installed even without a limit so a reload can add one
+fn rate_limited(routes: Router<CashuPosState>, limiter: Arc<RateLimiter>) -> Router<CashuPosState> {
+    routes.route_layer(middleware::from_fn_with_state(limiter, rate_limit::limit))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
 pub struct ChannelQuoteResponse {
     checking_id: Uuid,
     payment_request: String,
+    #[serde(flatten)]
+    amount: PosAmount,
+    amount_display: String,
+    /// Fiat price the amount was converted from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fiat: Option<FiatPrice>,
+    /// Amounts in other units the quote may be paid in instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alternative_amounts: Option<Vec<PosAmount>>,
+    /// Milliseconds spent in each phase, only present in diagnostics mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings_ms: Option<BTreeMap<String, f64>>,
 }
 
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/create",
+        tag = "quotes",
+        security(("api_key" = [])),
+        params(
+            ("amount" = Option<String>, Query, description = "Amount as an integer in minor units or a decimal in major units, required unless `fiat_amount` is given"),
+            ("amount_format" = Option<String>, Query, description = "`major` or `minor` to override how `amount` is read"),
+            ("fiat_amount" = Option<String>, Query, description = "Decimal price converted to `unit` at the current exchange rate"),
+            ("fiat_currency" = Option<String>, Query, description = "`usd` or `eur`, the currency of `fiat_amount`"),
+            ("unit" = Option<String>, Query, description = "Currency unit of the quote, `sat` if unset"),
+            ("amounts" = Option<String>, Query, description = "`string` to write amounts as decimal strings, `number` otherwise"),
+            ("fee_inclusive" = Option<bool>, Query, description = "Add the estimated input fees of the payment to the amount"),
+            ("preimage" = Option<String>, Query, description = "32 byte hex preimage for redeeming HTLC-locked proofs"),
+            ("memo" = Option<String>, Query, description = "Note shown to the payer"),
+            ("webhook_url" = Option<String>, Query, description = "URL notified once this quote is paid"),
+            ("reference" = Option<String>, Query, description = "Caller's own identifier for the quote"),
+            ("mints" = Option<String>, Query, description = "Comma separated subset of the accepted mints the quote may be paid from"),
+            ("multi_unit" = Option<bool>, Query, description = "Also accept payment in every other accepted unit"),
+        ),
+        responses(
+            (status = 200, description = "Quote created", body = ChannelQuoteResponse),
+            (status = 400, description = "Missing or invalid parameter, or unsupported unit or mint", body = ErrorBody),
+            (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+            (status = 429, description = "Too many quotes created, see `Retry-After`", body = ErrorBody),
+            (status = 500, description = "The quote could not be stored", body = ErrorBody),
+            (status = 503, description = "No exchange rate for `fiat_amount`", body = ErrorBody),
+        )
+    )
+)]
 pub async fn get_channel_quote(
     State(state): State<CashuPosState>,
     axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
-) -> Result<Json<ChannelQuoteResponse>, PosError> {
-    // Extract amount from query parameters
-    let amount = params
-        .get("amount")
-        .ok_or_else(|| PosError::InternalError("Missing amount parameter".to_string()))?
-        .parse::<u64>()
-        .map_err(|_| PosError::InternalError("Invalid amount format".to_string()))?;
+) -> Result<AmountJson<ChannelQuoteResponse>, PosError> {
+    let timer = PhaseTimer::new(state.pos_info().diagnostics);
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
 
     // Extract currency unit from query parameters, default to SAT if not provided
-    let unit = match params.get("unit") {
-        Some(unit_str) => {
-            // Check if the unit is supported (currently only SAT and USD)
-            let allowed_units = vec![CurrencyUnit::Sat, CurrencyUnit::Usd];
+    let unit = parse_unit(&state, params.get("unit"))?;
 
-            let unit = CurrencyUnit::from_str(unit_str).map_err(|_| {
-                PosError::InternalError(format!("Invalid currency unit format: {}", unit_str))
-            })?;
+    let amount_format = params
+        .get("amount_format")
+        .map(|f| AmountFormat::from_str(f))
+        .transpose()?;
 
-            // Check if the unit is supported
-            if !allowed_units.contains(&unit) {
-                return Err(PosError::UnsupportedCurrencyUnit {
-                    given: unit_str.to_string(),
-                    allowed: allowed_units,
-                });
-            }
+    // Extract amount from query parameters, in minor units of the currency, or convert the
+    // fiat price
+    let (amount, fiat) = match (params.get("amount"), params.get("fiat_amount")) {
+        (Some(amount), None) => (parse_amount(amount, &unit, amount_format)?, None),
+        (None, Some(fiat_amount)) => {
+            let (amount, fiat) =
+                fiat_quote_amount(&state, fiat_amount, params.get("fiat_currency"), &unit).await?;
+            (amount, Some(fiat))
+        }
+        (Some(_), Some(_)) => {
+            return Err(PosError::InvalidAmount(
+                "Pass either amount or fiat_amount, not both".to_string(),
+            ));
+        }
+        (None, None) => {
+            return Err(PosError::MissingParameter("amount".to_string()));
+        }
+    };
 
-            unit
+    let request = NewQuote {
+        amount,
+        fiat,
+        unit,
+        fee_inclusive: params.get("fee_inclusive").map(|f| f.as_str()) == Some("true"),
+        htlc_preimage: params.get("preimage").cloned(),
+        memo: params.get("memo").cloned(),
+        webhook_url: params.get("webhook_url").cloned(),
+        reference: params.get("reference").cloned(),
+        mints: params.get("mints").map(|mints| {
+            mints
+                .split(',')
+                .map(|mint| mint.trim().to_string())
+                .collect()
+        }),
+        multi_unit: params.get("multi_unit").map(|m| m.as_str()) == Some("true"),
+    };
+
+    let response = create_quote(&state, request, timer).await?;
+
+    Ok(AmountJson(response, encoding))
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/create",
+        tag = "quotes",
+        security(("api_key" = [])),
+        params(
+            ("amounts" = Option<String>, Query, description = "`string` to write amounts as decimal strings, `number` otherwise"),
+        ),
+        request_body = ChannelQuoteRequest,
+        responses(
+            (status = 200, description = "Quote created", body = ChannelQuoteResponse),
+            (status = 400, description = "Missing or invalid field, or unsupported unit or mint", body = ErrorBody),
+            (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+            (status = 429, description = "Too many quotes created, see `Retry-After`", body = ErrorBody),
+            (status = 500, description = "The quote could not be stored", body = ErrorBody),
+            (status = 503, description = "No exchange rate for `fiat_amount`", body = ErrorBody),
+        )
+    )
+)]
+pub async fn post_channel_quote(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+    Json(request): Json<ChannelQuoteRequest>,
+) -> Result<AmountJson<ChannelQuoteResponse>, PosError> {
+    let timer = PhaseTimer::new(state.pos_info().diagnostics);
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let unit = parse_unit(&state, request.unit.as_ref())?;
+
+    let (amount, fiat) = match (request.amount, &request.fiat_amount) {
+        (Some(amount), None) => (amount, None),
+        (None, Some(fiat_amount)) => {
+            let (amount, fiat) =
+                fiat_quote_amount(&state, fiat_amount, request.fiat_currency.as_ref(), &unit)
+                    .await?;
+            (amount, Some(fiat))
         }
-        None => CurrencyUnit::Sat,
+        (Some(_), Some(_)) => {
+            return Err(PosError::InvalidAmount(
+                "Pass either amount or fiat_amount, not both".to_string(),
+            ));
+        }
+        (None, None) => return Err(PosError::MissingParameter("amount".to_string())),
+    };
+
+    let request = NewQuote {
+        amount,
+        fiat,
+        unit,
+        fee_inclusive: request.fee_inclusive,
+        htlc_preimage: request.preimage,
+        memo: request.memo,
+        webhook_url: request.webhook_url,
+        reference: request.reference,
+        mints: request.mints,
+        multi_unit: request.multi_unit,
     };
 
+    let response = create_quote(&state, request, timer).await?;
+
+    Ok(AmountJson(response, encoding))
+}
+
+/// JSON response body whose amount fields, those serialized with [`serialize_amount`],
+/// follow the requested encoding
+pub struct AmountJson<T>(pub T, pub AmountEncoding);
+
+impl<T: Serialize> IntoResponse for AmountJson<T> {
+    fn into_response(self) -> Response {
+        let AmountJson(body, encoding) = self;
+
+        // The body is serialized right here, within the scope of its encoding
+        encoding.scope(|| Json(body).into_response())
+    }
+}
+
+/// Encoding asked for by the `amounts` query parameter, the configured default otherwise
+fn amount_encoding(
+    state: &CashuPosState,
+    param: Option<&String>,
+) -> Result<AmountEncoding, PosError> {
+    param.map_or(Ok(state.pos_info().amount_encoding), |encoding| {
+        AmountEncoding::from_str(encoding)
+    })
+}
+
+/// Parsed quote creation request shared by the GET and POST handlers
+struct NewQuote {
+    /// Amount in minor units of `unit`
+    amount: u64,
+    /// Fiat price `amount` was converted from
+    fiat: Option<FiatPrice>,
+    unit: CurrencyUnit,
+    fee_inclusive: bool,
+    htlc_preimage: Option<String>,
+    memo: Option<String>,
+    webhook_url: Option<String>,
+    reference: Option<String>,
+    /// Mint urls the quote is restricted to
+    mints: Option<Vec<String>>,
+    multi_unit: bool,
+}
+
+/// Longest external reference accepted, in characters
+const MAX_REFERENCE_LENGTH: usize = 128;
+
+/// Parse the mints a quote is restricted to, each must be an accepted mint
+fn quote_mints(state: &CashuPosState, mints: &[String]) -> Result<Vec<MintUrl>, PosError> {
+    let mut quote_mints: Vec<MintUrl> = Vec::with_capacity(mints.len());
+
+    for mint in mints {
+        let mint = MintUrl::from_str(mint).map_err(|e| {
+            PosError::InvalidQueryParameter(format!("Invalid mint url {}: {}", mint, e))
+        })?;
+
+        if !state.pos_info().accepted_mints.contains(&mint) {
+            return Err(PosError::UnsupportedMint(mint));
+        }
+
+        if !quote_mints.contains(&mint) {
+            quote_mints.push(mint);
+        }
+    }
+
+    if quote_mints.is_empty() {
+        return Err(PosError::InvalidQueryParameter(
+            "mints must list at least one mint".to_string(),
+        ));
+    }
+
+    Ok(quote_mints)
+}
+
+async fn create_quote(
+    state: &CashuPosState,
+    request: NewQuote,
+    mut timer: PhaseTimer,
+) -> Result<ChannelQuoteResponse, PosError> {
+    let NewQuote {
+        amount,
+        fiat,
+        unit,
+        fee_inclusive,
+        htlc_preimage,
+        memo,
+        webhook_url,
+        reference,
+        mints,
+        multi_unit,
+    } = request;
+    let mints = mints.map(|mints| quote_mints(state, &mints)).transpose()?;
+    timer.mark("parse");
+
+    let net_amount = fee_inclusive.then_some(amount);
+    let amount = quote_amount(state, amount, &unit, fee_inclusive, mints.as_deref()).await?;
+    if fee_inclusive {
+        timer.mark("fee_estimate");
+    }
+
     tracing::debug!(
         "Received channel quote request with amount: {} {}",
         amount,
         unit
     );
 
-    let payment_id = Uuid::new_v4();
+    // Only the encrypted preimage is stored
+    let htlc_preimage = htlc_preimage
+        .map(|preimage| {
+            validate_htlc_preimage(&preimage)?;
 
-    let transport = Transport::builder()
-        .transport_type(TransportType::HttpPost)
-        .target(state.payment_url)
-        .build()
-        .map_err(|e| {
-            tracing::error!("Failed to build transport: {}", e);
-            PosError::InternalError(format!("Failed to build transport: {}", e))
-        })?;
+            let key = state.htlc_preimage_key.as_ref().ok_or_else(|| {
+                PosError::InvalidQueryParameter(
+                    "preimage needs an htlc_preimage_key to store it encrypted".to_string(),
+                )
+            })?;
+            key.encrypt(&preimage)
+        })
+        .transpose()?;
 
-    let payment_request = PaymentRequest::builder()
-        .payment_id(payment_id)
-        .amount(amount)
-        .unit(unit.clone())
-        .single_use(true)
-        .mints(state.cashu_pos_info.accepted_mints)
-        .add_transport(transport)
-        .build();
+    validate_memo(state, memo.as_ref())?;
+
+    if let Some(url) = webhook_url
+        .as_ref()
+        .filter(|url| !url.starts_with("http://") && !url.starts_with("https://"))
+    {
+        return Err(PosError::InvalidWebhookUrl(url.clone()));
+    }
+
+    #[cfg(not(feature = "notifications-webhook"))]
+    if webhook_url.is_some() {
+        return Err(PosError::InvalidQueryParameter(
+            "webhook_url needs the notifications-webhook feature".to_string(),
+        ));
+    }
+
+    if let Some(reference) = reference
+        .as_ref()
+        .filter(|r| r.is_empty() || r.chars().count() > MAX_REFERENCE_LENGTH)
+    {
+        return Err(PosError::InvalidQueryParameter(format!(
+            "reference must be 1-{} characters, got {}",
+            MAX_REFERENCE_LENGTH,
+            reference.chars().count()
+        )));
+    }
+    ensure_quote_capacity(state, 1).await?;
+    timer.mark("validate");
+
+    // The amounts in other units are fixed now, so the payer knows what each unit costs
+    let alternative_amounts = match multi_unit {
+        true => {
+            let base = PosAmount::new(amount, unit.clone());
+            let mut alternatives = Vec::new();
+
+            for other in state.pos_info().accepted_units.iter() {
+                if *other != unit {
+                    let value = convert_amount(state, &base, other).await?;
+                    alternatives.push(PosAmount::new(value, other.clone()));
+                }
+            }
+
+            timer.mark("convert");
+            Some(alternatives)
+        }
+        false => None,
+    };
 
     let quote = QuoteInfo {
-        id: payment_id,
+        id: Uuid::new_v4(),
         state: QuoteState::Unpaid,
-        amount,
-        unit,
+        amount: PosAmount::new(amount, unit),
+        tag: None,
+        htlc_preimage,
+        payment_fingerprint: None,
+        expires_at: quote_expires_at(state, None),
+        memo,
+        receipt: None,
+        paid_amount: None,
+        created_at: Some(unix_time()),
+        paid_at: None,
+        webhook_url,
+        reference,
+        mints,
+        fiat,
+        alternative_amounts,
+        net_amount,
     };
 
-    state.db.add_quote(&quote).map_err(|e| {
+    let payment_request = build_payment_request(state, &quote, MintListMode::Compact)?;
+    timer.mark("build_request");
+
+    state.db.add_quote(&quote).await.map_err(|e| {
         tracing::error!("Failed to add quote to database: {}", e);
         PosError::DatabaseError(e.to_string())
     })?;
+    timer.mark("db_write");
 
-    tracing::info!("Created new channel quote: {}", payment_id);
+    tracing::info!("Created new channel quote: {}", quote.id);
 
-    Ok(Json(ChannelQuoteResponse {
-        checking_id: payment_id,
-        payment_request: payment_request.to_string(),
-    }))
-}
+    let timings_ms = timer.finish();
+    if let Some(timings) = &timings_ms {
+        tracing::info!(timings_ms = ?timings, "Quote creation timings for {}", quote.id);
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct QuoteStateResponse {
-    pub id: Uuid,
-    pub state: QuoteState,
+    Ok(ChannelQuoteResponse {
+        checking_id: quote.id,
+        payment_request,
+        amount_display: quote.amount.to_string(),
+        amount: quote.amount,
+        fiat: quote.fiat,
+        alternative_amounts: quote.alternative_amounts,
+        timings_ms,
+    })
 }
 
-pub async fn get_quote_state(
-    State(state): State<CashuPosState>,
-    axum::extract::Path(id): axum::extract::Path<String>,
-) -> Result<Json<QuoteStateResponse>, PosError> {
-    tracing::debug!("Received quote state request for ID: {}", id);
-
-    let id = Uuid::from_str(&id).map_err(|e| {
-        tracing::warn!("Invalid UUID format: {} - {}", id, e);
-        PosError::InvalidUuid(id.clone())
-    })?;
+/// Amount a new quote asks for, adding the estimated input fees of its payment at `mints`, or
+/// every accepted mint, when `fee_inclusive`
+async fn quote_amount(
+    state: &CashuPosState,
+    amount: u64,
+    unit: &CurrencyUnit,
+    fee_inclusive: bool,
+    mints: Option<&[MintUrl]>,
+) -> Result<u64, PosError> {
+    if amount == 0 {
+        return Err(PosError::InvalidAmount(
+            "Amount must be greater than zero".to_string(),
+        ));
+    }
 
-    let quote = state.db.get_quote(id).map_err(|e| {
-        tracing::warn!("Quote not found: {} - {}", id, e);
-        PosError::QuoteNotFound(id)
-    })?;
+    // Optionally ask the customer to cover the input fees of their payload
+    match fee_inclusive {
+        true => {
+            let pos_info = state.pos_info();
+            let mints = mints.unwrap_or(&pos_info.accepted_mints);
+            fee_inclusive_amount(state, mints, amount, unit).await
+        }
+        false => Ok(amount),
+    }
+}
 
-    let response = QuoteStateResponse {
-        id: quote.id,
-        state: quote.state,
-    };
+fn validate_memo(state: &CashuPosState, memo: Option<&String>) -> Result<(), PosError> {
+    // Counted in characters so non-ASCII memos get the same allowance
+    let max_memo_length = state.pos_info().max_memo_length;
 
-    tracing::debug!("Returning quote state for {}: {:?}", id, response);
-    Ok(Json(response))
+    match memo.map(|memo| memo.chars().count()) {
+        Some(length) if length > max_memo_length => Err(PosError::MemoTooLong {
+            length,
+            max: max_memo_length,
+        }),
+        _ => Ok(()),
+    }
 }
 
-pub async fn post_receive_payment(
-    State(state): State<CashuPosState>,
-    Json(payload): Json<PaymentRequestPayload>,
-) -> Result<(), PosError> {
-    tracing::debug!("Received payment for mint: {}", payload.mint);
+/// Fail unless `count` more quotes fit under `max_open_quotes`
+///
+/// Concurrent requests may both pass the check, so the limit can be exceeded by the
+/// quotes created at the same moment.
+async fn ensure_quote_capacity(state: &CashuPosState, count: usize) -> Result<(), PosError> {
+    let Some(max) = state.pos_info().max_open_quotes else {
+        return Ok(());
+    };
 
-    // Validate mint
-    if !state.cashu_pos_info.accepted_mints.contains(&payload.mint) {
-        return Err(PosError::UnsupportedMint(payload.mint.clone()));
+    let open = state
+        .db
+        .count_open_quotes(unix_time())
+        .await
+        .map_err(|e| PosError::DatabaseError(e.to_string()))?;
+
+    match open.saturating_add(count) > max {
+        true => Err(PosError::TooManyOpenQuotes { open, max }),
+        false => Ok(()),
     }
+}
 
-    // Validate payment ID
-    let id = payload.id.ok_or_else(|| {
-        tracing::warn!("Missing payment ID in request");
-        PosError::InvalidUuid("missing".to_string())
+/// Convert a fiat price to minor units of `unit` at the current exchange rate
+async fn fiat_quote_amount(
+    state: &CashuPosState,
+    fiat_amount: &str,
+    fiat_currency: Option<&String>,
+    unit: &CurrencyUnit,
+) -> Result<(u64, FiatPrice), PosError> {
+    let exchange_rate = state.exchange_rate.as_ref().ok_or_else(|| {
+        PosError::InvalidQueryParameter(
+            "fiat_amount needs an exchange_rate provider to be configured".to_string(),
+        )
     })?;
 
-    let id = Uuid::from_str(&id).map_err(|e| {
-        tracing::warn!("Invalid UUID format: {} - {}", id, e);
-        PosError::InvalidUuid(id.clone())
+    let currency = parse_unit_lenient(
+        fiat_currency.ok_or_else(|| {
+            PosError::InvalidQueryParameter(
+                "fiat_currency is required with fiat_amount".to_string(),
+            )
+        })?,
+        &FIAT_CURRENCIES,
+    )?;
+
+    let units_per_btc = bitcoin_units(unit).ok_or_else(|| {
+        PosError::InvalidQueryParameter(format!(
+            "Fiat prices can only be converted to sat or msat, not {}",
+            unit
+        ))
     })?;
 
-    // Get quote
-    let quote = state.db.get_quote(id).map_err(|e| {
-        tracing::warn!("Quote not found: {} - {}", id, e);
-        PosError::QuoteNotFound(id)
+    let fiat_minor = parse_amount(fiat_amount, &currency, Some(AmountFormat::Major))?;
+
+    let btc_price = exchange_rate.btc_price(&currency).await.map_err(|e| {
+        tracing::warn!("Could not get bitcoin price in {}: {}", currency, e);
+        PosError::ExchangeRateUnavailable(e.to_string())
     })?;
 
-    // Validate quote state
-    if quote.state != QuoteState::Unpaid {
-        tracing::warn!("Quote {} has invalid state: {:?}", id, quote.state);
-        return Err(PosError::InvalidQuoteState {
-            id,
-            state: quote.state,
-        });
+    // Rounded up so the payment never falls short of the fiat price
+    let fiat_major = fiat_minor as f64 / 10f64.powi(unit_decimals(&currency) as i32);
+    let amount = (fiat_major / btc_price * units_per_btc).ceil();
+
+    if !(1.0..u64::MAX as f64).contains(&amount) {
+        return Err(PosError::InvalidAmount(format!(
+            "{} {} converts to {} {}",
+            fiat_amount, currency, amount, unit
+        )));
     }
 
-    // Validate payment amount
-    let received_amount =
-        Amount::try_sum(payload.proofs.iter().map(|p| p.amount)).map_err(|e| {
-            tracing::warn!("Failed to sum proof amounts: {}", e);
-            PosError::InternalError("Failed to sum proof amounts".to_string())
-        })?;
+    Ok((
+        amount as u64,
+        FiatPrice {
+            amount: PosAmount::new(fiat_minor, currency),
+            btc_price,
+        },
+    ))
+}
 
-    if Amount::from(quote.amount) < received_amount {
-        tracing::warn!(
-            "Insufficient payment: expected {}, received {}",
-            quote.amount,
-            received_amount
-        );
-        return Err(PosError::InsufficientPayment {
-            expected: quote.amount,
-            received: received_amount.into(),
-        });
+/// Minor units of a bitcoin denominated unit in one bitcoin
+fn bitcoin_units(unit: &CurrencyUnit) -> Option<f64> {
+    match unit {
+        CurrencyUnit::Sat => Some(100_000_000.0),
+        CurrencyUnit::Msat => Some(100_000_000_000.0),
+        _ => None,
     }
+}
 
-    // Get wallet for the mint with the correct currency unit
-    let wallet = state
-        .node
-        .wallet
-        .get_wallet(&WalletKey::new(payload.mint.clone(), quote.unit.clone()))
-        .await
+/// Minor units of `unit` worth one bitcoin, asking the exchange rate for fiat units
+async fn units_per_btc(state: &CashuPosState, unit: &CurrencyUnit) -> Result<f64, PosError> {
+    if let Some(units) = bitcoin_units(unit) {
+        return Ok(units);
+    }
+
+    let exchange_rate = state
+        .exchange_rate
+        .as_ref()
+        .filter(|_| FIAT_CURRENCIES.contains(unit))
         .ok_or_else(|| {
-            let msg = format!(
-                "Wallet not created for {} with unit {:?}",
-                payload.mint, quote.unit
-            );
-            tracing::warn!("{}", msg);
-            PosError::WalletError(msg)
+            PosError::InvalidQueryParameter(format!(
+                "Converting to {} needs an exchange_rate provider to be configured",
+                unit
+            ))
         })?;
 
-    // Receive and verify proofs
-    let amount = wallet
-        .receive_proofs(payload.proofs, SplitTarget::default(), &[], &[])
-        .await
-        .map_err(|e| {
-            tracing::error!("Could not receive proofs for {}: {}", id, e);
-            PosError::ProofVerificationError(e.to_string())
-        })?;
+    let btc_price = exchange_rate.btc_price(unit).await.map_err(|e| {
+        tracing::warn!("Could not get bitcoin price in {}: {}", unit, e);
+        PosError::ExchangeRateUnavailable(e.to_string())
+    })?;
 
-    tracing::info!(
-        "Successfully received payment of {} {} for quote {}",
-        amount,
-        quote.unit,
-        id
-    );
+    Ok(btc_price * 10f64.powi(unit_decimals(unit) as i32))
+}
 
-    // Update quote state
-    let _quote = state
-        .db
-        .update_quote_state(id, QuoteState::Paid)
-        .map_err(|e| {
-            tracing::error!("Failed to update quote state: {}", e);
-            PosError::DatabaseError(e.to_string())
-        })?;
+/// `amount` in minor units of `to`, rounded up so a payment in `to` never falls short
+async fn convert_amount(
+    state: &CashuPosState,
+    amount: &PosAmount,
+    to: &CurrencyUnit,
+) -> Result<u64, PosError> {
+    if amount.unit == *to {
+        return Ok(amount.value);
+    }
 
-    tracing::info!("Payment processing completed for quote {}", id);
-    Ok(())
+    let from_per_btc = units_per_btc(state, &amount.unit).await?;
+    let to_per_btc = units_per_btc(state, to).await?;
+    let converted = (amount.value as f64 / from_per_btc * to_per_btc).ceil();
+
+    match (1.0..u64::MAX as f64).contains(&converted) {
+        true => Ok(converted as u64),
+        false => Err(PosError::InvalidAmount(format!(
+            "{} converts to {} {}",
+            amount, converted, to
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MintFeeEstimate {
+    pub mint: MintUrl,
+    pub input_fee_ppk: u64,
+    pub estimated_proofs: u64,
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub estimated_fee: u64,
+    /// Amount to request so the merchant receives the full amount after fees
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub gross_amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeEstimateResponse {
+    #[serde(flatten)]
+    pub amount: PosAmount,
+    pub assumptions: String,
+    pub estimates: Vec<MintFeeEstimate>,
+}
+
+pub async fn get_fee_estimate(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<FeeEstimateResponse>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+    let unit = parse_unit(&state, params.get("unit"))?;
+
+    let amount_format = params
+        .get("amount_format")
+        .map(|f| AmountFormat::from_str(f))
+        .transpose()?;
+
+    let amount = parse_amount(
+        params
+            .get("amount")
+            .ok_or_else(|| PosError::MissingParameter("amount".to_string()))?,
+        &unit,
+        amount_format,
+    )?;
+
+    let mints = match params.get("mint") {
+        Some(mint) => {
+            let mint = MintUrl::from_str(mint).map_err(|_| {
+                PosError::InvalidQueryParameter(format!("Invalid mint url: {}", mint))
+            })?;
+
+            if !state.pos_info().accepted_mints.contains(&mint) {
+                return Err(PosError::UnsupportedMint(mint));
+            }
+
+            vec![mint]
+        }
+        None => state.pos_info().accepted_mints.clone(),
+    };
+
+    let mut estimates = Vec::with_capacity(mints.len());
+
+    for mint in mints {
+        let input_fee_ppk = mint_input_fee_ppk(&state, &mint, &unit).await?;
+        let gross_amount = fees::gross_amount(amount, input_fee_ppk);
+        let estimated_proofs = fees::estimated_proof_count(gross_amount);
+
+        estimates.push(MintFeeEstimate {
+            mint,
+            input_fee_ppk,
+            estimated_proofs,
+            estimated_fee: fees::input_fee(estimated_proofs, input_fee_ppk),
+            gross_amount,
+        });
+    }
+
+    let response = FeeEstimateResponse {
+        amount: PosAmount::new(amount, unit),
+        assumptions: fees::FEE_ESTIMATE_ASSUMPTIONS.to_string(),
+        estimates,
+    };
+
+    Ok(AmountJson(response, encoding))
+}
+
+/// How long `/ready` waits for each mint to answer
+const MINT_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    /// Serving, but some accepted mints are unreachable
+    Degraded,
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct MintHealth {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub mint: MintUrl,
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct HealthResponse {
+    pub status: HealthStatus,
+    pub database: HealthStatus,
+    /// Only reported by `/ready`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mints: Option<Vec<MintHealth>>,
+}
+
+impl IntoResponse for HealthResponse {
+    fn into_response(self) -> Response {
+        let status = match self.status {
+            HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            HealthStatus::Ok | HealthStatus::Degraded => StatusCode::OK,
+        };
+
+        (status, Json(self)).into_response()
+    }
+}
+
+async fn database_health(state: &CashuPosState) -> HealthStatus {
+    match state.db.check().await {
+        Ok(()) => HealthStatus::Ok,
+        Err(e) => {
+            tracing::error!("Health check could not read the database: {}", e);
+            HealthStatus::Unavailable
+        }
+    }
+}
+
+/// Liveness, only the database is checked so unreachable mints don't fail it
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/health",
+        tag = "health",
+        responses(
+            (status = 200, description = "The database can be read", body = HealthResponse),
+            (status = 503, description = "The database cannot be read", body = HealthResponse),
+        )
+    )
+)]
+pub async fn get_health(State(state): State<CashuPosState>) -> HealthResponse {
+    let database = database_health(&state).await;
+
+    HealthResponse {
+        status: database,
+        database,
+        mints: None,
+    }
+}
+
+/// Readiness, checking the database and every accepted mint
+///
+/// Unavailable if the database fails or no mint answers, degraded if only some mints answer.
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/ready",
+        tag = "health",
+        responses(
+            (status = 200, description = "Ready, possibly with some mints unreachable", body = HealthResponse),
+            (status = 503, description = "The database fails or no mint answers", body = HealthResponse),
+        )
+    )
+)]
+pub async fn get_ready(State(state): State<CashuPosState>) -> HealthResponse {
+    let database = database_health(&state).await;
+
+    let mints =
+        futures::future::join_all(state.pos_info().accepted_mints.iter().map(|mint| async {
+            let result = state.node.check_mint(mint, MINT_CHECK_TIMEOUT).await;
+
+            if let Err(e) = &result {
+                tracing::warn!("Readiness check of mint {} failed: {}", mint, e);
+            }
+
+            MintHealth {
+                mint: mint.clone(),
+                status: match result {
+                    Ok(()) => HealthStatus::Ok,
+                    Err(_) => HealthStatus::Unavailable,
+                },
+                error: result.err().map(|e| e.to_string()),
+            }
+        }))
+        .await;
+
+    let reachable = mints
+        .iter()
+        .filter(|mint| mint.status == HealthStatus::Ok)
+        .count();
+
+    let status = match (database, reachable) {
+        (HealthStatus::Unavailable, _) | (_, 0) => HealthStatus::Unavailable,
+        _ if reachable < mints.len() => HealthStatus::Degraded,
+        _ => HealthStatus::Ok,
+    };
+
+    HealthResponse {
+        status,
+        database,
+        mints: Some(mints),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletBalance {
+    pub mint: MintUrl,
+    pub unit: CurrencyUnit,
+    /// Unspent proofs held for the mint, in minor units of `unit`
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub balance: u64,
+}
+
+pub async fn get_balances(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<Vec<WalletBalance>>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let balances = state.node.balances().await.map_err(|e| {
+        tracing::warn!("Could not read wallet balances: {}", e);
+        PosError::WalletError(e.to_string())
+    })?;
+
+    let balances = balances
+        .into_iter()
+        .map(|(mint, unit, balance)| WalletBalance {
+            mint,
+            unit,
+            balance: balance.into(),
+        })
+        .collect();
+
+    Ok(AmountJson(balances, encoding))
+}
+
+/// Balance of one wallet, `mint` being the percent-encoded mint url
+pub async fn get_balance(
+    State(state): State<CashuPosState>,
+    axum::extract::Path(mint): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<WalletBalance>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+    let unit = parse_unit(&state, params.get("unit"))?;
+
+    let mint = MintUrl::from_str(&mint)
+        .map_err(|_| PosError::InvalidQueryParameter(format!("Invalid mint url: {}", mint)))?;
+
+    let balance = state
+        .node
+        .balance(&mint, &unit)
+        .await
+        .map_err(|e| {
+            tracing::warn!("Could not read balance for {} {}: {}", mint, unit, e);
+            PosError::WalletError(e.to_string())
+        })?
+        .ok_or_else(|| PosError::WalletNotFound {
+            mint: mint.clone(),
+            unit: unit.clone(),
+        })?;
+
+    Ok(AmountJson(
+        WalletBalance {
+            mint,
+            unit,
+            balance: balance.into(),
+        },
+        encoding,
+    ))
+}
+
+/// Time a reconciliation may take unless `timeout_seconds` says otherwise
+const DEFAULT_RECONCILE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_RECONCILE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReconcileStatus {
+    /// The mint reported the state of the wallet's proofs
+    Ok,
+    /// The mint failed or did not answer before the timeout
+    Unknown,
+}
+
+/// Proof the wallet holds as unspent but the mint does not
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofDiscrepancy {
+    pub y: PublicKey,
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub amount: u64,
+    pub keyset_id: Id,
+    /// State reported by the mint
+    pub state: ProofSpendState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletReconciliation {
+    pub mint: MintUrl,
+    pub unit: CurrencyUnit,
+    pub status: ReconcileStatus,
+    /// Proofs the wallet holds as unspent
+    pub proof_count: usize,
+    /// Value of the proofs the wallet holds as unspent
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub balance: u64,
+    /// Part of `balance` the mint confirms as unspent, unset when the status is unknown
+    #[serde(
+        default,
+        serialize_with = "serialize_optional_amount",
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    pub unspent_amount: Option<u64>,
+    pub discrepancies: Vec<ProofDiscrepancy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Compare the proofs every wallet holds with the states their mints report (NUT-07)
+///
+/// Mints are asked one after the other until `timeout_seconds` runs out, wallets not
+/// checked by then are reported as unknown.
+pub async fn post_reconcile(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<Vec<WalletReconciliation>>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+    let timeout = match params.get("timeout_seconds") {
+        Some(seconds) => seconds
+            .parse::<u64>()
+            .ok()
+            .map(Duration::from_secs)
+            .filter(|timeout| !timeout.is_zero() && *timeout <= MAX_RECONCILE_TIMEOUT)
+            .ok_or_else(|| {
+                PosError::InvalidQueryParameter(format!(
+                    "timeout_seconds must be 1-{}, got {}",
+                    MAX_RECONCILE_TIMEOUT.as_secs(),
+                    seconds
+                ))
+            })?,
+        None => DEFAULT_RECONCILE_TIMEOUT,
+    };
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    let mut report = Vec::new();
+    for wallet in state.node.wallet.get_wallets().await {
+        report.push(reconcile_wallet(&wallet, deadline).await);
+    }
+
+    let discrepancies: usize = report.iter().map(|w| w.discrepancies.len()).sum();
+    tracing::info!(
+        "Reconciled {} wallets, {} proofs held but not unspent at the mint",
+        report.len(),
+        discrepancies
+    );
+
+    Ok(AmountJson(report, encoding))
+}
+
+async fn reconcile_wallet(wallet: &Wallet, deadline: tokio::time::Instant) -> WalletReconciliation {
+    let mut reconciliation = WalletReconciliation {
+        mint: wallet.mint_url.clone(),
+        unit: wallet.unit.clone(),
+        status: ReconcileStatus::Unknown,
+        proof_count: 0,
+        balance: 0,
+        unspent_amount: None,
+        discrepancies: Vec::new(),
+        error: None,
+    };
+
+    let proofs = match wallet.get_unspent_proofs().await {
+        Ok(proofs) => proofs,
+        Err(e) => {
+            reconciliation.error = Some(format!("Could not read wallet proofs: {}", e));
+            return reconciliation;
+        }
+    };
+
+    reconciliation.proof_count = proofs.len();
+    reconciliation.balance = proofs.iter().map(|proof| u64::from(proof.amount)).sum();
+
+    let states = match proofs.is_empty() {
+        true => Vec::new(),
+        false => {
+            match tokio::time::timeout_at(deadline, wallet.check_proofs_spent(proofs.clone())).await
+            {
+                Ok(Ok(states)) => states,
+                Ok(Err(e)) => {
+                    tracing::warn!("Could not check proofs with {}: {}", wallet.mint_url, e);
+                    reconciliation.error = Some(e.to_string());
+                    return reconciliation;
+                }
+                Err(_) => {
+                    reconciliation.error = Some("Timed out".to_string());
+                    return reconciliation;
+                }
+            }
+        }
+    };
+
+    let states: HashMap<PublicKey, ProofSpendState> = states
+        .into_iter()
+        .map(|state| (state.y, state.state))
+        .collect();
+
+    let mut unspent_amount = 0;
+    for proof in proofs.iter() {
+        let Some(state) = proof
+            .y()
+            .ok()
+            .and_then(|y| states.get(&y).map(|state| (y, *state)))
+        else {
+            continue;
+        };
+
+        match state {
+            (_, ProofSpendState::Unspent) => unspent_amount += u64::from(proof.amount),
+            (y, state) => reconciliation.discrepancies.push(ProofDiscrepancy {
+                y,
+                amount: proof.amount.into(),
+                keyset_id: proof.keyset_id,
+                state,
+            }),
+        }
+    }
+
+    reconciliation.status = ReconcileStatus::Ok;
+    reconciliation.unspent_amount = Some(unspent_amount);
+
+    reconciliation
+}
+
+/// Consolidate the proofs of every wallet above the configured threshold now
+pub async fn post_consolidate(
+    State(state): State<CashuPosState>,
+) -> Json<Vec<WalletConsolidation>> {
+    Json(consolidation::consolidate(&state.node, &state.pos_info().consolidation).await)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawRequest {
+    pub mint: MintUrl,
+    pub unit: Option<String>,
+    pub bolt11: String,
+}
+
+/// Pay a lightning invoice by melting proofs of one wallet
+pub async fn post_withdraw(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+    Json(request): Json<WithdrawRequest>,
+) -> Result<AmountJson<Withdrawal>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+    let unit = parse_unit(&state, request.unit.as_ref())?;
+
+    let wallet = state
+        .node
+        .wallet
+        .get_wallet(&WalletKey::new(request.mint.clone(), unit.clone()))
+        .await
+        .ok_or_else(|| PosError::WalletNotFound {
+            mint: request.mint.clone(),
+            unit: unit.clone(),
+        })?;
+
+    let melt_quote = wallet
+        .melt_quote(request.bolt11.clone(), None)
+        .await
+        .map_err(|e| PosError::MeltError(e.to_string()))?;
+
+    // Consolidation or a sweep swapping the proofs away mid-melt would fail it
+    let _spending = state.node.consolidation.lock().await;
+
+    // The mint holds back the fee reserve until it knows the actual routing fee
+    let required = u64::from(melt_quote.amount).saturating_add(melt_quote.fee_reserve.into());
+    let available: u64 = wallet
+        .total_balance()
+        .await
+        .map_err(|e| PosError::WalletError(e.to_string()))?
+        .into();
+
+    if available < required {
+        return Err(PosError::InsufficientBalance {
+            available: PosAmount::new(available, unit.clone()),
+            required: PosAmount::new(required, unit),
+        });
+    }
+
+    let melted = wallet.melt(&melt_quote.id).await.map_err(|e| {
+        tracing::error!(
+            "Melt of quote {} at {} failed: {}",
+            melt_quote.id,
+            request.mint,
+            e
+        );
+        PosError::MeltError(e.to_string())
+    })?;
+
+    let withdrawal = Withdrawal {
+        id: Uuid::new_v4(),
+        mint: request.mint,
+        unit,
+        bolt11: request.bolt11,
+        melt_quote_id: melt_quote.id,
+        state: melted.state,
+        amount: melted.amount.into(),
+        fee_paid: melted.fee_paid.into(),
+        preimage: melted.preimage,
+        created_at: unix_time(),
+    };
+
+    tracing::info!(
+        "Withdrew {} from {} ({:?})",
+        PosAmount::new(withdrawal.amount, withdrawal.unit.clone()),
+        withdrawal.mint,
+        withdrawal.state
+    );
+
+    // The invoice is already paid, so a failed write must not turn into an error response
+    if let Err(e) = state.db.add_withdrawal(&withdrawal).await {
+        tracing::error!("Failed to record withdrawal {}: {}", withdrawal.id, e);
+    }
+
+    Ok(AmountJson(withdrawal, encoding))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendRequest {
+    pub mint: MintUrl,
+    pub unit: Option<String>,
+    /// Amount in minor units of `unit`
+    pub amount: u64,
+    pub memo: Option<String>,
+}
+
+/// Export ecash from one wallet as a cashu token
+///
+/// The proofs are reserved in the wallet as pending and the token is stored, so it can be
+/// fetched again from `GET /send` if the response never arrives.
+pub async fn post_send(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+    Json(request): Json<SendRequest>,
+) -> Result<AmountJson<SentToken>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+    let unit = parse_unit(&state, request.unit.as_ref())?;
+
+    if request.amount == 0 {
+        return Err(PosError::InvalidAmount(
+            "Amount must be positive".to_string(),
+        ));
+    }
+
+    let wallet = state
+        .node
+        .wallet
+        .get_wallet(&WalletKey::new(request.mint.clone(), unit.clone()))
+        .await
+        .ok_or_else(|| PosError::WalletNotFound {
+            mint: request.mint.clone(),
+            unit: unit.clone(),
+        })?;
+
+    // Consolidation or a sweep swapping the proofs away mid-send would fail it
+    let _spending = state.node.consolidation.lock().await;
+
+    let available: u64 = wallet
+        .total_balance()
+        .await
+        .map_err(|e| PosError::WalletError(e.to_string()))?
+        .into();
+
+    if available < request.amount {
+        return Err(PosError::InsufficientBalance {
+            available: PosAmount::new(available, unit.clone()),
+            required: PosAmount::new(request.amount, unit),
+        });
+    }
+
+    let token = wallet
+        .send(
+            Amount::from(request.amount),
+            request.memo.as_deref().map(SendMemo::for_token),
+            None,
+            &SplitTarget::default(),
+            &SendKind::default(),
+            false,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                "Send of {} from {} failed: {}",
+                request.amount,
+                request.mint,
+                e
+            );
+            PosError::WalletError(e.to_string())
+        })?;
+
+    let sent_token = SentToken {
+        id: Uuid::new_v4(),
+        mint: request.mint,
+        unit,
+        amount: request.amount,
+        memo: request.memo,
+        token: token.to_string(),
+        created_at: unix_time(),
+    };
+
+    tracing::info!(
+        "Sent {} from {} as token {}",
+        PosAmount::new(sent_token.amount, sent_token.unit.clone()),
+        sent_token.mint,
+        sent_token.id
+    );
+
+    // The proofs are already reserved for the token, so a failed write must not hide it
+    if let Err(e) = state.db.add_sent_token(&sent_token).await {
+        tracing::error!("Failed to record sent token {}: {}", sent_token.id, e);
+    }
+
+    Ok(AmountJson(sent_token, encoding))
+}
+
+pub async fn get_sent_tokens(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<Vec<SentToken>>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let sent_tokens = state
+        .db
+        .list_sent_tokens()
+        .await
+        .map_err(|e| PosError::DatabaseError(e.to_string()))?;
+
+    Ok(AmountJson(sent_tokens, encoding))
+}
+
+/// Payments each rule in `shadow_mode` would have rejected since the server started
+pub async fn get_shadow_rejections(
+    State(state): State<CashuPosState>,
+) -> Json<BTreeMap<String, u64>> {
+    let counts = state.shadow_rejections.counts();
+
+    Json(
+        state
+            .pos_info()
+            .shadow_mode
+            .iter()
+            .map(|rule| {
+                (
+                    rule.to_string(),
+                    counts.get(rule).copied().unwrap_or_default(),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Every attempt to sweep a wallet to the lightning address, newest first
+pub async fn get_sweeps(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<Vec<Sweep>>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let sweeps = state
+        .db
+        .list_sweeps()
+        .await
+        .map_err(|e| PosError::DatabaseError(e.to_string()))?;
+
+    Ok(AmountJson(sweeps, encoding))
+}
+
+/// Gross amount covering the estimated input fees at the most expensive accepted mint
+async fn fee_inclusive_amount(
+    state: &CashuPosState,
+    mints: &[MintUrl],
+    amount: u64,
+    unit: &CurrencyUnit,
+) -> Result<u64, PosError> {
+    let mut gross = amount;
+
+    for mint in mints {
+        let input_fee_ppk = mint_input_fee_ppk(state, mint, unit).await?;
+        gross = gross.max(fees::gross_amount(amount, input_fee_ppk));
+    }
+
+    Ok(gross)
+}
+
+async fn mint_input_fee_ppk(
+    state: &CashuPosState,
+    mint: &MintUrl,
+    unit: &CurrencyUnit,
+) -> Result<u64, PosError> {
+    state.node.input_fee_ppk(mint, unit).await.map_err(|e| {
+        tracing::warn!("Could not get input fee for {} {}: {}", mint, unit, e);
+        PosError::WalletError(e.to_string())
+    })
+}
+
+/// Maximum number of quotes a single bulk request may create
+pub const MAX_BULK_QUOTES: u64 = 500;
+
+/// Smallest width and height of the QR codes in a bulk quote zip, in pixels
+const BULK_QR_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkQuote {
+    pub id: Uuid,
+    pub payment_request: String,
+}
+
+pub async fn post_bulk_quotes(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+    Json(request): Json<BulkQuoteRequest>,
+) -> Result<Response, PosError> {
+    if request.count == 0 || request.count > MAX_BULK_QUOTES {
+        return Err(PosError::InvalidQuoteCount {
+            count: request.count,
+            max: MAX_BULK_QUOTES,
+        });
+    }
+
+    let include_qr = match params.get("include_qr").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(other) => {
+            return Err(PosError::InvalidQueryParameter(format!(
+                "include_qr must be true or false, got {}",
+                other
+            )));
+        }
+    };
+
+    if request.expiry_seconds == Some(0) {
+        return Err(PosError::InvalidQueryParameter(
+            "expiry_seconds must be at least 1".to_string(),
+        ));
+    }
+
+    let unit = parse_unit(&state, request.unit.as_ref())?;
+    let amount = quote_amount(&state, request.amount, &unit, request.fee_inclusive, None).await?;
+    validate_memo(&state, request.memo.as_ref())?;
+    ensure_quote_capacity(&state, request.count as usize).await?;
+
+    tracing::debug!(
+        "Received bulk quote request for {} quotes of {} {}",
+        request.count,
+        amount,
+        unit
+    );
+
+    let expires_at = quote_expires_at(&state, request.expiry_seconds);
+    let mut quotes = Vec::with_capacity(request.count as usize);
+    let mut response = Vec::with_capacity(request.count as usize);
+
+    for _ in 0..request.count {
+        let quote = QuoteInfo {
+            id: Uuid::new_v4(),
+            state: QuoteState::Unpaid,
+            amount: PosAmount::new(amount, unit.clone()),
+            tag: request.tag.clone(),
+            htlc_preimage: None,
+            payment_fingerprint: None,
+            expires_at,
+            memo: request.memo.clone(),
+            receipt: None,
+            paid_amount: None,
+            created_at: Some(unix_time()),
+            paid_at: None,
+            webhook_url: None,
+            reference: None,
+            mints: None,
+            fiat: None,
+            alternative_amounts: None,
+            net_amount: request.fee_inclusive.then_some(request.amount),
+        };
+
+        response.push(BulkQuote {
+            id: quote.id,
+            payment_request: build_payment_request(&state, &quote, MintListMode::Compact)?,
+        });
+        quotes.push(quote);
+    }
+
+    // Rendered before storing so a failure leaves no quotes behind
+    let qr_zip = match include_qr {
+        true => Some(bulk_quotes_zip(&response)?),
+        false => None,
+    };
+
+    state.db.add_quotes(&quotes).await.map_err(|e| {
+        tracing::error!("Failed to add bulk quotes to database: {}", e);
+        PosError::DatabaseError(e.to_string())
+    })?;
+
+    tracing::info!("Created {} bulk quotes", quotes.len());
+
+    match qr_zip {
+        Some(zip) => Ok((
+            [
+                (CONTENT_TYPE, "application/zip"),
+                (CONTENT_DISPOSITION, "attachment; filename=\"quotes.zip\""),
+            ],
+            zip,
+        )
+            .into_response()),
+        None => Ok(Json(response).into_response()),
+    }
+}
+
+/// Zip of a `quotes.json` listing `quotes` and a `<id>.svg` QR code of each payment request
+fn bulk_quotes_zip(quotes: &[BulkQuote]) -> Result<Vec<u8>, PosError> {
+    use std::io::Write;
+
+    let zip_error = |e: zip::result::ZipError| {
+        PosError::InternalError(format!("Failed to write quote zip: {}", e))
+    };
+
+    // SVGs and JSON are small and the QR codes barely compress, so files are stored as is
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+
+    zip.start_file("quotes.json", options).map_err(zip_error)?;
+    serde_json::to_writer_pretty(&mut zip, quotes)
+        .map_err(|e| PosError::InternalError(format!("Failed to write quote list: {}", e)))?;
+
+    for quote in quotes {
+        let svg = qrcode::QrCode::new(quote.payment_request.as_bytes())
+            .map_err(|e| {
+                PosError::InternalError(format!("Failed to encode QR code of {}: {}", quote.id, e))
+            })?
+            .render::<qrcode::render::svg::Color<'_>>()
+            .min_dimensions(BULK_QR_SIZE, BULK_QR_SIZE)
+            .build();
+
+        zip.start_file(format!("{}.svg", quote.id), options)
+            .map_err(zip_error)?;
+        zip.write_all(svg.as_bytes())
+            .map_err(|e| PosError::InternalError(format!("Failed to write quote zip: {}", e)))?;
+    }
+
+    Ok(zip.finish().map_err(zip_error)?.into_inner())
+}
+
+/// Parse the requested currency unit, defaulting to SAT, or the first accepted unit if SAT
+/// isn't accepted, when not provided
+fn parse_unit(state: &CashuPosState, unit: Option<&String>) -> Result<CurrencyUnit, PosError> {
+    let accepted_units = &state.pos_info().accepted_units;
+
+    match unit {
+        Some(unit_str) => parse_unit_lenient(unit_str, accepted_units),
+        None if accepted_units.contains(&CurrencyUnit::Sat) => Ok(CurrencyUnit::Sat),
+        None => accepted_units
+            .first()
+            .cloned()
+            .ok_or_else(|| PosError::InternalError("No accepted units configured".to_string())),
+    }
+}
+
+/// Expiry for a quote created now, after `expiry_seconds` or the configured expiry if quotes
+/// expire
+fn quote_expires_at(state: &CashuPosState, expiry_seconds: Option<u64>) -> Option<u64> {
+    expiry_seconds
+        .or(state.pos_info().quote_expiry_seconds)
+        .map(|seconds| unix_time().saturating_add(seconds))
+}
+
+/// Build the encoded NUT-18 payment request advertised for a quote
+fn build_payment_request(
+    state: &CashuPosState,
+    quote: &QuoteInfo,
+    mint_list: MintListMode,
+) -> Result<String, PosError> {
+    let transport = Transport::builder()
+        .transport_type(TransportType::HttpPost)
+        .target(state.payment_url.clone())
+        .build()
+        .map_err(|e| {
+            tracing::error!("Failed to build transport: {}", e);
+            PosError::InternalError(format!("Failed to build transport: {}", e))
+        })?;
+
+    // Accepted mints are listed in priority order, so compact requests keep the first ones.
+    // Payments are still validated against every mint the quote accepts.
+    let pos_info = state.pos_info();
+    let accepted_mints = quote.accepted_mints(&pos_info);
+    let mints = match (mint_list, pos_info.max_mints_per_request) {
+        (MintListMode::Compact, Some(max)) => accepted_mints.iter().take(max).cloned().collect(),
+        _ => accepted_mints.to_vec(),
+    };
+
+    let mut builder = PaymentRequest::builder()
+        .payment_id(quote.id)
+        .amount(quote.amount.value)
+        .unit(quote.amount.unit.clone())
+        .single_use(true)
+        .mints(mints)
+        .add_transport(transport);
+
+    #[cfg(feature = "notifications-nostr")]
+    if let Some(nostr) = &state.nostr {
+        builder = builder.add_transport(nostr.transport());
+    }
+
+    // Proofs locked to our key are useless to anyone intercepting the payload
+    if let Some(key) = &state.p2pk_key {
+        builder = builder.nut10(Nut10SecretRequest {
+            kind: Kind::P2PK,
+            data: key.public_key().to_hex(),
+            tags: None,
+        });
+    }
+
+    // NUT-18 has no expiry field, the description is what wallets show the payer
+    let expiry = quote
+        .expires_at
+        .and_then(|expires_at| DateTime::from_timestamp(expires_at as i64, 0))
+        .map(|expires_at| format!("Expires {}", expires_at.format("%Y-%m-%d %H:%M:%S UTC")));
+
+    let description = match (&quote.memo, expiry) {
+        (Some(memo), Some(expiry)) => Some(format!("{} ({})", memo, expiry)),
+        (Some(memo), None) => Some(memo.clone()),
+        (None, expiry) => expiry,
+    };
+
+    if let Some(description) = description {
+        builder = builder.description(description);
+    }
+
+    let payment_request = builder.build().to_string();
+
+    tracing::debug!(
+        "Encoded payment request for quote {} is {} characters",
+        quote.id,
+        payment_request.len()
+    );
+
+    if payment_request.len() > state.pos_info().payment_request_warn_length {
+        tracing::warn!(
+            "Payment request for quote {} is {} characters, QR codes may not scan on small screens",
+            quote.id,
+            payment_request.len()
+        );
+    }
+
+    Ok(payment_request)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct QuotePaymentRequestResponse {
+    pub id: Uuid,
+    pub payment_request: String,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/quote/{id}/request",
+        tag = "quotes",
+        params(
+            ("id" = Uuid, Path, description = "Quote id"),
+            ("mints" = Option<String>, Query, description = "`all` to list every accepted mint, `compact` for the first `max_mints_per_request`"),
+        ),
+        responses(
+            (status = 200, description = "NUT-18 payment request of the quote", body = QuotePaymentRequestResponse),
+            (status = 400, description = "Invalid id or `mints` value", body = ErrorBody),
+            (status = 404, description = "No such quote", body = ErrorBody),
+        )
+    )
+)]
+pub async fn get_quote_payment_request(
+    State(state): State<CashuPosState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<Json<QuotePaymentRequestResponse>, PosError> {
+    let id = Uuid::from_str(&id).map_err(|e| {
+        tracing::warn!("Invalid UUID format: {} - {}", id, e);
+        PosError::InvalidUuid(id.clone())
+    })?;
+
+    let mint_list = params
+        .get("mints")
+        .map(|m| MintListMode::from_str(m))
+        .transpose()?
+        .unwrap_or_default();
+
+    let quote = state.db.get_quote(id).await.map_err(|e| {
+        tracing::warn!("Quote not found: {} - {}", id, e);
+        quote_store_error(id, e)
+    })?;
+
+    Ok(Json(QuotePaymentRequestResponse {
+        id,
+        payment_request: build_payment_request(&state, &quote, mint_list)?,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct QuoteStateResponse {
+    pub id: Uuid,
+    pub state: QuoteState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<Receipt>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_amount",
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<AmountValue>))]
+    pub paid_amount: Option<u64>,
+    /// Amount still to be paid in the quote's unit
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = AmountValue))]
+    pub remaining_amount: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Unix time the quote was created, missing for quotes stored before it was recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Unix time the quote became `Paid`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paid_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fiat: Option<FiatPrice>,
+    /// Amounts in other units the quote may still be paid in instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternative_amounts: Option<Vec<PosAmount>>,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        get,
+        path = "/check/{id}",
+        tag = "quotes",
+        params(
+            ("id" = Uuid, Path, description = "Quote id"),
+            ("amounts" = Option<String>, Query, description = "`string` to write amounts as decimal strings, `number` otherwise"),
+        ),
+        responses(
+            (status = 200, description = "Current state of the quote", body = QuoteStateResponse),
+            (status = 400, description = "Invalid id", body = ErrorBody),
+            (status = 404, description = "No such quote", body = ErrorBody),
+            (status = 500, description = "The quote could not be read", body = ErrorBody),
+        )
+    )
+)]
+pub async fn get_quote_state(
+    State(state): State<CashuPosState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<QuoteStateResponse>, PosError> {
+    tracing::debug!("Received quote state request for ID: {}", id);
+
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let id = Uuid::from_str(&id).map_err(|e| {
+        tracing::warn!("Invalid UUID format: {} - {}", id, e);
+        PosError::InvalidUuid(id.clone())
+    })?;
+
+    let quote = state.db.get_quote(id).await.map_err(|e| {
+        tracing::warn!("Quote not found: {} - {}", id, e);
+        quote_store_error(id, e)
+    })?;
+
+    let response = QuoteStateResponse::from(quote);
+
+    tracing::debug!("Returning quote state for {}: {:?}", id, response);
+    Ok(AmountJson(response, encoding))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotePaymentsResponse {
+    pub quote_id: Uuid,
+    /// Payloads redeemed for the quote, several with partial payments
+    pub payments: Vec<ReceivedPayment>,
+}
+
+/// Audit records of the proofs received for a quote
+pub async fn get_quote_payments(
+    State(state): State<CashuPosState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<QuotePaymentsResponse>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let id = Uuid::from_str(&id).map_err(|_| PosError::InvalidUuid(id.clone()))?;
+
+    state
+        .db
+        .get_quote(id)
+        .await
+        .map_err(|e| quote_store_error(id, e))?;
+
+    let payments = state
+        .db
+        .get_payments(id)
+        .await
+        .map_err(|e| quote_store_error(id, e))?;
+
+    Ok(AmountJson(
+        QuotePaymentsResponse {
+            quote_id: id,
+            payments,
+        },
+        encoding,
+    ))
+}
+
+/// Resolve an external reference to its quote
+///
+/// References are not unique: creating another quote with a reference already in use
+/// is allowed, e.g. to retry an order whose quote expired, and the reference then
+/// resolves to the most recent of those quotes.
+pub async fn get_quote_by_reference(
+    State(state): State<CashuPosState>,
+    axum::extract::Path(reference): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<QuoteStateResponse>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let quote = state
+        .db
+        .get_quote_by_reference(&reference)
+        .await
+        .map_err(|e| PosError::DatabaseError(e.to_string()))?
+        .ok_or(PosError::ReferenceNotFound(reference))?;
+
+    Ok(AmountJson(QuoteStateResponse::from(quote), encoding))
+}
+
+impl From<QuoteInfo> for QuoteStateResponse {
+    fn from(quote: QuoteInfo) -> Self {
+        Self {
+            id: quote.id,
+            state: quote.state_at(unix_time()),
+            remaining_amount: quote
+                .amount
+                .value
+                .saturating_sub(quote.paid_amount.unwrap_or_default()),
+            receipt: quote.receipt,
+            paid_amount: quote.paid_amount,
+            memo: quote.memo,
+            reference: quote.reference,
+            created_at: quote.created_at,
+            paid_at: quote.paid_at,
+            fiat: quote.fiat,
+            alternative_amounts: quote.alternative_amounts,
+        }
+    }
+}
+
+/// Tell live subscribers about the new state of `quote`
+fn publish_quote_update(state: &CashuPosState, quote: &QuoteInfo) {
+    // Sending only fails when nobody is subscribed
+    let _ = state
+        .quote_updates
+        .send(QuoteStateResponse::from(quote.clone()));
+}
+
+/// Maximum number of quotes a single WebSocket may follow
+const MAX_WS_SUBSCRIPTIONS: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WsRequest {
+    Subscribe(Uuid),
+    Unsubscribe(Uuid),
+}
+
+pub async fn get_ws(State(state): State<CashuPosState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| handle_ws(state, socket))
+}
+
+/// Forward updates of the subscribed quotes until the client goes away
+async fn handle_ws(state: CashuPosState, mut socket: WebSocket) {
+    // Dropped with this function, so closed sockets never leave receivers behind
+    let mut updates = state.quote_updates.subscribe();
+    let mut subscriptions = HashSet::new();
+
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+
+                ws_request_reply(&state, &mut subscriptions, text.as_str()).await
+            }
+            update = updates.recv() => match update {
+                Ok(update) if subscriptions.contains(&update.id) => {
+                    serde_json::to_value(update).unwrap_or_default()
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!("WebSocket subscriber missed {} quote updates", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+
+        if socket
+            .send(Message::Text(reply.to_string().into()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Apply a client message to `subscriptions`, answering subscriptions with the current state
+async fn ws_request_reply(
+    state: &CashuPosState,
+    subscriptions: &mut HashSet<Uuid>,
+    text: &str,
+) -> serde_json::Value {
+    let error =
+        |err: PosError| serde_json::json!({ "error": err.code(), "message": err.to_string() });
+
+    match serde_json::from_str::<WsRequest>(text) {
+        Ok(WsRequest::Subscribe(id)) => {
+            if subscriptions.len() >= MAX_WS_SUBSCRIPTIONS && !subscriptions.contains(&id) {
+                return error(PosError::InvalidQuoteCount {
+                    count: subscriptions.len() as u64 + 1,
+                    max: MAX_WS_SUBSCRIPTIONS as u64,
+                });
+            }
+
+            match state.db.get_quote(id).await {
+                Ok(quote) => {
+                    subscriptions.insert(id);
+                    serde_json::to_value(QuoteStateResponse::from(quote)).unwrap_or_default()
+                }
+                Err(e) => error(quote_store_error(id, e)),
+            }
+        }
+        Ok(WsRequest::Unsubscribe(id)) => {
+            subscriptions.remove(&id);
+            serde_json::json!({ "unsubscribed": id })
+        }
+        Err(e) => error(PosError::InvalidQueryParameter(format!(
+            "Expected {{\"subscribe\": \"<quote id>\"}} or {{\"unsubscribe\": \"<quote id>\"}}: {}",
+            e
+        ))),
+    }
+}
+
+/// Void an unpaid quote so it no longer accepts payment
+pub async fn cancel_quote(
+    State(state): State<CashuPosState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<QuoteStateResponse>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let id = Uuid::from_str(&id).map_err(|e| {
+        tracing::warn!("Invalid UUID format: {} - {}", id, e);
+        PosError::InvalidUuid(id.clone())
+    })?;
+
+    // Lapsed quotes are still stored as unpaid and may be cancelled too
+    let quote = state
+        .db
+        .transition_quote_state(id, QuoteState::Unpaid, QuoteState::Cancelled)
+        .await
+        .map_err(|e| quote_store_error(id, e))?;
+    publish_quote_update(&state, &quote);
+
+    tracing::info!("Cancelled quote {}", id);
+
+    Ok(AmountJson(QuoteStateResponse::from(quote), encoding))
+}
+
+/// Default and maximum page size of `GET /quotes`
+const DEFAULT_QUOTE_PAGE_SIZE: usize = 100;
+const MAX_QUOTE_PAGE_SIZE: usize = 500;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct QuoteListResponse {
+    pub quotes: Vec<QuoteInfo>,
+    /// Pass as `cursor` to fetch the next page, absent on the last page
+    pub next_cursor: Option<Uuid>,
+}
+
+/// List quotes oldest first by `created_at`, quotes created in the same second ordered by id
+///
+/// Quotes stored before `created_at` was recorded are listed first. `next_cursor` is the id
+/// of the last quote of the page, which stays a valid position in this order.
+pub async fn get_quotes(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<AmountJson<QuoteListResponse>, PosError> {
+    let encoding = amount_encoding(&state, params.get("amounts"))?;
+
+    let cursor = params
+        .get("cursor")
+        .map(|cursor| Uuid::from_str(cursor).map_err(|_| PosError::InvalidUuid(cursor.clone())))
+        .transpose()?;
+
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse::<u64>()
+            .map_err(|_| PosError::InvalidQuoteCount {
+                count: 0,
+                max: MAX_QUOTE_PAGE_SIZE as u64,
+            })?,
+        None => DEFAULT_QUOTE_PAGE_SIZE as u64,
+    };
+
+    if limit == 0 || limit > MAX_QUOTE_PAGE_SIZE as u64 {
+        return Err(PosError::InvalidQuoteCount {
+            count: limit,
+            max: MAX_QUOTE_PAGE_SIZE as u64,
+        });
+    }
+
+    let filter = QuoteFilter {
+        state: params
+            .get("state")
+            .map(|s| QuoteState::from_str(s))
+            .transpose()?,
+        tag: params.get("tag").cloned(),
+        from: parse_timestamp(params.get("from"), "from")?,
+        to: parse_timestamp(params.get("to"), "to")?,
+        time_field: params
+            .get("by")
+            .map(|field| QuoteTimeField::from_str(field))
+            .transpose()?
+            .unwrap_or_default(),
+    };
+
+    let (mut quotes, next_cursor) = state
+        .db
+        .list_quotes(cursor, limit as usize, &filter, unix_time())
+        .await
+        .map_err(|e| match e {
+            DbError::QuoteNotFound(id) => {
+                PosError::InvalidQueryParameter(format!("cursor {} is not a quote", id))
+            }
+            e => PosError::DatabaseError(e.to_string()),
+        })?;
+
+    // The preimage is a secret shared with the payer, listings never expose it
+    for quote in quotes.iter_mut() {
+        quote.htlc_preimage = None;
+    }
+
+    Ok(AmountJson(
+        QuoteListResponse {
+            quotes,
+            next_cursor,
+        },
+        encoding,
+    ))
+}
+
+fn parse_timestamp(value: Option<&String>, name: &str) -> Result<Option<u64>, PosError> {
+    value
+        .map(|value| {
+            value.parse::<u64>().map_err(|_| {
+                PosError::InvalidQueryParameter(format!(
+                    "{} must be a unix timestamp in seconds, got {}",
+                    name, value
+                ))
+            })
+        })
+        .transpose()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct PaymentDiagnosticsResponse {
+    /// Milliseconds spent in each phase of payment processing
+    pub timings_ms: BTreeMap<String, f64>,
+}
+
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/payment",
+        tag = "payments",
+        request_body(
+            content = Object,
+            description = "NUT-18 `PaymentRequestPayload` as sent by wallets: the quote `id`, the `mint`, `unit` and `proofs`, and an optional `memo`",
+        ),
+        responses(
+            (status = 200, description = "Payment accepted, the body is empty unless diagnostics mode is on", body = PaymentDiagnosticsResponse),
+            (status = 400, description = "Invalid payload, insufficient payment, unsupported mint or unit, proofs the mint refused or that don't meet the quote's spending conditions", body = ErrorBody),
+            (status = 404, description = "No such quote", body = ErrorBody),
+            (status = 408, description = "The client disconnected before the payment started", body = ErrorBody),
+            (status = 409, description = "The mint reports the proofs as already spent", body = ErrorBody),
+            (status = 410, description = "The quote has expired", body = ErrorBody),
+            (status = 429, description = "Too many payments, see `Retry-After`", body = ErrorBody),
+            (status = 500, description = "The wallet or database failed, e.g. the quote could not be updated after its proofs were redeemed", body = ErrorBody),
+            (status = 502, description = "The mint could not be reached to redeem the proofs", body = ErrorBody),
+        )
+    )
+)]
+pub async fn post_receive_payment(
+    State(state): State<CashuPosState>,
+    method: Method,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    // Parsed here rather than by the extractor so rejected bodies can be captured too
+    let payload = match Json::<PaymentRequestPayload>::from_bytes(&body) {
+        Ok(Json(payload)) => payload,
+        Err(rejection) => {
+            state.capture.record(
+                uri.path(),
+                &body,
+                rejection.status().as_u16(),
+                rejection.body_text(),
+            );
+            return rejection.into_response();
+        }
+    };
+
+    // Lets one filter on the quote id or mint show the request along with the payment
+    let span = tracing::Span::current();
+    span.record("mint", tracing::field::display(&payload.mint));
+    if let Some(id) = &payload.id {
+        span.record("quote_id", id.as_str());
+    }
+
+    // Track wallets that deliver payloads in non-standard ways
+    if method != Method::POST || uri.path().ends_with('/') {
+        tracing::info!(
+            "Payment for mint {} delivered via {} {}",
+            payload.mint,
+            method,
+            uri.path()
+        );
+    }
+
+    // Dropping the handler future means the client went away, which cancels the token
+    let client_gone = CancellationToken::new();
+    let _disconnect_guard = client_gone.clone().drop_guard();
+
+    // Processing runs in its own task so a disconnect can never interrupt it between the
+    // wallet receive and recording the quote as paid, and is tracked so shutdown waits for it
+    let capture = Arc::clone(&state.capture);
+    let payments = state.node.payments.clone();
+    let result = payments
+        .spawn(handle_payment(state, payload, client_gone).in_current_span())
+        .await;
+
+    let response = match result {
+        Ok(Ok(None)) => StatusCode::OK.into_response(),
+        Ok(Ok(Some(timings_ms))) => Json(PaymentDiagnosticsResponse { timings_ms }).into_response(),
+        Ok(Err(err)) => {
+            capture.record(uri.path(), &body, err.status().as_u16(), err.to_string());
+            return err.into_unlogged_response();
+        }
+        Err(err) => {
+            PosError::InternalError(format!("Payment task failed: {}", err)).into_response()
+        }
+    };
+
+    capture.record(uri.path(), &body, response.status().as_u16(), String::new());
+
+    response
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaptureQuery {
+    pub quote_id: Option<Uuid>,
+}
+
+pub async fn get_captures(
+    State(state): State<CashuPosState>,
+    axum::extract::Query(query): axum::extract::Query<CaptureQuery>,
+) -> Json<Vec<Capture>> {
+    Json(state.capture.captures(query.quote_id))
+}
+
+/// Process a payload received over nostr, where there is no client to answer
+#[cfg(feature = "notifications-nostr")]
+async fn handle_nostr_payment(state: CashuPosState, payload: PaymentRequestPayload) {
+    // handle_payment logs failures, the payer learns the outcome from the quote state
+    let payments = state.node.payments.clone();
+    let _ = payments
+        .spawn(handle_payment(state, payload, CancellationToken::new()))
+        .await;
+}
+
+async fn handle_payment(
+    state: CashuPosState,
+    payload: PaymentRequestPayload,
+    client_gone: CancellationToken,
+) -> Result<Option<BTreeMap<String, f64>>, PosError> {
+    let quote_id = payload.id.as_deref().and_then(|id| Uuid::from_str(id).ok());
+    let mut timer = PhaseTimer::new(state.pos_info().diagnostics);
+
+    // Every log line of the payment carries the quote and mint as structured fields
+    let span = tracing::info_span!(
+        "payment",
+        quote_id = tracing::field::Empty,
+        mint = %payload.mint
+    );
+    if let Some(id) = quote_id {
+        span.record("quote_id", tracing::field::display(id));
+    }
+
+    let result = process_payment(&state, payload, &client_gone, &mut timer)
+        .instrument(span.clone())
+        .await;
+    let _entered = span.enter();
+
+    if let Err(err) = &result {
+        // Wallets retrying a broken payload repeat the same failure, so only sample those logs
+        if state.log_throttle.should_log(quote_id, err.code()) {
+            tracing::warn!(
+                client_disconnected = client_gone.is_cancelled(),
+                "Payment for quote {} failed: {}",
+                quote_id.map_or("unknown".to_string(), |id| id.to_string()),
+                err
+            );
+        }
+    }
+
+    let timings_ms = timer.finish();
+    if let Some(timings) = &timings_ms {
+        tracing::info!(
+            timings_ms = ?timings,
+            "Payment timings for quote {}",
+            quote_id.map_or("unknown".to_string(), |id| id.to_string())
+        );
+    }
+
+    result.map(|()| timings_ms)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentSimulationResponse {
+    /// Always true, nothing was redeemed or recorded
+    pub simulation: bool,
+    /// Whether the real endpoint would accept the payload
+    pub accepted: bool,
+    /// The payload exactly repeats the one that already paid the quote
+    pub already_paid: bool,
+    /// HTTP status the real endpoint would respond with
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub async fn post_simulate_payment(
+    State(state): State<CashuPosState>,
+    Json(payload): Json<PaymentRequestPayload>,
+) -> Json<PaymentSimulationResponse> {
+    tracing::debug!("Simulating payment for mint: {}", payload.mint);
+
+    let verdict = validate_payment(&state, &payload, &mut PhaseTimer::new(false)).await;
+
+    // The mint is the final judge of the proofs, the simulation stops before asking it
+    let response = match verdict {
+        Ok(check) => PaymentSimulationResponse {
+            simulation: true,
+            accepted: true,
+            already_paid: matches!(check, PaymentCheck::AlreadyPaid),
+            status: StatusCode::OK.as_u16(),
+            code: None,
+            error: None,
+        },
+        Err(err) => PaymentSimulationResponse {
+            simulation: true,
+            accepted: false,
+            already_paid: false,
+            status: err.status().as_u16(),
+            code: Some(err.code().to_string()),
+            error: Some(err.to_string()),
+        },
+    };
+
+    Json(response)
+}
+
+/// Outcome of validating a payment payload against its quote
+enum PaymentCheck {
+    /// Exact retry of a payload already received for the quote
+    AlreadyPaid,
+    /// The payload may be redeemed for the quote
+    Ready {
+        id: Uuid,
+        quote: QuoteInfo,
+        wallet: Wallet,
+        fingerprint: String,
+        /// Total value of the payload's proofs
+        paid_amount: u64,
+    },
+}
+
+async fn process_payment(
+    state: &CashuPosState,
+    payload: PaymentRequestPayload,
+    client_gone: &CancellationToken,
+    timer: &mut PhaseTimer,
+) -> Result<(), PosError> {
+    tracing::debug!("Received payment for mint: {}", payload.mint);
+
+    let (id, quote, wallet, fingerprint, paid_amount) =
+        match validate_payment(state, &payload, timer).await? {
+            PaymentCheck::AlreadyPaid => return Ok(()),
+            PaymentCheck::Ready {
+                id,
+                quote,
+                wallet,
+                fingerprint,
+                paid_amount,
+            } => (id, quote, wallet, fingerprint, paid_amount),
+        };
+
+    // Audit record of what was received, taken before the wallet consumes the proofs
+    let received = payload
+        .proofs
+        .iter()
+        .map(|proof| {
+            Ok(ReceivedProof {
+                y: proof.y()?,
+                amount: proof.amount.into(),
+                keyset_id: proof.keyset_id,
+            })
+        })
+        .collect::<Result<Vec<_>, cdk::nuts::nut00::Error>>()
+        .map_err(|e| PosError::ProofVerificationError(e.to_string()))?;
+
+    // Once the wallet call starts the payment is always completed and recorded
+    if client_gone.is_cancelled() && state.pos_info().disconnect_policy == DisconnectPolicy::Cancel
+    {
+        return Err(PosError::ClientDisconnected(id));
+    }
+
+    // The preimage stays encrypted until the wallet receive needs it
+    let preimages = htlc_preimages(state, &quote)?;
+
+    // Claim the quote so a concurrent payment for it fails instead of being redeemed too
+    let claimed = state
+        .db
+        .transition_quote_state(id, quote.state, QuoteState::Pending)
+        .await
+        .map_err(|e| quote_store_error(id, e))?;
+    publish_quote_update(state, &claimed);
+    timer.mark("db_claim");
+
+    let signing_keys: Vec<SecretKey> = state.p2pk_key.iter().cloned().collect();
+
+    // Receive and verify proofs, releasing the quote again if they are refused
+    let amount = match wallet
+        .receive_proofs(
+            payload.proofs,
+            SplitTarget::default(),
+            &signing_keys,
+            &preimages,
+        )
+        .await
+    {
+        Ok(amount) => amount,
+        Err(e) => {
+            tracing::info!(
+                rejected_by = "mint",
+                "Mint refused proofs for quote {}: {}",
+                id,
+                e
+            );
+
+            match state
+                .db
+                .transition_quote_state(id, QuoteState::Pending, quote.state)
+                .await
+            {
+                Ok(released) => publish_quote_update(state, &released),
+                Err(release_err) => {
+                    tracing::error!("Failed to release quote {}: {}", id, release_err)
+                }
+            }
+            return Err(mint_refusal(id, e));
+        }
+    };
+    timer.mark("wallet_receive");
+
+    tracing::info!(
+        "Successfully received payment of {} {} for quote {}",
+        amount,
+        wallet.unit,
+        id
+    );
+
+    // Update quote state
+    let now = unix_time();
+    let receipt_date = receipt_date(now, state.pos_info().receipt_utc_offset_minutes);
+    let payment = ReceivedPayment {
+        mint: payload.mint,
+        unit: wallet.unit.clone(),
+        amount: paid_amount,
+        payment_fingerprint: fingerprint,
+        received_at: now,
+        proofs: received,
+    };
+    let updated = record_received_payment(state, id, payment, receipt_date).await?;
+    timer.mark("db_write");
+
+    notify_paid(state, updated, now);
+
+    tracing::info!(
+        client_disconnected = client_gone.is_cancelled(),
+        "Payment processing completed for quote {}",
+        id
+    );
+    timer.mark("post_processing");
+
+    Ok(())
+}
+
+/// Decrypted HTLC preimage of `quote` to redeem HTLC-locked proofs with, if it has one
+fn htlc_preimages(state: &CashuPosState, quote: &QuoteInfo) -> Result<Vec<String>, PosError> {
+    let Some(encrypted) = &quote.htlc_preimage else {
+        return Ok(vec![]);
+    };
+
+    let key = state.htlc_preimage_key.as_ref().ok_or_else(|| {
+        PosError::InternalError(format!(
+            "Quote {} has an HTLC preimage but no htlc_preimage_key is set",
+            quote.id
+        ))
+    })?;
+
+    Ok(vec![key.decrypt(encrypted)?])
+}
+
+/// POST `quote` to its webhook once the payments received add up to it, not for partial
+/// payments
+#[cfg(feature = "notifications-webhook")]
+fn notify_paid(state: &CashuPosState, quote: QuoteInfo, paid_at: u64) {
+    let webhook_url = quote
+        .webhook_url
+        .clone()
+        .or_else(|| state.pos_info().webhook_url.clone());
+
+    if let (QuoteState::Paid, Some(url)) = (quote.state, webhook_url) {
+        state.webhooks.send(
+            url,
+            QuotePaidEvent {
+                quote_id: quote.id,
+                paid_amount: quote.paid_amount.unwrap_or_default(),
+                amount: quote.amount,
+                paid_at,
+            },
+        );
+    }
+}
+
+/// Without the `notifications-webhook` feature no webhook can be configured
+#[cfg(not(feature = "notifications-webhook"))]
+fn notify_paid(_state: &CashuPosState, _quote: QuoteInfo, _paid_at: u64) {}
+
+/// Record `payment` of proofs already redeemed for quote `id`, publishing the quote update
+///
+/// The proofs are spent by now, so a payment that can't be recorded is kept with the quote
+/// moved to `Unsettled` for the merchant to resolve, instead of leaving it `Pending`.
+async fn record_received_payment(
+    state: &CashuPosState,
+    id: Uuid,
+    payment: ReceivedPayment,
+    receipt_date: String,
+) -> Result<QuoteInfo, PosError> {
+    let err = match state
+        .db
+        .record_payment(id, payment.clone(), receipt_date)
+        .await
+    {
+        Ok(updated) => {
+            publish_quote_update(state, &updated);
+            return Ok(updated);
+        }
+        Err(err) => err,
+    };
+
+    tracing::error!(
+        "Failed to record payment of {} {} from {} for quote {}: {}",
+        payment.amount,
+        payment.unit,
+        payment.mint,
+        id,
+        err
+    );
+
+    match state.db.hold_payment(id, payment.clone()).await {
+        Ok(held) => {
+            tracing::warn!("Quote {} is unsettled, its payment needs review", id);
+            publish_quote_update(state, &held);
+        }
+        Err(hold_err) => {
+            // Last resort, the log is the only record of what the wallet received
+            tracing::error!(
+                payment = %serde_json::to_string(&payment).unwrap_or_default(),
+                "Failed to hold payment for quote {}: {}",
+                id,
+                hold_err
+            );
+        }
+    }
+
+    Err(quote_store_error(id, err))
+}
+
+/// Map the mint's refusal of the proofs paying quote `id` to the error reported to the payer
+fn mint_refusal(id: Uuid, err: cdk::Error) -> PosError {
+    match err {
+        cdk::Error::TokenAlreadySpent => PosError::ProofsAlreadySpent(id),
+        // Nothing is known to be wrong with proofs the mint could not be asked about
+        err @ cdk::Error::HttpError(..) => PosError::MintUnreachable(err.to_string()),
+        err => PosError::ProofVerificationError(err.to_string()),
+    }
+}
+
+/// Map a store failure on quote `id` to the error reported to the client
+fn quote_store_error(id: Uuid, err: DbError) -> PosError {
+    match err {
+        DbError::QuoteNotFound(id) => PosError::QuoteNotFound(id),
+        DbError::StateConflict { actual } => PosError::InvalidQuoteState { id, state: actual },
+        DbError::Backend(msg) => PosError::DatabaseError(msg),
+        err @ DbError::UnsupportedSchemaVersion { .. } => PosError::DatabaseError(err.to_string()),
+    }
+}
+
+/// Whether any of `proofs` was already received in one of `payments`
+fn contains_received_proof(proofs: &Proofs, payments: &[ReceivedPayment]) -> bool {
+    let received: HashSet<&PublicKey> = payments
+        .iter()
+        .flat_map(|payment| payment.proofs.iter().map(|proof| &proof.y))
+        .collect();
+
+    proofs
+        .iter()
+        .filter_map(|proof| proof.y().ok())
+        .any(|y| received.contains(&y))
+}
+
+/// Check `received` still covers `net_amount` once `fee` is paid out of it
+fn covers_net_amount(received: &PosAmount, fee: u64, net_amount: u64) -> Result<(), PosError> {
+    let net_received = received.value.saturating_sub(fee);
+
+    match net_received < net_amount {
+        true => Err(PosError::InsufficientPayment {
+            expected: PosAmount::new(net_amount, received.unit.clone()),
+            received: PosAmount::new(net_received, received.unit.clone()),
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Run every check on `payload` short of redeeming it, without touching the wallet or quote
+async fn validate_payment(
+    state: &CashuPosState,
+    payload: &PaymentRequestPayload,
+    timer: &mut PhaseTimer,
+) -> Result<PaymentCheck, PosError> {
+    let shadowed = &state.pos_info().shadow_mode;
+    let rejections = &state.shadow_rejections;
+
+    // Validate payment ID
+    let id = payload
+        .id
+        .clone()
+        .ok_or_else(|| PosError::MissingParameter("id".to_string()))?;
+
+    let id = Uuid::from_str(&id).map_err(|_| PosError::InvalidUuid(id.clone()))?;
+    timer.mark("parse");
+
+    // Get quote
+    let quote = state
+        .db
+        .get_quote(id)
+        .await
+        .map_err(|e| quote_store_error(id, e))?;
+    timer.mark("db_read");
+
+    // Validate mint against the quote's own list when it has one
+    let mint_accepted = match quote
+        .accepted_mints(&state.pos_info())
+        .contains(&payload.mint)
+    {
+        true => Ok(()),
+        false => Err(PosError::UnsupportedMint(payload.mint.clone())),
+    };
+    validation::enforce(
+        ValidationRule::MintAccepted,
+        shadowed,
+        rejections,
+        id,
+        mint_accepted,
+    )?;
+
+    // A wallet that timed out waiting for our response may resend the exact payload that
+    // was already received for the quote, treat that as success rather than failing the retry
+    let fingerprint = payment_fingerprint(&payload.proofs);
+    if matches!(quote.state, QuoteState::Paid | QuoteState::PartiallyPaid)
+        && quote.payment_fingerprint.as_ref() == Some(&fingerprint)
+    {
+        tracing::info!("Payment retry for already received payload of quote {}", id);
+        return Ok(PaymentCheck::AlreadyPaid);
+    }
+
+    let partial_payments = state.pos_info().partial_payments;
+
+    // Validate quote state, expiry is checked here as no job marks quotes expired
+    match quote.state_at(unix_time()) {
+        QuoteState::Unpaid => (),
+        QuoteState::PartiallyPaid if partial_payments => (),
+        QuoteState::Expired => return Err(PosError::QuoteExpired(id)),
+        state => return Err(PosError::InvalidQuoteState { id, state }),
+    }
+
+    // Proofs of an earlier partial payment are spent, refusing them here saves the mint
+    // round-trip. A resend of the latest payload was answered as a retry above
+    if quote.paid_amount.unwrap_or_default() > 0 {
+        let payments = state
+            .db
+            .get_payments(id)
+            .await
+            .map_err(|e| quote_store_error(id, e))?;
+
+        let not_replayed = match contains_received_proof(&payload.proofs, &payments) {
+            true => Err(PosError::ProofsAlreadyReceived(id)),
+            false => Ok(()),
+        };
+        validation::enforce(
+            ValidationRule::ReplayCheck,
+            shadowed,
+            rejections,
+            id,
+            not_replayed,
+        )?;
+    }
+
+    // Validate payment amount, counting what earlier partial payments already covered
+    let received_amount = Amount::try_sum(payload.proofs.iter().map(|p| p.amount))
+        .map_err(|e| PosError::InternalError(format!("Failed to sum proof amounts: {}", e)))?;
+    let received_amount = PosAmount::new(received_amount.into(), payload.unit.clone());
+
+    // Multi-unit quotes take the amount of whichever unit the payer used, a unit the quote
+    // has no amount for fails the comparison below
+    let due = quote
+        .amount_in(&payload.unit)
+        .unwrap_or(&quote.amount)
+        .clone();
+
+    let total_amount = PosAmount::new(
+        received_amount
+            .value
+            .saturating_add(quote.paid_amount.unwrap_or_default()),
+        received_amount.unit.clone(),
+    );
+
+    let amount_sufficient = total_amount
+        .checked_cmp(&due)
+        .and_then(|ordering| match ordering {
+            Ordering::Less if !partial_payments => Err(PosError::InsufficientPayment {
+                expected: due.clone(),
+                received: total_amount.clone(),
+            }),
+            _ => Ok(()),
+        });
+    validation::enforce(
+        ValidationRule::AmountSufficient,
+        shadowed,
+        rejections,
+        id,
+        amount_sufficient,
+    )?;
+
+    // Refusing before the wallet receive leaves the token with the customer
+    if state.pos_info().overpayment_policy == OverpaymentPolicy::Reject {
+        let limit = due
+            .value
+            .saturating_add(state.pos_info().overpayment_tolerance);
+
+        let amount_not_excessive = match total_amount.value > limit {
+            true => Err(PosError::Overpayment {
+                expected: due.clone(),
+                received: total_amount.clone(),
+            }),
+            false => Ok(()),
+        };
+        validation::enforce(
+            ValidationRule::AmountNotExcessive,
+            shadowed,
+            rejections,
+            id,
+            amount_not_excessive,
+        )?;
+    }
+
+    // Get wallet for the mint with the correct currency unit
+    let wallet = state
+        .node
+        .wallet
+        .get_wallet(&WalletKey::new(payload.mint.clone(), due.unit.clone()))
+        .await
+        .ok_or_else(|| PosError::MintUnitNotSupported {
+            mint: payload.mint.clone(),
+            unit: due.unit.clone(),
+        })?;
+
+    // Validate the proofs were issued for the quote's unit, keysets the wallet hasn't cached
+    // yet are left for the mint to judge
+    let keysets = wallet
+        .localstore
+        .get_mint_keysets(payload.mint.clone())
+        .await
+        .map_err(|e| PosError::WalletError(e.to_string()))?
+        .unwrap_or_default();
+
+    let mismatched_keyset = payload.proofs.iter().find_map(|proof| {
+        keysets
+            .iter()
+            .find(|keyset| keyset.id == proof.keyset_id && keyset.unit != due.unit)
+    });
+
+    let unit_match = match mismatched_keyset {
+        Some(keyset) => Err(PosError::UnitMismatch {
+            expected: due.unit.clone(),
+            received: keyset.unit.clone(),
+        }),
+        None => Ok(()),
+    };
+    validation::enforce(
+        ValidationRule::UnitMatch,
+        shadowed,
+        rejections,
+        id,
+        unit_match,
+    )?;
+
+    // A fee-inclusive quote asked the payer to cover the input fees, so what is left after the
+    // fees of the actual proofs must still cover the amount the merchant asked for. Partial
+    // payments are only checked by the one completing the quote
+    if let Some(net_amount) = quote
+        .net_amount
+        .filter(|_| payload.unit == quote.amount.unit && total_amount.value >= due.value)
+    {
+        let fee = fees::proofs_fee(payload.proofs.iter().map(|proof| {
+            keysets
+                .iter()
+                .find(|keyset| keyset.id == proof.keyset_id)
+                .map_or(0, |keyset| keyset.input_fee_ppk)
+        }));
+
+        let fee_policy = covers_net_amount(&total_amount, fee, net_amount);
+        validation::enforce(
+            ValidationRule::FeePolicy,
+            shadowed,
+            rejections,
+            id,
+            fee_policy,
+        )?;
+    }
+
+    // Verifying DLEQ proofs locally refuses forged proofs without a round-trip to the mint
+    let dleq_valid = match state.node.verify_dleq(&wallet, &payload.proofs).await {
+        Ok(verified) => {
+            tracing::debug!(
+                "Verified {} of {} DLEQ proofs for quote {} locally",
+                verified,
+                payload.proofs.len(),
+                id
+            );
+            Ok(())
+        }
+        Err(e) => {
+            tracing::info!(
+                rejected_by = "local",
+                "Refused proofs for quote {}: {}",
+                id,
+                e
+            );
+            Err(PosError::ProofVerificationError(e.to_string()))
+        }
+    };
+    validation::enforce(
+        ValidationRule::DleqValid,
+        shadowed,
+        rejections,
+        id,
+        dleq_valid,
+    )?;
+
+    // HTLC-locked proofs can only be redeemed with the preimage agreed for this quote
+    let htlc_locked = payload.proofs.iter().any(|proof| {
+        Nut10Secret::try_from(&proof.secret).is_ok_and(|secret| secret.kind == Kind::HTLC)
+    });
+
+    if htlc_locked && quote.htlc_preimage.is_none() {
+        return Err(PosError::MissingHtlcPreimage(id));
+    }
+
+    // Unlocked proofs are accepted unless P2PK is required, those locked to another key are
+    // left for the wallet receive to refuse
+    if let (Some(key), true) = (&state.p2pk_key, state.pos_info().require_p2pk) {
+        let pubkey = key.public_key().to_hex();
+        let all_locked = payload.proofs.iter().all(|proof| {
+            Nut10Secret::try_from(&proof.secret)
+                .is_ok_and(|secret| secret.kind == Kind::P2PK && secret.secret_data.data == pubkey)
+        });
+
+        let p2pk_locked = match all_locked {
+            true => Ok(()),
+            false => Err(PosError::P2pkRequired(id)),
+        };
+        validation::enforce(
+            ValidationRule::P2pkLocked,
+            shadowed,
+            rejections,
+            id,
+            p2pk_locked,
+        )?;
+    }
+
+    timer.mark("validate");
+
+    Ok(PaymentCheck::Ready {
+        id,
+        quote,
+        wallet,
+        fingerprint,
+        paid_amount: received_amount.value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cdk::nuts::Proof;
+    use cdk::wallet::MultiMintWallet;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::db::MemoryDb;
+
+    const MINT: &str = "https://mint.example.com";
+
+    fn test_pos_info(overrides: serde_json::Value) -> CashuPosInfo {
+        let mut pos_info = json!({ "accepted_mints": [MINT] });
+        if let (Some(pos_info), Some(overrides)) = (pos_info.as_object_mut(), overrides.as_object())
+        {
+            pos_info.extend(overrides.clone());
+        }
+
+        serde_json::from_value(pos_info).unwrap()
+    }
+
+    fn test_state(pos_info: CashuPosInfo, db: Arc<dyn QuoteStore>) -> CashuPosState {
+        CashuPosState {
+            node: Arc::new(CashuPos::new(MultiMintWallet::new(vec![])).unwrap()),
+            payment_url: versioned_payment_url("http://localhost:8080").unwrap(),
+            db,
+            log_throttle: Arc::new(LogThrottle::new(pos_info.log_throttle)),
+            shadow_rejections: Arc::new(ShadowRejections::default()),
+            capture: Arc::new(CaptureLog::new(pos_info.debug_capture)),
+            #[cfg(feature = "notifications-webhook")]
+            webhooks: WebhookSender::new(),
+            quote_updates: broadcast::channel(QUOTE_UPDATES_CAPACITY).0,
+            cashu_pos_info: Arc::new(RwLock::new(Arc::new(pos_info))),
+            #[cfg(feature = "notifications-nostr")]
+            nostr: None,
+            p2pk_key: None,
+            htlc_preimage_key: None,
+            exchange_rate: None,
+        }
+    }
+
+    fn test_quote(amount: u64, unit: &str, state: QuoteState) -> QuoteInfo {
+        serde_json::from_value(json!({
+            "id": Uuid::new_v4(),
+            "amount": amount,
+            "unit": unit,
+            "state": state,
+            "created_at": unix_time(),
+        }))
+        .unwrap()
+    }
+
+    fn test_payment(amount: u64, unit: CurrencyUnit) -> ReceivedPayment {
+        ReceivedPayment {
+            mint: MintUrl::from_str(MINT).unwrap(),
+            unit,
+            amount,
+            payment_fingerprint: "fingerprint".to_string(),
+            received_at: unix_time(),
+            proofs: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_payment_pays_quote() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+        let quote = test_quote(100, "sat", QuoteState::Pending);
+        db.add_quote(&quote).await.unwrap();
+
+        let paid = record_received_payment(
+            &state,
+            quote.id,
+            test_payment(100, CurrencyUnit::Sat),
+            "2026-01-01".to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(paid.state, QuoteState::Paid);
+        assert_eq!(db.get_payments(quote.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unrecordable_payment_leaves_quote_unsettled() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+        let quote = test_quote(100, "sat", QuoteState::Pending);
+        db.add_quote(&quote).await.unwrap();
+
+        // A sat quote without alternatives can't be settled in usd, so recording fails after
+        // the proofs were redeemed
+        let err = record_received_payment(
+            &state,
+            quote.id,
+            test_payment(100, CurrencyUnit::Usd),
+            "2026-01-01".to_string(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let held = db.get_quote(quote.id).await.unwrap();
+        assert_eq!(held.state, QuoteState::Unsettled);
+        assert_eq!(held.receipt, None);
+
+        let payments = db.get_payments(quote.id).await.unwrap();
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].amount, 100);
+
+        // The quote takes no further payment until the merchant resolves it
+        assert!(matches!(
+            db.transition_quote_state(quote.id, QuoteState::Unpaid, QuoteState::Pending)
+                .await,
+            Err(DbError::StateConflict {
+                actual: QuoteState::Unsettled
+            })
+        ));
+    }
+
+    /// Proof with `secret`, its C is the secp256k1 generator
+    fn test_proof(secret: &str, amount: u64) -> Proof {
+        serde_json::from_value(json!({
+            "amount": amount,
+            "id": "009a1f293253e41e",
+            "secret": secret,
+            "C": "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        }))
+        .unwrap()
+    }
+
+    fn test_payload(quote_id: Uuid, proofs: Vec<Proof>) -> PaymentRequestPayload {
+        serde_json::from_value(json!({
+            "id": quote_id.to_string(),
+            "mint": MINT,
+            "unit": "sat",
+            "proofs": proofs,
+        }))
+        .unwrap()
+    }
+
+    /// Quote of 100 sat partially paid with a proof of `secret`
+    async fn partially_paid_quote(db: &dyn QuoteStore, secret: &str) -> QuoteInfo {
+        let quote = test_quote(100, "sat", QuoteState::Pending);
+        db.add_quote(&quote).await.unwrap();
+
+        let proof = test_proof(secret, 32);
+        let payment = ReceivedPayment {
+            proofs: vec![ReceivedProof {
+                y: proof.y().unwrap(),
+                amount: 32,
+                keyset_id: proof.keyset_id,
+            }],
+            ..test_payment(32, CurrencyUnit::Sat)
+        };
+
+        let quote = db
+            .record_payment(quote.id, payment, "2026-01-01".to_string())
+            .await
+            .unwrap();
+        assert_eq!(quote.state, QuoteState::PartiallyPaid);
+
+        quote
+    }
+
+    async fn validate(state: &CashuPosState, payload: PaymentRequestPayload) -> PosError {
+        let mut timer = PhaseTimer::new(false);
+        match validate_payment(state, &payload, &mut timer).await {
+            Ok(_) => panic!("payment unexpectedly passed validation"),
+            Err(err) => err,
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_check_refuses_proofs_of_an_earlier_payment() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({ "partial_payments": true })),
+            db.clone(),
+        );
+        let quote = partially_paid_quote(db.as_ref(), "first").await;
+
+        let payload = test_payload(
+            quote.id,
+            vec![test_proof("first", 32), test_proof("next", 64)],
+        );
+        let err = validate(&state, payload).await;
+
+        assert_eq!(err.code(), "PROOFS_ALREADY_RECEIVED");
+        assert!(state.shadow_rejections.counts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_check_lets_new_proofs_through() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({ "partial_payments": true })),
+            db.clone(),
+        );
+        let quote = partially_paid_quote(db.as_ref(), "first").await;
+
+        // Passing every check before the wallet lookup, which has no wallet for the mint
+        let payload = test_payload(quote.id, vec![test_proof("next", 64)]);
+        let err = validate(&state, payload).await;
+
+        assert_eq!(err.code(), "MINT_UNIT_NOT_SUPPORTED");
+    }
+
+    #[tokio::test]
+    async fn shadowed_replay_check_counts_the_would_be_rejection() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({
+                "partial_payments": true,
+                "shadow_mode": ["replay_check"],
+            })),
+            db.clone(),
+        );
+        let quote = partially_paid_quote(db.as_ref(), "first").await;
+
+        let payload = test_payload(
+            quote.id,
+            vec![test_proof("first", 32), test_proof("next", 64)],
+        );
+        let err = validate(&state, payload).await;
+
+        assert_eq!(err.code(), "MINT_UNIT_NOT_SUPPORTED");
+        assert_eq!(
+            state
+                .shadow_rejections
+                .counts()
+                .get(&ValidationRule::ReplayCheck),
+            Some(&1)
+        );
+
+        let Json(counts) = get_shadow_rejections(State(state.clone())).await;
+        assert_eq!(counts.get("replay_check"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn payment_without_a_wallet_is_a_client_error() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+        let quote = test_quote(64, "sat", QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        let err = validate(&state, test_payload(quote.id, vec![test_proof("a", 64)])).await;
+
+        assert_eq!(err.code(), "MINT_UNIT_NOT_SUPPORTED");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn mint_refusals_are_not_server_errors() {
+        let id = Uuid::new_v4();
+
+        let spent = mint_refusal(id, cdk::Error::TokenAlreadySpent);
+        assert_eq!(spent.code(), "PROOFS_ALREADY_SPENT");
+        assert_eq!(spent.status(), StatusCode::CONFLICT);
+
+        let invalid = mint_refusal(id, cdk::Error::TokenPending);
+        assert_eq!(invalid.code(), "PROOF_VERIFICATION_ERROR");
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+
+        let unreachable = mint_refusal(id, cdk::Error::HttpError("connection refused".to_string()));
+        assert!(matches!(unreachable, PosError::MintUnreachable(_)));
+        assert_eq!(unreachable.status(), StatusCode::BAD_GATEWAY);
+        assert!(unreachable.to_string().contains("connection refused"));
+
+        let internal = PosError::WalletError("localstore unavailable".to_string());
+        assert_eq!(internal.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn contains_received_proof_compares_ys() {
+        let proof = test_proof("first", 32);
+        let payment = ReceivedPayment {
+            proofs: vec![ReceivedProof {
+                y: proof.y().unwrap(),
+                amount: 32,
+                keyset_id: proof.keyset_id,
+            }],
+            ..test_payment(32, CurrencyUnit::Sat)
+        };
+
+        assert!(contains_received_proof(&vec![proof], &[payment.clone()]));
+        assert!(!contains_received_proof(
+            &vec![test_proof("other", 32)],
+            &[payment]
+        ));
+        assert!(!contains_received_proof(
+            &vec![test_proof("first", 32)],
+            &[]
+        ));
+    }
+
+    #[test]
+    fn fee_policy_checks_the_amount_left_after_fees() {
+        let received = PosAmount::new(103, CurrencyUnit::Sat);
+
+        assert!(covers_net_amount(&received, 3, 100).is_ok());
+
+        let err = covers_net_amount(&received, 4, 100).unwrap_err();
+        assert_eq!(err.code(), "INSUFFICIENT_PAYMENT");
+        assert_eq!(err.detail().unwrap()["received"], json!(99));
+    }
+
+    #[test]
+    fn fee_policy_in_shadow_and_enforce_modes() {
+        let rejections = ShadowRejections::default();
+        let received = PosAmount::new(100, CurrencyUnit::Sat);
+        let check = |shadowed: &[ValidationRule]| {
+            validation::enforce(
+                ValidationRule::FeePolicy,
+                shadowed,
+                &rejections,
+                Uuid::nil(),
+                covers_net_amount(&received, 1, 100),
+            )
+        };
+
+        assert!(check(&[]).is_err());
+        assert!(check(&[ValidationRule::FeePolicy]).is_ok());
+        assert_eq!(
+            rejections.counts().get(&ValidationRule::FeePolicy),
+            Some(&1)
+        );
+    }
+
+    #[tokio::test]
+    async fn amount_json_follows_the_requested_encoding() {
+        let quote = QuoteInfo {
+            paid_amount: Some(1 << 53),
+            ..test_quote(u64::MAX, "sat", QuoteState::PartiallyPaid)
+        };
+        let remaining = u64::MAX - (1 << 53);
+
+        for (encoding, paid, remaining) in [
+            (AmountEncoding::Number, json!(1u64 << 53), json!(remaining)),
+            (
+                AmountEncoding::String,
+                json!((1u64 << 53).to_string()),
+                json!(remaining.to_string()),
+            ),
+        ] {
+            let response =
+                AmountJson(QuoteStateResponse::from(quote.clone()), encoding).into_response();
+            let body: serde_json::Value =
+                serde_json::from_slice(&body_bytes(response).await).unwrap();
+
+            assert_eq!(body["paid_amount"], paid);
+            assert_eq!(body["remaining_amount"], remaining);
+            assert_eq!(body["created_at"], json!(quote.created_at));
+
+            let read: QuoteStateResponse = serde_json::from_value(body).unwrap();
+            assert_eq!(read.paid_amount, Some(1 << 53));
+            assert_eq!(read.remaining_amount, u64::MAX - (1 << 53));
+        }
+    }
+
+    #[tokio::test]
+    async fn reconciliation_amounts_follow_the_requested_encoding() {
+        let proof = test_proof("held", 1 << 53);
+        let reconciliation = WalletReconciliation {
+            mint: MintUrl::from_str(MINT).unwrap(),
+            unit: CurrencyUnit::Sat,
+            status: ReconcileStatus::Ok,
+            proof_count: 1,
+            balance: 1 << 53,
+            unspent_amount: Some(0),
+            discrepancies: vec![ProofDiscrepancy {
+                y: proof.y().unwrap(),
+                amount: 1 << 53,
+                keyset_id: proof.keyset_id,
+                state: ProofSpendState::Spent,
+            }],
+            error: None,
+        };
+
+        let response = AmountJson(vec![reconciliation], AmountEncoding::String).into_response();
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+
+        assert_eq!(body[0]["balance"], json!((1u64 << 53).to_string()));
+        assert_eq!(body[0]["unspent_amount"], json!("0"));
+        assert_eq!(
+            body[0]["discrepancies"][0]["amount"],
+            json!((1u64 << 53).to_string())
+        );
+        assert_eq!(body[0]["proof_count"], json!(1));
+
+        let read: Vec<WalletReconciliation> = serde_json::from_value(body).unwrap();
+        assert_eq!(read[0].balance, 1 << 53);
+        assert_eq!(read[0].unspent_amount, Some(0));
+        assert_eq!(read[0].discrepancies[0].amount, 1 << 53);
+    }
+
+    async fn test_router(overrides: serde_json::Value) -> Router {
+        create_cashu_pos_router(
+            Arc::new(CashuPos::new(MultiMintWallet::new(vec![])).unwrap()),
+            test_pos_info(overrides),
+            "http://localhost:8080".to_string(),
+            Arc::new(MemoryDb::new()),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn send(router: &Router, method: Method, path: &str, api_key: Option<&str>) -> Response {
+        let mut request = axum::http::Request::builder().method(method).uri(path);
+        if let Some(api_key) = api_key {
+            request = request.header(
+                axum::http::header::AUTHORIZATION,
+                format!("Bearer {}", api_key),
+            );
+        }
+
+        router
+            .clone()
+            .oneshot(request.body(axum::body::Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn status(
+        router: &Router,
+        method: Method,
+        path: &str,
+        api_key: Option<&str>,
+    ) -> StatusCode {
+        send(router, method, path, api_key).await.status()
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_the_api_key() {
+        let router = test_router(json!({ "api_key": "secret" })).await;
+
+        for path in [
+            "/v1/quotes",
+            "/v1/balance",
+            "/v1/admin/shadow",
+            "/v1/admin/sweeps",
+        ] {
+            assert_eq!(
+                status(&router, Method::GET, path, None).await,
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                path
+            );
+            assert_eq!(
+                status(&router, Method::GET, path, Some("wrong")).await,
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                path
+            );
+            assert_eq!(
+                status(&router, Method::GET, path, Some("secret")).await,
+                StatusCode::OK,
+                "{}",
+                path
+            );
+        }
+
+        let response = send(&router, Method::GET, "/v1/quotes", None).await;
+        assert_eq!(
+            response.headers().get(axum::http::header::WWW_AUTHENTICATE),
+            Some(&HeaderValue::from_static("Bearer"))
+        );
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["code"], "UNAUTHORIZED");
+
+        // Merchant routes take the same key, payer routes stay open
+        assert_eq!(
+            status(&router, Method::DELETE, "/v1/quote/not-a-uuid", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(
+                &router,
+                Method::DELETE,
+                "/v1/quote/not-a-uuid",
+                Some("secret")
+            )
+            .await,
+            StatusCode::BAD_REQUEST
+        );
+        #[cfg(feature = "openapi")]
+        assert_eq!(
+            status(&router, Method::GET, "/v1/openapi.json", None).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_routes_are_not_served_without_an_api_key() {
+        let router = test_router(json!({ "wallet_routes": true })).await;
+
+        for path in [
+            "/v1/quotes",
+            "/v1/balance",
+            "/v1/admin/shadow",
+            "/v1/admin/sweeps",
+            "/v1/send",
+            "/quotes",
+        ] {
+            assert_eq!(
+                status(&router, Method::GET, path, None).await,
+                StatusCode::NOT_FOUND,
+                "{}",
+                path
+            );
+        }
+
+        // Merchant routes stay open as before
+        assert_eq!(
+            status(&router, Method::DELETE, "/v1/quote/not-a-uuid", None).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn legacy_routes_answer_like_v1_but_deprecated() {
+        let router = test_router(json!({ "api_key": "secret" })).await;
+        let missing = format!("/check/{}", Uuid::new_v4());
+
+        for (path, expected) in [
+            ("/health", StatusCode::OK),
+            (missing.as_str(), StatusCode::NOT_FOUND),
+            ("/quotes", StatusCode::OK),
+        ] {
+            let current = send(
+                &router,
+                Method::GET,
+                &format!("/v1{}", path),
+                Some("secret"),
+            )
+            .await;
+            assert_eq!(current.status(), expected, "/v1{}", path);
+            assert!(
+                current.headers().get("deprecation").is_none(),
+                "/v1{}",
+                path
+            );
+            assert!(current.headers().get(LINK).is_none(), "/v1{}", path);
+
+            let legacy = send(&router, Method::GET, path, Some("secret")).await;
+            assert_eq!(legacy.status(), expected, "{}", path);
+            assert_eq!(legacy.headers()["deprecation"], "true", "{}", path);
+            assert_eq!(
+                legacy.headers()[LINK],
+                format!("</v1{}>; rel=\"successor-version\"", path),
+            );
+        }
+
+        // The legacy routes are protected like their /v1 counterparts
+        assert_eq!(
+            status(&router, Method::GET, "/quotes", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn legacy_routes_can_be_turned_off() {
+        let router = test_router(json!({ "legacy_routes": false })).await;
+
+        assert_eq!(
+            status(&router, Method::GET, "/v1/health", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&router, Method::GET, "/health", None).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    async fn send_payload(
+        router: &Router,
+        method: Method,
+        path: &str,
+        payload: &PaymentRequestPayload,
+    ) -> Response {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(path)
+            .header(CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(serde_json::to_vec(payload).unwrap()))
+            .unwrap();
+
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn payments_are_taken_by_put_and_with_a_trailing_slash() {
+        let router = test_router(json!({})).await;
+        let payload = test_payload(Uuid::new_v4(), vec![test_proof("a", 64)]);
+
+        // Reaching the handler, which doesn't know the quote
+        for (method, path) in [
+            (Method::POST, "/v1/payment"),
+            (Method::PUT, "/v1/payment"),
+            (Method::POST, "/v1/payment/"),
+            (Method::PUT, "/v1/payment/"),
+            (Method::PUT, "/payment/"),
+        ] {
+            let response = send_payload(&router, method.clone(), path, &payload).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::NOT_FOUND,
+                "{} {}",
+                method,
+                path
+            );
+
+            let body: serde_json::Value =
+                serde_json::from_slice(&body_bytes(response).await).unwrap();
+            assert_eq!(body["code"], "QUOTE_NOT_FOUND", "{} {}", method, path);
+        }
+
+        assert_eq!(
+            status(&router, Method::GET, "/v1/payment", None).await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn other_routes_do_not_take_put() {
+        let router = test_router(json!({ "sandbox": true })).await;
+        let check = format!("/v1/check/{}", Uuid::new_v4());
+
+        for path in [
+            "/v1/health",
+            "/v1/create",
+            check.as_str(),
+            "/v1/payment/simulate",
+        ] {
+            assert_eq!(
+                status(&router, Method::PUT, path, None).await,
+                StatusCode::METHOD_NOT_ALLOWED,
+                "PUT {}",
+                path
+            );
+        }
+    }
+
+    fn bulk_request(count: u64, amount: u64) -> BulkQuoteRequest {
+        BulkQuoteRequest {
+            count,
+            amount,
+            unit: Some("sat".to_string()),
+            tag: Some("fridge".to_string()),
+            memo: None,
+            fee_inclusive: false,
+            expiry_seconds: None,
+        }
+    }
+
+    async fn bulk_quotes(
+        state: &CashuPosState,
+        request: BulkQuoteRequest,
+        query: &[(&str, &str)],
+    ) -> Result<Response, PosError> {
+        let query = query
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        post_bulk_quotes(
+            State(state.clone()),
+            axum::extract::Query(query),
+            Json(request),
+        )
+        .await
+    }
+
+    async fn body_bytes(response: Response) -> Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    async fn stored_quotes(db: &dyn QuoteStore, filter: &QuoteFilter) -> Vec<QuoteInfo> {
+        db.list_quotes(None, MAX_QUOTE_PAGE_SIZE, filter, unix_time())
+            .await
+            .unwrap()
+            .0
+    }
+
+    #[tokio::test]
+    async fn bulk_quotes_are_stored_with_tag_and_expiry() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({ "quote_expiry_seconds": 60 })),
+            db.clone(),
+        );
+
+        let mut request = bulk_request(3, 500);
+        request.expiry_seconds = Some(30 * 24 * 60 * 60);
+        request.memo = Some("Fridge".to_string());
+
+        let response = bulk_quotes(&state, request, &[]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let created: Vec<BulkQuote> = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(created.len(), 3);
+
+        let filter = QuoteFilter {
+            tag: Some("fridge".to_string()),
+            ..Default::default()
+        };
+        let quotes = stored_quotes(db.as_ref(), &filter).await;
+        assert_eq!(quotes.len(), 3);
+
+        for quote in quotes {
+            assert!(created.iter().any(|bulk| bulk.id == quote.id));
+            assert_eq!(quote.amount, PosAmount::new(500, CurrencyUnit::Sat));
+            assert_eq!(quote.memo.as_deref(), Some("Fridge"));
+            // The override replaces the 60 second default
+            assert!(quote.expires_at.unwrap() > unix_time() + 29 * 24 * 60 * 60);
+        }
+    }
+
+    #[tokio::test]
+    async fn bulk_quotes_tag_filter_excludes_other_quotes() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+        db.add_quote(&test_quote(100, "sat", QuoteState::Unpaid))
+            .await
+            .unwrap();
+
+        bulk_quotes(&state, bulk_request(2, 100), &[])
+            .await
+            .unwrap();
+
+        let tagged = QuoteFilter {
+            tag: Some("fridge".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(stored_quotes(db.as_ref(), &tagged).await.len(), 2);
+        assert_eq!(
+            stored_quotes(db.as_ref(), &QuoteFilter::default())
+                .await
+                .len(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn bulk_quotes_enforce_count_cap() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+
+        for count in [0, MAX_BULK_QUOTES + 1] {
+            let err = bulk_quotes(&state, bulk_request(count, 100), &[])
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), "INVALID_QUOTE_COUNT");
+        }
+
+        assert!(
+            bulk_quotes(&state, bulk_request(MAX_BULK_QUOTES, 100), &[])
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn bulk_quotes_are_all_or_nothing() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({ "max_open_quotes": 5, "max_memo_length": 4 })),
+            db.clone(),
+        );
+
+        // Each of these fails before anything is stored
+        let err = bulk_quotes(&state, bulk_request(6, 100), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "TOO_MANY_OPEN_QUOTES");
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let err = bulk_quotes(&state, bulk_request(2, 0), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "INVALID_AMOUNT");
+
+        let mut request = bulk_request(2, 100);
+        request.memo = Some("Too long".to_string());
+        let err = bulk_quotes(&state, request, &[]).await.unwrap_err();
+        assert_eq!(err.code(), "MEMO_TOO_LONG");
+
+        let mut request = bulk_request(2, 100);
+        request.unit = Some("yen".to_string());
+        let err = bulk_quotes(&state, request, &[]).await.unwrap_err();
+        assert_eq!(err.code(), "UNSUPPORTED_CURRENCY_UNIT");
+
+        let err = bulk_quotes(&state, bulk_request(2, 100), &[("include_qr", "yes")])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "INVALID_QUERY_PARAMETER");
+
+        assert!(
+            stored_quotes(db.as_ref(), &QuoteFilter::default())
+                .await
+                .is_empty()
+        );
+
+        // Capacity counts quotes already open
+        bulk_quotes(&state, bulk_request(5, 100), &[])
+            .await
+            .unwrap();
+        let err = bulk_quotes(&state, bulk_request(1, 100), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "TOO_MANY_OPEN_QUOTES");
+        assert_eq!(
+            stored_quotes(db.as_ref(), &QuoteFilter::default())
+                .await
+                .len(),
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn bulk_quotes_zip_holds_list_and_qr_codes() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+
+        let response = bulk_quotes(&state, bulk_request(3, 100), &[("include_qr", "true")])
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/zip");
+
+        let mut zip =
+            zip::ZipArchive::new(std::io::Cursor::new(body_bytes(response).await.to_vec()))
+                .unwrap();
+        assert_eq!(zip.len(), 4);
+
+        let quotes: Vec<BulkQuote> =
+            serde_json::from_reader(zip.by_name("quotes.json").unwrap()).unwrap();
+        assert_eq!(quotes.len(), 3);
+
+        for quote in &quotes {
+            let mut svg = String::new();
+            std::io::Read::read_to_string(
+                &mut zip.by_name(&format!("{}.svg", quote.id)).unwrap(),
+                &mut svg,
+            )
+            .unwrap();
+            assert!(svg.contains("<svg"));
+        }
+
+        assert_eq!(
+            stored_quotes(db.as_ref(), &QuoteFilter::default())
+                .await
+                .len(),
+            3
+        );
+    }
+
+    #[cfg(feature = "server-bin")]
+    const CHEAP_MINT: &str = "https://cheap.example.com";
+    #[cfg(feature = "server-bin")]
+    const DEAR_MINT: &str = "https://dear.example.com";
+
+    /// State whose wallets have a cached active sat keyset charging each mint's input fee, so
+    /// fee estimates never reach out to a mint
+    #[cfg(feature = "server-bin")]
+    async fn state_with_input_fees(
+        dir: &std::path::Path,
+        input_fees: &[(&str, u64)],
+        mut overrides: serde_json::Value,
+        db: Arc<dyn QuoteStore>,
+    ) -> CashuPosState {
+        use cdk::cdk_database::WalletDatabase;
+
+        let localstore =
+            Arc::new(cdk_redb::WalletRedbDatabase::new(&dir.join("cdk-wallet.redb")).unwrap());
+        let mut wallets = vec![];
+
+        for (i, (mint, input_fee_ppk)) in input_fees.iter().enumerate() {
+            let keyset = serde_json::from_value(json!({
+                "id": format!("00{:014x}", i + 1),
+                "unit": "sat",
+                "active": true,
+                "input_fee_ppk": input_fee_ppk,
+            }))
+            .unwrap();
+            localstore
+                .add_mint_keysets(MintUrl::from_str(mint).unwrap(), vec![keyset])
+                .await
+                .unwrap();
+
+            wallets.push(
+                Wallet::new(mint, CurrencyUnit::Sat, localstore.clone(), &[0; 64], None).unwrap(),
+            );
+        }
+
+        let mints: Vec<&str> = input_fees.iter().map(|(mint, _)| *mint).collect();
+        overrides["accepted_mints"] = json!(mints);
+        let mut state = test_state(test_pos_info(overrides), db);
+        state.node = Arc::new(CashuPos::new(MultiMintWallet::new(wallets)).unwrap());
+        state
+    }
+
+    #[cfg(feature = "server-bin")]
+    async fn fee_estimate(
+        state: &CashuPosState,
+        query: &[(&str, &str)],
+    ) -> Result<FeeEstimateResponse, PosError> {
+        let query = query
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let AmountJson(estimate, _) =
+            get_fee_estimate(State(state.clone()), axum::extract::Query(query)).await?;
+        Ok(estimate)
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn fee_estimates_follow_each_mint_keyset_fee() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state_with_input_fees(
+            dir.path(),
+            &[(MINT, 0), (CHEAP_MINT, 100), (DEAR_MINT, 1000)],
+            json!({}),
+            Arc::new(MemoryDb::new()),
+        )
+        .await;
+
+        let estimate = fee_estimate(&state, &[("amount", "100"), ("unit", "sat")])
+            .await
+            .unwrap();
+        assert_eq!(estimate.amount, PosAmount::new(100, CurrencyUnit::Sat));
+        assert_eq!(estimate.assumptions, fees::FEE_ESTIMATE_ASSUMPTIONS);
+
+        // (mint, input_fee_ppk, estimated_proofs, estimated_fee, gross_amount)
+        let expected = [
+            (MINT, 0, 3, 0, 100),
+            (CHEAP_MINT, 100, 4, 1, 101),
+            (DEAR_MINT, 1000, 4, 4, 105),
+        ];
+        assert_eq!(estimate.estimates.len(), expected.len());
+
+        for (estimate, (mint, input_fee_ppk, proofs, fee, gross)) in
+            estimate.estimates.iter().zip(expected)
+        {
+            assert_eq!(estimate.mint, MintUrl::from_str(mint).unwrap());
+            assert_eq!(estimate.input_fee_ppk, input_fee_ppk);
+            assert_eq!(estimate.estimated_proofs, proofs);
+            assert_eq!(estimate.estimated_fee, fee);
+            assert_eq!(estimate.gross_amount, gross);
+            assert!(estimate.gross_amount - estimate.estimated_fee >= 100);
+        }
+
+        let estimate = fee_estimate(
+            &state,
+            &[("amount", "100"), ("unit", "sat"), ("mint", DEAR_MINT)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(estimate.estimates.len(), 1);
+        assert_eq!(estimate.estimates[0].gross_amount, 105);
+
+        let unaccepted = fee_estimate(
+            &state,
+            &[
+                ("amount", "100"),
+                ("unit", "sat"),
+                ("mint", "https://other.example.com"),
+            ],
+        )
+        .await;
+        assert!(matches!(unaccepted, Err(PosError::UnsupportedMint(_))));
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn fee_inclusive_quotes_ask_for_the_most_expensive_mint() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(MemoryDb::new());
+        let state = state_with_input_fees(
+            dir.path(),
+            &[(MINT, 0), (CHEAP_MINT, 100), (DEAR_MINT, 1000)],
+            json!({}),
+            db.clone(),
+        )
+        .await;
+        let cheap = [MintUrl::from_str(CHEAP_MINT).unwrap()];
+
+        assert_eq!(
+            quote_amount(&state, 100, &CurrencyUnit::Sat, false, None)
+                .await
+                .unwrap(),
+            100
+        );
+        assert_eq!(
+            quote_amount(&state, 100, &CurrencyUnit::Sat, true, None)
+                .await
+                .unwrap(),
+            105
+        );
+        // A quote restricted to some mints only covers their fees
+        assert_eq!(
+            quote_amount(&state, 100, &CurrencyUnit::Sat, true, Some(&cheap))
+                .await
+                .unwrap(),
+            101
+        );
+
+        let mut request = bulk_request(2, 100);
+        request.fee_inclusive = true;
+        bulk_quotes(&state, request, &[]).await.unwrap();
+
+        let quotes = stored_quotes(db.as_ref(), &QuoteFilter::default()).await;
+        assert_eq!(quotes.len(), 2);
+        for quote in quotes {
+            assert_eq!(quote.amount, PosAmount::new(105, CurrencyUnit::Sat));
+            assert_eq!(quote.net_amount, Some(100));
+        }
+    }
+
+    const PRIORITY_MINTS: [&str; 4] = [
+        MINT,
+        "https://second.example.com",
+        "https://third.example.com",
+        "https://fourth.example.com",
+    ];
+
+    async fn requested_mints(state: &CashuPosState, id: Uuid, mints: Option<&str>) -> Vec<MintUrl> {
+        let query = mints
+            .map(|mints| ("mints".to_string(), mints.to_string()))
+            .into_iter()
+            .collect();
+
+        let Json(response) = get_quote_payment_request(
+            State(state.clone()),
+            axum::extract::Path(id.to_string()),
+            axum::extract::Query(query),
+        )
+        .await
+        .unwrap();
+
+        PaymentRequest::from_str(&response.payment_request)
+            .unwrap()
+            .mints
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn compact_payment_requests_keep_the_first_mints() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({
+                "accepted_mints": PRIORITY_MINTS,
+                "max_mints_per_request": 2,
+            })),
+            db.clone(),
+        );
+        let quote = test_quote(64, "sat", QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        let mints = |count: usize| -> Vec<MintUrl> {
+            PRIORITY_MINTS[..count]
+                .iter()
+                .map(|mint| MintUrl::from_str(mint).unwrap())
+                .collect()
+        };
+
+        assert_eq!(requested_mints(&state, quote.id, None).await, mints(2));
+        assert_eq!(
+            requested_mints(&state, quote.id, Some("compact")).await,
+            mints(2)
+        );
+        assert_eq!(
+            requested_mints(&state, quote.id, Some("all")).await,
+            mints(4)
+        );
+
+        let unknown = get_quote_payment_request(
+            State(state.clone()),
+            axum::extract::Path(quote.id.to_string()),
+            axum::extract::Query(HashMap::from([("mints".to_string(), "some".to_string())])),
+        )
+        .await;
+        assert!(matches!(unknown, Err(PosError::InvalidQueryParameter(_))));
+    }
+
+    #[tokio::test]
+    async fn payment_requests_list_every_mint_without_a_cap() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({ "accepted_mints": PRIORITY_MINTS })),
+            db.clone(),
+        );
+        let quote = test_quote(64, "sat", QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        assert_eq!(requested_mints(&state, quote.id, None).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn payments_from_accepted_mints_left_out_of_the_request_are_accepted() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({
+                "accepted_mints": PRIORITY_MINTS,
+                "max_mints_per_request": 1,
+            })),
+            db.clone(),
+        );
+        let quote = test_quote(64, "sat", QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        // Passing the mint check and stopping at the wallet lookup, which has no wallets
+        let mut payload = test_payload(quote.id, vec![test_proof("a", 64)]);
+        payload.mint = MintUrl::from_str(PRIORITY_MINTS[3]).unwrap();
+        assert_eq!(
+            validate(&state, payload).await.code(),
+            "MINT_UNIT_NOT_SUPPORTED"
+        );
+
+        let mut payload = test_payload(quote.id, vec![test_proof("b", 64)]);
+        payload.mint = MintUrl::from_str("https://other.example.com").unwrap();
+        assert!(matches!(
+            validate(&state, payload).await,
+            PosError::UnsupportedMint(_)
+        ));
+    }
+
+    /// Mint nothing listens on, so the wallet receive fails straight away
+    #[cfg(feature = "server-bin")]
+    const UNREACHABLE_MINT: &str = "http://127.0.0.1:9";
+
+    #[cfg(feature = "server-bin")]
+    async fn disconnect_test_state(
+        dir: &std::path::Path,
+        disconnect_policy: &str,
+    ) -> (CashuPosState, QuoteInfo) {
+        let db = Arc::new(MemoryDb::new());
+        let state = state_with_input_fees(
+            dir,
+            &[(UNREACHABLE_MINT, 0)],
+            json!({ "disconnect_policy": disconnect_policy }),
+            db.clone(),
+        )
+        .await;
+
+        let quote = test_quote(64, "sat", QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        (state, quote)
+    }
+
+    #[cfg(feature = "server-bin")]
+    fn unreachable_mint_payload(quote_id: Uuid) -> PaymentRequestPayload {
+        let mut payload = test_payload(quote_id, vec![test_proof("a", 64)]);
+        payload.mint = MintUrl::from_str(UNREACHABLE_MINT).unwrap();
+        payload
+    }
+
+    /// Start the payment handler and drop it once it awaits the payment task, as when the
+    /// client disconnects, then wait for the payment to finish
+    #[cfg(feature = "server-bin")]
+    async fn pay_and_disconnect(state: &CashuPosState, payload: &PaymentRequestPayload) {
+        let handler = post_receive_payment(
+            State(state.clone()),
+            Method::POST,
+            Uri::from_static("/v1/payment"),
+            Bytes::from(serde_json::to_vec(payload).unwrap()),
+        );
+        assert!(tokio::time::timeout(Duration::ZERO, handler).await.is_err());
+
+        assert_eq!(state.node.drain_payments(Duration::from_secs(30)).await, 0);
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn cancel_policy_refuses_payments_of_gone_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, quote) = disconnect_test_state(dir.path(), "cancel").await;
+
+        let client_gone = CancellationToken::new();
+        client_gone.cancel();
+        let err = process_payment(
+            &state,
+            unreachable_mint_payload(quote.id),
+            &client_gone,
+            &mut PhaseTimer::new(false),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), "CLIENT_DISCONNECTED");
+        assert_eq!(err.status(), StatusCode::REQUEST_TIMEOUT);
+
+        // The wallet receive never started, so the quote was never claimed
+        let mut updates = state.quote_updates.subscribe();
+        pay_and_disconnect(&state, &unreachable_mint_payload(quote.id)).await;
+        assert!(updates.try_recv().is_err());
+        assert_eq!(
+            state.db.get_quote(quote.id).await.unwrap().state,
+            QuoteState::Unpaid
+        );
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn cancel_policy_pays_while_the_client_is_connected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, quote) = disconnect_test_state(dir.path(), "cancel").await;
+
+        let err = process_payment(
+            &state,
+            unreachable_mint_payload(quote.id),
+            &CancellationToken::new(),
+            &mut PhaseTimer::new(false),
+        )
+        .await
+        .unwrap_err();
+        assert_ne!(err.code(), "CLIENT_DISCONNECTED");
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn complete_policy_carries_on_after_the_client_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, quote) = disconnect_test_state(dir.path(), "complete").await;
+
+        let mut updates = state.quote_updates.subscribe();
+        pay_and_disconnect(&state, &unreachable_mint_payload(quote.id)).await;
+
+        // The quote was claimed for the wallet receive, then released when the mint couldn't
+        // be reached
+        assert_eq!(updates.try_recv().unwrap().state, QuoteState::Pending);
+        assert_eq!(updates.try_recv().unwrap().state, QuoteState::Unpaid);
+        assert_eq!(
+            state.db.get_quote(quote.id).await.unwrap().state,
+            QuoteState::Unpaid
+        );
+    }
+
+    /// NUT-10 secret locking a proof to the hash of a preimage (NUT-14)
+    #[cfg(feature = "server-bin")]
+    fn htlc_secret(nonce: &str) -> String {
+        json!([
+            "HTLC",
+            {
+                "nonce": nonce,
+                "data": "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925",
+            }
+        ])
+        .to_string()
+    }
+
+    #[cfg(feature = "server-bin")]
+    async fn preimages_for(state: &CashuPosState, payload: PaymentRequestPayload) -> Vec<String> {
+        match validate_payment(state, &payload, &mut PhaseTimer::new(false)).await {
+            Ok(PaymentCheck::Ready { quote, .. }) => htlc_preimages(state, &quote).unwrap(),
+            Ok(PaymentCheck::AlreadyPaid) => panic!("payment was taken for a retry"),
+            Err(err) => panic!("payment unexpectedly failed validation: {}", err),
+        }
+    }
+
+    /// Create a quote through `/create` with `preimage`, returning its id
+    async fn create_htlc_quote(state: &CashuPosState, preimage: &str) -> Result<Uuid, PosError> {
+        let query = HashMap::from([
+            ("amount".to_string(), "64".to_string()),
+            ("unit".to_string(), "sat".to_string()),
+            ("preimage".to_string(), preimage.to_string()),
+        ]);
+
+        let AmountJson(response, _) =
+            get_channel_quote(State(state.clone()), axum::extract::Query(query)).await?;
+        Ok(response.checking_id)
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn htlc_preimage_of_the_quote_is_passed_to_the_wallet() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(MemoryDb::new());
+        let mut state =
+            state_with_input_fees(dir.path(), &[(MINT, 0)], json!({}), db.clone()).await;
+        state.htlc_preimage_key = Some(HtlcPreimageKey::new([7; 32]));
+
+        let preimage = "00".repeat(32);
+        let id = create_htlc_quote(&state, &preimage).await.unwrap();
+
+        let payload = test_payload(id, vec![test_proof(&htlc_secret("a"), 64)]);
+        assert_eq!(preimages_for(&state, payload).await, vec![preimage]);
+    }
+
+    #[tokio::test]
+    async fn htlc_preimage_is_stored_encrypted() {
+        let db = Arc::new(MemoryDb::new());
+        let mut state = test_state(test_pos_info(json!({})), db.clone());
+        let preimage = "ab".repeat(32);
+
+        // Without a key the preimage can't be stored encrypted, so it isn't taken
+        assert!(matches!(
+            create_htlc_quote(&state, &preimage).await,
+            Err(PosError::InvalidQueryParameter(_))
+        ));
+
+        state.htlc_preimage_key = Some(HtlcPreimageKey::new([7; 32]));
+        let id = create_htlc_quote(&state, &preimage).await.unwrap();
+
+        let stored = db.get_quote(id).await.unwrap();
+        assert!(!serde_json::to_string(&stored).unwrap().contains(&preimage));
+        assert_eq!(htlc_preimages(&state, &stored).unwrap(), vec![preimage]);
+    }
+
+    #[cfg(feature = "store-redb")]
+    #[tokio::test]
+    async fn htlc_preimage_is_not_in_the_database_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cashu-pos.redb");
+        let mut state = test_state(
+            test_pos_info(json!({})),
+            Arc::new(crate::db::Db::new(path.clone()).unwrap()),
+        );
+        state.htlc_preimage_key = Some(HtlcPreimageKey::new([7; 32]));
+        let preimage = "cd".repeat(32);
+
+        let id = create_htlc_quote(&state, &preimage).await.unwrap();
+        let stored = state.db.get_quote(id).await.unwrap();
+        drop(state);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|window| window == needle);
+        assert!(contains(id.as_bytes()));
+        assert!(!contains(preimage.as_bytes()));
+        assert!(contains(stored.htlc_preimage.unwrap().as_bytes()));
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn htlc_locked_proofs_need_a_preimage() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(MemoryDb::new());
+        let state = state_with_input_fees(dir.path(), &[(MINT, 0)], json!({}), db.clone()).await;
+
+        let quote = test_quote(64, "sat", QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        let payload = test_payload(
+            quote.id,
+            vec![test_proof("plain", 32), test_proof(&htlc_secret("b"), 32)],
+        );
+        let err = validate(&state, payload).await;
+        assert_eq!(err.code(), "MISSING_HTLC_PREIMAGE");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        // Unlocked proofs are received without a preimage
+        let payload = test_payload(quote.id, vec![test_proof("plain", 64)]);
+        assert!(preimages_for(&state, payload).await.is_empty());
+    }
+
+    async fn create_quote_timings(diagnostics: bool) -> Option<BTreeMap<String, f64>> {
+        let state = test_state(
+            test_pos_info(json!({ "diagnostics": diagnostics })),
+            Arc::new(MemoryDb::new()),
+        );
+        let query = HashMap::from([
+            ("amount".to_string(), "100".to_string()),
+            ("unit".to_string(), "sat".to_string()),
+        ]);
+
+        let AmountJson(response, _) = get_channel_quote(State(state), axum::extract::Query(query))
+            .await
+            .unwrap();
+        let body = serde_json::to_value(&response).unwrap();
+        assert_eq!(body.get("timings_ms").is_some(), diagnostics);
+
+        response.timings_ms
+    }
+
+    #[tokio::test]
+    async fn quote_timings_only_in_diagnostics_mode() {
+        assert_eq!(create_quote_timings(false).await, None);
+
+        let timings = create_quote_timings(true).await.unwrap();
+        assert_eq!(
+            timings.keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["build_request", "db_write", "parse", "validate"]
+        );
+    }
+
+    #[cfg(feature = "server-bin")]
+    #[tokio::test]
+    async fn payment_validation_marks_its_phases() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(MemoryDb::new());
+        let state = state_with_input_fees(
+            dir.path(),
+            &[(MINT, 0)],
+            json!({ "diagnostics": true }),
+            db.clone(),
+        )
+        .await;
+        let quote = test_quote(64, "sat", QuoteState::Unpaid);
+        db.add_quote(&quote).await.unwrap();
+
+        let mut timer = PhaseTimer::new(state.pos_info().diagnostics);
+        let payload = test_payload(quote.id, vec![test_proof("a", 64)]);
+        assert!(validate_payment(&state, &payload, &mut timer).await.is_ok());
+
+        let timings = timer.finish().unwrap();
+        assert_eq!(
+            timings.keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["db_read", "parse", "validate"]
+        );
+    }
+
+    /// Quote of 64 sat paid with proofs of secrets `a` and `b`
+    async fn paid_quote(db: &dyn QuoteStore) -> QuoteInfo {
+        let quote = QuoteInfo {
+            payment_fingerprint: Some(payment_fingerprint(&vec![
+                test_proof("a", 32),
+                test_proof("b", 32),
+            ])),
+            paid_amount: Some(64),
+            ..test_quote(64, "sat", QuoteState::Paid)
+        };
+        db.add_quote(&quote).await.unwrap();
+        quote
+    }
+
+    #[tokio::test]
+    async fn exact_retry_of_the_paying_payload_succeeds() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+        let quote = paid_quote(db.as_ref()).await;
+
+        // Proofs in another order are still the same payload
+        let payload = test_payload(quote.id, vec![test_proof("b", 32), test_proof("a", 32)]);
+        let check = validate_payment(&state, &payload, &mut PhaseTimer::new(false)).await;
+        assert!(matches!(check, Ok(PaymentCheck::AlreadyPaid)));
+
+        let response = post_receive_payment(
+            State(state.clone()),
+            Method::POST,
+            Uri::from_static("/v1/payment"),
+            Bytes::from(serde_json::to_vec(&payload).unwrap()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            db.get_quote(quote.id).await.unwrap().state,
+            QuoteState::Paid
+        );
+
+        let Json(simulation) = post_simulate_payment(State(state), Json(payload)).await;
+        assert!(simulation.accepted);
+        assert!(simulation.already_paid);
+    }
+
+    #[tokio::test]
+    async fn other_payloads_for_a_paid_quote_fail() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(test_pos_info(json!({})), db.clone());
+        let quote = paid_quote(db.as_ref()).await;
+
+        for proofs in [
+            // Partial overlap with the paying payload
+            vec![test_proof("a", 32), test_proof("c", 32)],
+            vec![test_proof("a", 32)],
+            vec![
+                test_proof("a", 32),
+                test_proof("b", 32),
+                test_proof("c", 32),
+            ],
+            // No overlap at all
+            vec![test_proof("c", 32), test_proof("d", 32)],
+        ] {
+            let err = validate(&state, test_payload(quote.id, proofs)).await;
+            assert_eq!(err.code(), "INVALID_QUOTE_STATE");
+        }
+    }
+
+    #[tokio::test]
+    async fn simulation_answers_like_the_payment_endpoint() {
+        let db = Arc::new(MemoryDb::new());
+        let state = test_state(
+            test_pos_info(json!({ "partial_payments": true })),
+            db.clone(),
+        );
+
+        let unpaid = test_quote(64, "sat", QuoteState::Unpaid);
+        let expired = QuoteInfo {
+            expires_at: Some(unix_time() - 60),
+            ..test_quote(64, "sat", QuoteState::Unpaid)
+        };
+        let partially_paid = partially_paid_quote(db.as_ref(), "first").await;
+        let paid = paid_quote(db.as_ref()).await;
+        for quote in [&unpaid, &expired] {
+            db.add_quote(quote).await.unwrap();
+        }
+
+        let mut unaccepted_mint = test_payload(unpaid.id, vec![test_proof("a", 64)]);
+        unaccepted_mint.mint = MintUrl::from_str("https://other.example.com").unwrap();
+        let mut no_id = test_payload(unpaid.id, vec![test_proof("a", 64)]);
+        no_id.id = None;
+
+        let cases = [
+            (no_id, "MISSING_PARAMETER"),
+            (
+                test_payload(Uuid::new_v4(), vec![test_proof("a", 64)]),
+                "QUOTE_NOT_FOUND",
+            ),
+            (unaccepted_mint, "UNSUPPORTED_MINT"),
+            (
+                test_payload(expired.id, vec![test_proof("a", 64)]),
+                "QUOTE_EXPIRED",
+            ),
+            (
+                test_payload(paid.id, vec![test_proof("c", 64)]),
+                "INVALID_QUOTE_STATE",
+            ),
+            (
+                test_payload(partially_paid.id, vec![test_proof("first", 32)]),
+                "PROOFS_ALREADY_RECEIVED",
+            ),
+            // Passing every check before the wallet lookup
+            (
+                test_payload(unpaid.id, vec![test_proof("a", 64)]),
+                "MINT_UNIT_NOT_SUPPORTED",
+            ),
+        ];
+
+        for (payload, code) in cases {
+            let before = stored_quotes(db.as_ref(), &QuoteFilter::default()).await;
+
+            let Json(simulation) =
+                post_simulate_payment(State(state.clone()), Json(payload.clone())).await;
+            assert!(simulation.simulation);
+            assert!(!simulation.accepted);
+            assert_eq!(simulation.code.as_deref(), Some(code));
+
+            // The simulation leaves every quote as it was
+            let after = stored_quotes(db.as_ref(), &QuoteFilter::default()).await;
+            assert_eq!(
+                serde_json::to_value(&after).unwrap(),
+                serde_json::to_value(&before).unwrap()
+            );
+
+            let response = post_receive_payment(
+                State(state.clone()),
+                Method::POST,
+                Uri::from_static("/v1/payment"),
+                Bytes::from(serde_json::to_vec(&payload).unwrap()),
+            )
+            .await;
+            assert_eq!(response.status().as_u16(), simulation.status, "{}", code);
+
+            let body: crate::error::ErrorBody =
+                serde_json::from_slice(&body_bytes(response).await).unwrap();
+            assert_eq!(body.code, code);
+        }
+    }
 }
@@ -0,0 +1,175 @@
+//! Per client rate limiting of quote creation and payment submission
+//!
+//! Every client address gets a token bucket holding up to a minute's worth of
+//! requests that refills continuously. Only a bounded number of addresses is
+//! tracked, the one seen least recently is forgotten to make room for a new one.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::HeaderValue;
+use axum::http::header::RETRY_AFTER;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+
+use crate::error::PosError;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    /// Requests per minute a client may make to `/create` and `/quotes/bulk`, unlimited if unset
+    pub create_per_minute: Option<u32>,
+    /// Requests per minute a client may make to `/payment`, unlimited if unset
+    pub payment_per_minute: Option<u32>,
+    /// Identify clients by the last `X-Forwarded-For` address, only safe behind a proxy setting it
+    pub trust_proxy: bool,
+    /// Maximum number of client addresses tracked per limit
+    pub max_clients: usize,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            create_per_minute: None,
+            payment_per_minute: None,
+            trust_proxy: false,
+            max_clients: 10_000,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// 0 for no limit, changed by [`RateLimiter::set_per_minute`] on config reload
+    per_minute: AtomicU32,
+    trust_proxy: bool,
+    max_clients: usize,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Limiter allowing `per_minute` requests per client, `None` for no limit
+    pub fn new(per_minute: Option<u32>, settings: &RateLimitSettings) -> Self {
+        Self {
+            per_minute: AtomicU32::new(per_minute.unwrap_or(0)),
+            trust_proxy: settings.trust_proxy,
+            max_clients: settings.max_clients.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Change the limit, `None` for no limit
+    ///
+    /// Clients keep the tokens they have, capped at the new limit on their next request.
+    pub fn set_per_minute(&self, per_minute: Option<u32>) {
+        self.per_minute
+            .store(per_minute.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Take a token for `client`, or the time until one is available
+    fn acquire(&self, client: IpAddr) -> Result<(), Duration> {
+        let per_minute = self.per_minute.load(Ordering::Relaxed);
+        if per_minute == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let capacity = f64::from(per_minute);
+        let refill_per_sec = capacity / 60.0;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if !buckets.contains_key(&client) && buckets.len() >= self.max_clients {
+            let least_recent = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.last_refill)
+                .map(|(ip, _)| *ip);
+
+            if let Some(least_recent) = least_recent {
+                buckets.remove(&least_recent);
+            }
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.last_refill = now;
+
+        match bucket.tokens >= 1.0 {
+            true => {
+                bucket.tokens -= 1.0;
+                Ok(())
+            }
+            false => Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / refill_per_sec,
+            )),
+        }
+    }
+
+    fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        let forwarded = match self.trust_proxy {
+            true => request
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                // The last address is the one added by the proxy, earlier ones are client supplied
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse().ok()),
+            false => None,
+        };
+
+        forwarded.or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip())
+        })
+    }
+}
+
+/// Middleware answering 429 with `Retry-After` once a client exceeds its limit
+///
+/// Requests whose client address is unknown, e.g. when the router is served without
+/// connect info, are not limited.
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(client) = limiter.client_ip(&request) else {
+        return next.run(request).await;
+    };
+
+    match limiter.acquire(client) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            tracing::debug!(
+                "Rate limited {} on {}, retry in {}s",
+                client,
+                request.uri().path(),
+                retry_after
+            );
+
+            let mut response = PosError::RateLimited { retry_after }.into_unlogged_response();
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+            response
+        }
+    }
+}
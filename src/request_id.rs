@@ -0,0 +1,66 @@
+//! Request ids and per request tracing spans
+//!
+//! Each request is handled in a span carrying an id, taken from its `X-Request-Id`
+//! header when a client or proxy sent one, along with the method, path, status and
+//! latency. The id is echoed in the response so a client reporting a failure can be
+//! matched to every log line it caused.
+
+use std::time::Instant;
+
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Instrument;
+use tracing::field::Empty;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest id taken from a request, longer ones are replaced by a generated id
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Middleware running the request in a `request` span and echoing its id
+///
+/// Handlers can add the `quote_id` and `mint` they work on to the span with
+/// `tracing::Span::current().record(..)`.
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.chars().all(|c| c.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        status = Empty,
+        latency_ms = Empty,
+        quote_id = Empty,
+        mint = Empty,
+    );
+
+    let started = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", started.elapsed().as_millis() as u64);
+    span.in_scope(|| tracing::debug!("Handled request"));
+
+    // Only ids of visible ASCII reach here, which are always valid header values
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+
+    response
+}
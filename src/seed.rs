@@ -0,0 +1,79 @@
+//! Wallet seed persistence
+//!
+//! The wallet derives the secrets of received proofs from its seed, so the same
+//! mnemonic has to be used on every start for earlier payments to stay spendable.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use bip39::Mnemonic;
+use cdk::nuts::SecretKey;
+use sha2::{Digest, Sha256};
+
+use crate::htlc::HtlcPreimageKey;
+use crate::setup::write_private_file;
+
+/// Load the wallet mnemonic from the config or `seed_path`, creating the seed file on first run
+///
+/// Fails if the config and the seed file hold different mnemonics rather than picking one.
+pub fn load_or_create_mnemonic(seed_path: &Path, configured: Option<&str>) -> Result<Mnemonic> {
+    let configured = configured
+        .map(|words| {
+            Mnemonic::from_str(words.trim()).map_err(|e| anyhow!("Invalid pos.mnemonic: {}", e))
+        })
+        .transpose()?;
+
+    let stored = match seed_path.exists() {
+        true => {
+            let words = std::fs::read_to_string(seed_path)
+                .map_err(|e| anyhow!("Failed to read seed file {}: {}", seed_path.display(), e))?;
+            let mnemonic = Mnemonic::from_str(words.trim()).map_err(|e| {
+                anyhow!(
+                    "Invalid mnemonic in seed file {}: {}",
+                    seed_path.display(),
+                    e
+                )
+            })?;
+            Some(mnemonic)
+        }
+        false => None,
+    };
+
+    match (configured, stored) {
+        (Some(configured), Some(stored)) if configured != stored => bail!(
+            "pos.mnemonic does not match the seed stored in {}, remove one of them",
+            seed_path.display()
+        ),
+        (Some(mnemonic), _) | (None, Some(mnemonic)) => Ok(mnemonic),
+        (None, None) => {
+            let mnemonic = Mnemonic::generate(12)?;
+            write_private_file(seed_path, &format!("{}\n", mnemonic))?;
+            tracing::info!("Generated new wallet seed at {}", seed_path.display());
+            Ok(mnemonic)
+        }
+    }
+}
+
+/// P2PK key payment requests lock proofs to, derived from the wallet seed
+///
+/// Kept apart from the wallet's own derivation paths by hashing the seed with a
+/// fixed label, so it is stable across restarts without being stored.
+pub fn derive_p2pk_key(mnemonic: &Mnemonic) -> Result<SecretKey> {
+    let mut hasher = Sha256::new();
+    hasher.update(mnemonic.to_seed_normalized(""));
+    hasher.update(b"cashu-pos/p2pk");
+
+    SecretKey::from_slice(&hasher.finalize())
+        .map_err(|e| anyhow!("Failed to derive P2PK key: {}", e))
+}
+
+/// Key HTLC preimages are stored encrypted with, derived from the wallet seed like the
+/// P2PK key
+pub fn derive_htlc_preimage_key(mnemonic: &Mnemonic) -> HtlcPreimageKey {
+    let mut hasher = Sha256::new();
+    hasher.update(mnemonic.to_seed_normalized(""));
+    hasher.update(b"cashu-pos/htlc-preimage");
+
+    HtlcPreimageKey::new(hasher.finalize().into())
+}
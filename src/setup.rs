@@ -0,0 +1,299 @@
+//! First-run configuration wizard behind `cashu-pos setup`
+
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use cdk::mint_url::MintUrl;
+use cdk::wallet::{HttpClient, MintConnector};
+
+use crate::config::{AppConfig, PosConfig};
+
+const DEFAULT_LISTEN_HOST: &str = "127.0.0.1";
+const DEFAULT_LISTEN_PORT: u16 = 3000;
+
+/// Answers given up front, anything missing is prompted for in interactive mode
+#[derive(Debug, Clone, Default)]
+pub struct SetupAnswers {
+    pub listen_host: Option<String>,
+    pub listen_port: Option<u16>,
+    pub payment_url: Option<String>,
+    pub accepted_mints: Vec<String>,
+}
+
+/// Build a config from `answers` and prompts, validate it, and write it to `config_path`
+pub async fn run_setup(
+    config_path: &Path,
+    answers: SetupAnswers,
+    interactive: bool,
+    force: bool,
+) -> Result<AppConfig> {
+    if config_path.exists() && !force {
+        bail!(
+            "Config already exists at {}, pass --force to overwrite it",
+            config_path.display()
+        );
+    }
+
+    let listen_host = match answers.listen_host {
+        Some(host) => host,
+        None if interactive => prompt("Listen host", Some(DEFAULT_LISTEN_HOST))?,
+        None => DEFAULT_LISTEN_HOST.to_string(),
+    };
+
+    let listen_port = match answers.listen_port {
+        Some(port) => port,
+        None if interactive => {
+            let port = prompt("Listen port", Some(&DEFAULT_LISTEN_PORT.to_string()))?;
+            port.parse()
+                .map_err(|_| anyhow!("Invalid listen port: {}", port))?
+        }
+        None => DEFAULT_LISTEN_PORT,
+    };
+
+    let payment_url = match answers.payment_url {
+        Some(url) => url,
+        None if interactive => prompt(
+            "Public URL wallets will reach this server at (e.g. https://pos.example.com)",
+            None,
+        )?,
+        None => bail!("--payment-url is required in non-interactive mode"),
+    };
+
+    let mut accepted_mints = Vec::new();
+
+    for mint in answers.accepted_mints {
+        check_mint(&mint).await?;
+        accepted_mints.push(mint);
+    }
+
+    if interactive && accepted_mints.is_empty() {
+        loop {
+            let mint = prompt("Accepted mint URL (leave empty to finish)", Some(""))?;
+
+            if mint.is_empty() {
+                if accepted_mints.is_empty() {
+                    println!("At least one mint is required");
+                    continue;
+                }
+                break;
+            }
+
+            match check_mint(&mint).await {
+                Ok(()) => accepted_mints.push(mint),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    let config = AppConfig {
+        pos: PosConfig {
+            listen_host,
+            listen_port,
+            payment_url,
+            accepted_mints,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    config.validate()?;
+
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    write_private_file(config_path, &toml::to_string_pretty(&config)?)?;
+
+    // Make sure the server will read back exactly what was written
+    let written = AppConfig::new(Some(config_path))?;
+    written.validate()?;
+
+    println!("Wrote configuration to {}", config_path.display());
+    println!("Next steps:");
+    println!(
+        "  - Make sure {} reaches this server at {}:{}",
+        written.pos.payment_url, written.pos.listen_host, written.pos.listen_port
+    );
+    println!("  - Start the server with: cashu-pos");
+
+    Ok(written)
+}
+
+/// Write `contents` to `path`, readable and writable by the owner only on unix
+pub fn write_private_file(path: &Path, contents: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// Check a mint url parses and the mint answers its info endpoint
+async fn check_mint(mint: &str) -> Result<()> {
+    let mint_url =
+        MintUrl::from_str(mint).map_err(|e| anyhow!("Invalid mint url {}: {}", mint, e))?;
+
+    let info = HttpClient::new(mint_url)
+        .get_mint_info()
+        .await
+        .map_err(|e| anyhow!("Could not reach mint {}: {}", mint, e))?;
+
+    println!(
+        "Found mint {} at {}",
+        info.name.unwrap_or_else(|| "(unnamed)".to_string()),
+        mint
+    );
+
+    Ok(())
+}
+
+fn prompt(question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) if !default.is_empty() => print!("{} [{}]: ", question, default),
+        _ => print!("{}: ", question),
+    }
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+
+    match (answer.is_empty(), default) {
+        (true, Some(default)) => Ok(default.to_string()),
+        _ => Ok(answer.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::routing::get;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Serve a mint info endpoint on a local port, returning the mint url
+    async fn stub_mint() -> String {
+        let router = Router::new().route(
+            "/v1/info",
+            get(|| async { axum::Json(json!({ "name": "Test mint" })) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        format!("http://{}", addr)
+    }
+
+    fn answers(mint: &str) -> SetupAnswers {
+        SetupAnswers {
+            listen_host: None,
+            listen_port: Some(4000),
+            payment_url: Some("https://pos.example.com".to_string()),
+            accepted_mints: vec![mint.to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn non_interactive_setup_writes_a_valid_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("nested").join("config.toml");
+        let mint = stub_mint().await;
+
+        let config = run_setup(&config_path, answers(&mint), false, false)
+            .await
+            .unwrap();
+        assert_eq!(config.pos.listen_host, DEFAULT_LISTEN_HOST);
+        assert_eq!(config.pos.listen_port, 4000);
+        assert_eq!(config.pos.payment_url, "https://pos.example.com");
+        assert_eq!(config.pos.accepted_mints, vec![mint]);
+
+        let written = AppConfig::new(Some(&config_path)).unwrap();
+        written.validate().unwrap();
+        assert_eq!(written.pos.accepted_mints, config.pos.accepted_mints);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&config_path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn setup_only_overwrites_with_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(&config_path, "# hand edited\n").unwrap();
+        let mint = stub_mint().await;
+
+        assert!(
+            run_setup(&config_path, answers(&mint), false, false)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            std::fs::read_to_string(&config_path).unwrap(),
+            "# hand edited\n"
+        );
+
+        run_setup(&config_path, answers(&mint), false, true)
+            .await
+            .unwrap();
+        AppConfig::new(Some(&config_path))
+            .unwrap()
+            .validate()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn setup_writes_nothing_the_server_would_reject() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        let mint = stub_mint().await;
+
+        let missing_url = SetupAnswers {
+            payment_url: None,
+            ..answers(&mint)
+        };
+        let no_mints = SetupAnswers {
+            accepted_mints: vec![],
+            ..answers(&mint)
+        };
+        let bad_host = SetupAnswers {
+            listen_host: Some("not a host".to_string()),
+            ..answers(&mint)
+        };
+        let bad_url = SetupAnswers {
+            payment_url: Some("pos.example.com".to_string()),
+            ..answers(&mint)
+        };
+        // Nothing listens on the discard port
+        let unreachable_mint = answers("http://127.0.0.1:9");
+
+        for answers in [missing_url, no_mints, bad_host, bad_url, unreachable_mint] {
+            assert!(
+                run_setup(&config_path, answers.clone(), false, false)
+                    .await
+                    .is_err(),
+                "{:?} was accepted",
+                answers
+            );
+            assert!(!config_path.exists());
+        }
+    }
+}
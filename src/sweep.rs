@@ -0,0 +1,391 @@
+//! Scheduled sweep of received funds to a lightning address
+//!
+//! Wallets whose balance exceeds the threshold are melted to an invoice fetched
+//! from the lightning address (LUD-16), so ecash doesn't stay in the POS wallet.
+//! Every attempt is recorded, and a wallet whose sweep failed is retried with an
+//! exponentially growing delay rather than on every tick. The sweep itself needs the
+//! `sweep` feature, its settings are always available.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "sweep")]
+pub use self::task::{LightningAddress, spawn_sweep};
+use crate::types::{AmountCfg, ConfigDuration};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SweepSettings {
+    /// Lightning address (`user@domain`) funds are swept to, sweeping is off if unset
+    pub lightning_address: Option<String>,
+    /// Balance in sats a wallet must exceed before it is swept, e.g. `10000` or `"10000 sat"`
+    pub threshold: AmountCfg,
+    /// Time between balance checks, in seconds or e.g. `"1h"`
+    pub interval_seconds: ConfigDuration,
+    /// Longest delay in seconds before a wallet whose sweeps keep failing is retried
+    pub max_backoff_seconds: u64,
+}
+
+impl Default for SweepSettings {
+    fn default() -> Self {
+        Self {
+            lightning_address: None,
+            threshold: AmountCfg {
+                value: 10_000,
+                unit: None,
+            },
+            interval_seconds: ConfigDuration::from_secs(3600),
+            max_backoff_seconds: 86_400,
+        }
+    }
+}
+
+/// The lightning address client and the sweep task, which need an HTTP client
+#[cfg(feature = "sweep")]
+mod task {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::{Result, anyhow, bail};
+    use cdk::mint_url::MintUrl;
+    use cdk::nuts::{CurrencyUnit, MeltQuoteState};
+    use cdk::util::unix_time;
+    use cdk::wallet::Wallet;
+    use serde::Deserialize;
+    use tokio::task::JoinHandle;
+    use tokio::time::Instant;
+    use tokio_util::sync::CancellationToken;
+    use uuid::Uuid;
+
+    use super::SweepSettings;
+    use crate::CashuPos;
+    use crate::db::QuoteStore;
+    use crate::fees;
+    use crate::types::Sweep;
+
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    impl SweepSettings {
+        /// Time between balance checks, at least a second
+        fn interval(&self) -> Duration {
+            self.interval_seconds
+                .as_duration()
+                .max(Duration::from_secs(1))
+        }
+    }
+
+    /// Millisats per minor unit of the units that can be swept over lightning
+    fn msat_per_unit(unit: &CurrencyUnit) -> Option<u64> {
+        match unit {
+            CurrencyUnit::Sat => Some(1000),
+            CurrencyUnit::Msat => Some(1),
+            _ => None,
+        }
+    }
+
+    /// LUD-06 pay request served at the lightning address
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct PayRequest {
+        callback: String,
+        /// Millisats
+        min_sendable: u64,
+        /// Millisats
+        max_sendable: u64,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct InvoiceResponse {
+        pr: String,
+    }
+
+    /// Fetches invoices from a lightning address
+    pub struct LightningAddress {
+        client: reqwest::Client,
+        address: String,
+        url: String,
+    }
+
+    impl LightningAddress {
+        pub fn new(address: &str) -> Result<Self> {
+            let (user, domain) = address
+                .split_once('@')
+                .filter(|(user, domain)| !user.is_empty() && !domain.is_empty())
+                .ok_or(anyhow!("Invalid lightning address {}", address))?;
+
+            let scheme = if domain.ends_with(".onion") {
+                "http"
+            } else {
+                "https"
+            };
+
+            Ok(Self {
+                client: reqwest::Client::new(),
+                address: address.to_string(),
+                url: format!("{}://{}/.well-known/lnurlp/{}", scheme, domain, user),
+            })
+        }
+
+        async fn get_json(&self, url: &str) -> Result<serde_json::Value> {
+            let body: serde_json::Value = self
+                .client
+                .get(url)
+                .timeout(REQUEST_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            // LNURL services report errors in the body, often with a 200 status
+            if body.get("status").and_then(|status| status.as_str()) == Some("ERROR") {
+                bail!(
+                    "{} returned an error: {}",
+                    self.address,
+                    body.get("reason")
+                        .and_then(|reason| reason.as_str())
+                        .unwrap_or("no reason given")
+                );
+            }
+
+            Ok(body)
+        }
+
+        /// Amounts in millisats the address accepts, as `(min, max)`
+        pub async fn sendable(&self) -> Result<(u64, u64)> {
+            let pay_request: PayRequest = serde_json::from_value(self.get_json(&self.url).await?)?;
+
+            Ok((pay_request.min_sendable, pay_request.max_sendable))
+        }
+
+        /// Bolt11 invoice for `amount_msat`
+        pub async fn invoice(&self, amount_msat: u64) -> Result<String> {
+            let pay_request: PayRequest = serde_json::from_value(self.get_json(&self.url).await?)?;
+
+            if amount_msat < pay_request.min_sendable || amount_msat > pay_request.max_sendable {
+                bail!(
+                    "{} accepts {} to {} msat, not {}",
+                    self.address,
+                    pay_request.min_sendable,
+                    pay_request.max_sendable,
+                    amount_msat
+                );
+            }
+
+            let mut callback = url::Url::parse(&pay_request.callback)?;
+            callback
+                .query_pairs_mut()
+                .append_pair("amount", &amount_msat.to_string());
+
+            let invoice: InvoiceResponse =
+                serde_json::from_value(self.get_json(callback.as_str()).await?)?;
+
+            Ok(invoice.pr)
+        }
+    }
+
+    /// Consecutive failed sweeps of a wallet and when it may be tried again
+    struct Backoff {
+        failures: u32,
+        retry_at: Instant,
+    }
+
+    /// Check the balances every `interval_seconds` and sweep wallets above the threshold
+    /// until `shutdown` is cancelled
+    ///
+    /// Does nothing if no lightning address is configured.
+    pub fn spawn_sweep(
+        pos: Arc<CashuPos>,
+        db: Arc<dyn QuoteStore>,
+        settings: SweepSettings,
+        shutdown: CancellationToken,
+    ) -> Result<Option<JoinHandle<()>>> {
+        let Some(address) = &settings.lightning_address else {
+            return Ok(None);
+        };
+
+        let address = LightningAddress::new(address)?;
+
+        Ok(Some(tokio::spawn(async move {
+            let interval = settings.interval();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            let mut backoff: HashMap<(MintUrl, CurrencyUnit), Backoff> = HashMap::new();
+
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = ticker.tick() => {
+                        sweep_wallets(&pos, db.as_ref(), &address, &settings, &mut backoff).await;
+                    }
+                }
+            }
+
+            tracing::debug!("Sweep task stopped");
+        })))
+    }
+
+    async fn sweep_wallets(
+        pos: &CashuPos,
+        db: &dyn QuoteStore,
+        address: &LightningAddress,
+        settings: &SweepSettings,
+        backoff: &mut HashMap<(MintUrl, CurrencyUnit), Backoff>,
+    ) {
+        for wallet in pos.wallet.get_wallets().await {
+            let Some(msat_per_unit) = msat_per_unit(&wallet.unit) else {
+                continue;
+            };
+
+            let key = (wallet.mint_url.clone(), wallet.unit.clone());
+
+            if backoff
+                .get(&key)
+                .is_some_and(|backoff| Instant::now() < backoff.retry_at)
+            {
+                continue;
+            }
+
+            // Held from the balance read to the melt so consolidation, withdrawals and sends
+            // can't spend the proofs in between
+            let _spending = pos.consolidation.lock().await;
+
+            let balance: u64 = match wallet.total_balance().await {
+                Ok(balance) => balance.into(),
+                Err(e) => {
+                    tracing::warn!("Could not read balance of {}: {}", wallet.mint_url, e);
+                    continue;
+                }
+            };
+
+            if balance.saturating_mul(msat_per_unit) / 1000 <= settings.threshold.value {
+                continue;
+            }
+
+            let mut sweep = Sweep {
+                id: Uuid::new_v4(),
+                mint: wallet.mint_url.clone(),
+                unit: wallet.unit.clone(),
+                lightning_address: address.address.clone(),
+                amount: 0,
+                fee_paid: 0,
+                melt_quote_id: None,
+                state: None,
+                preimage: None,
+                error: None,
+                created_at: unix_time(),
+            };
+
+            match sweep_wallet(pos, &wallet, address, balance, msat_per_unit, &mut sweep).await {
+                Ok(()) => {
+                    tracing::info!(
+                        "Swept {} {} from {} to {} ({:?})",
+                        sweep.amount,
+                        sweep.unit,
+                        sweep.mint,
+                        sweep.lightning_address,
+                        sweep.state
+                    );
+                    backoff.remove(&key);
+                }
+                Err(e) => {
+                    let failures = backoff.get(&key).map_or(0, |backoff| backoff.failures) + 1;
+                    let delay = settings
+                        .interval()
+                        .saturating_mul(1 << failures.min(16))
+                        .min(Duration::from_secs(settings.max_backoff_seconds));
+
+                    tracing::warn!(
+                        "Sweep of {} {} from {} failed {} times, retrying in {:?}: {}",
+                        balance,
+                        wallet.unit,
+                        wallet.mint_url,
+                        failures,
+                        delay,
+                        e
+                    );
+
+                    sweep.error = Some(e.to_string());
+                    backoff.insert(
+                        key,
+                        Backoff {
+                            failures,
+                            retry_at: Instant::now() + delay,
+                        },
+                    );
+                }
+            }
+
+            if let Err(e) = db.add_sweep(&sweep).await {
+                tracing::error!("Failed to record sweep {}: {}", sweep.id, e);
+            }
+        }
+    }
+
+    /// Melt the balance of `wallet` minus fees to an invoice from `address`, filling in `sweep`
+    async fn sweep_wallet(
+        pos: &CashuPos,
+        wallet: &Wallet,
+        address: &LightningAddress,
+        balance: u64,
+        msat_per_unit: u64,
+        sweep: &mut Sweep,
+    ) -> Result<()> {
+        let (min_sendable, max_sendable) = address.sendable().await?;
+
+        // The fee reserve is only known from a melt quote, so quote the whole balance first
+        let probe_amount = balance.min(max_sendable / msat_per_unit);
+        let probe_invoice = address.invoice(probe_amount * msat_per_unit).await?;
+        let fee_reserve: u64 = wallet
+            .melt_quote(probe_invoice, None)
+            .await?
+            .fee_reserve
+            .into();
+
+        // Spending every proof is the most the input fees can be
+        let proof_count = wallet.get_unspent_proofs().await?.len() as u64;
+        let input_fee_ppk = pos.input_fee_ppk(&wallet.mint_url, &wallet.unit).await?;
+        let input_fee = fees::input_fee(proof_count, input_fee_ppk);
+
+        let amount = probe_amount
+            .saturating_sub(fee_reserve)
+            .saturating_sub(input_fee);
+
+        if amount == 0 || amount * msat_per_unit < min_sendable {
+            bail!(
+                "Nothing left to sweep after a fee reserve of {} and input fees of {}",
+                fee_reserve,
+                input_fee
+            );
+        }
+
+        sweep.amount = amount;
+
+        let invoice = address.invoice(amount * msat_per_unit).await?;
+        let melt_quote = wallet.melt_quote(invoice, None).await?;
+        sweep.melt_quote_id = Some(melt_quote.id.clone());
+
+        if u64::from(melt_quote.amount) != amount {
+            bail!(
+                "Invoice from {} is for {} instead of {}",
+                address.address,
+                melt_quote.amount,
+                amount
+            );
+        }
+
+        let melted = wallet.melt(&melt_quote.id).await?;
+
+        sweep.state = Some(melted.state);
+        sweep.amount = melted.amount.into();
+        sweep.fee_paid = melted.fee_paid.into();
+        sweep.preimage = melted.preimage;
+
+        if melted.state == MeltQuoteState::Unpaid {
+            bail!("The mint could not pay the invoice");
+        }
+
+        Ok(())
+    }
+}
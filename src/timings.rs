@@ -0,0 +1,66 @@
+//! Per-phase request timings reported in diagnostics mode
+
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Accumulates how long each phase of a request took
+///
+/// A disabled timer holds nothing and every call on it is a no-op.
+#[derive(Debug)]
+pub struct PhaseTimer {
+    inner: Option<(Instant, BTreeMap<&'static str, f64>)>,
+}
+
+impl PhaseTimer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            inner: enabled.then(|| (Instant::now(), BTreeMap::new())),
+        }
+    }
+
+    /// Attribute the time since the previous mark to `phase`
+    pub fn mark(&mut self, phase: &'static str) {
+        if let Some((last, phases)) = self.inner.as_mut() {
+            let now = Instant::now();
+            *phases.entry(phase).or_default() += now.duration_since(*last).as_secs_f64() * 1000.0;
+            *last = now;
+        }
+    }
+
+    /// Milliseconds spent in each phase, `None` when diagnostics are off
+    pub fn finish(self) -> Option<BTreeMap<String, f64>> {
+        self.inner.map(|(_, phases)| {
+            phases
+                .into_iter()
+                .map(|(phase, ms)| (phase.to_string(), ms))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_timer_reports_nothing() {
+        let mut timer = PhaseTimer::new(false);
+        timer.mark("parse");
+        assert!(timer.inner.is_none());
+        assert_eq!(timer.finish(), None);
+    }
+
+    #[test]
+    fn enabled_timer_reports_each_phase_once() {
+        let mut timer = PhaseTimer::new(true);
+        timer.mark("parse");
+        timer.mark("db_read");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        timer.mark("parse");
+
+        let timings = timer.finish().unwrap();
+        assert_eq!(timings.keys().collect::<Vec<_>>(), vec!["db_read", "parse"]);
+        // Repeated phases add up
+        assert!(timings["parse"] >= 5.0);
+    }
+}
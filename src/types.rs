@@ -1,28 +1,1681 @@
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
 use cdk::mint_url::MintUrl;
-use cdk::nuts::CurrencyUnit;
-use serde::{Deserialize, Serialize};
+use cdk::nuts::{CurrencyUnit, Id, MeltQuoteState, Proofs, PublicKey};
+use chrono::DateTime;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::capture::CaptureSettings;
+use crate::consolidation::ConsolidationSettings;
+use crate::error::PosError;
+use crate::exchange_rate::ExchangeRateSettings;
+use crate::log_throttle::LogThrottleSettings;
+use crate::rate_limit::RateLimitSettings;
+use crate::sweep::SweepSettings;
+use crate::validation::ValidationRule;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct QuoteInfo {
     pub id: Uuid,
-    pub amount: u64,
+    /// Serialized as separate `amount` and `unit` fields
+    #[serde(flatten)]
+    pub amount: PosAmount,
     pub state: QuoteState,
+    /// Free-form label grouping quotes created together, e.g. a sticker batch
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Preimage agreed out of band for redeeming HTLC-locked proofs (NUT-14), encrypted
+    /// with the server's [`HtlcPreimageKey`](crate::htlc::HtlcPreimageKey)
+    #[serde(default)]
+    pub htlc_preimage: Option<String>,
+    /// Hash of the proof secrets that paid the quote, see [`payment_fingerprint`]
+    #[serde(default)]
+    pub payment_fingerprint: Option<String>,
+    /// Unix time after which an unpaid quote no longer accepts payment
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// Note for the payer included in the payment request
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Receipt number assigned when the quote was paid
+    #[serde(default)]
+    pub receipt: Option<Receipt>,
+    /// Total value of the proofs received for the quote so far, in the quote's unit
+    #[serde(
+        default,
+        serialize_with = "serialize_optional_amount",
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    pub paid_amount: Option<u64>,
+    /// Unix time the quote was created, unset for quotes stored before it was recorded
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    /// Unix time the quote became `Paid`
+    #[serde(default)]
+    pub paid_at: Option<u64>,
+    /// Notified once the quote is paid instead of the configured `webhook_url`
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Caller's own identifier for the quote, such as an order number
+    #[serde(default)]
+    pub reference: Option<String>,
+    /// Mints the quote accepts payment from, every accepted mint if unset
+    #[serde(default)]
+    pub mints: Option<Vec<MintUrl>>,
+    /// Fiat price the quote amount was converted from
+    #[serde(default)]
+    pub fiat: Option<FiatPrice>,
+    /// Equivalent amounts in other units the quote may be paid in instead
+    #[serde(default)]
+    pub alternative_amounts: Option<Vec<PosAmount>>,
+    /// Amount asked for before the estimated input fees were added, set on fee-inclusive quotes
+    #[serde(
+        default,
+        serialize_with = "serialize_optional_amount",
+        deserialize_with = "deserialize_optional_amount"
+    )]
+    pub net_amount: Option<u64>,
+}
+
+/// Fiat amount a quote was priced in and the rate used to convert it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct FiatPrice {
+    #[serde(flatten)]
+    pub amount: PosAmount,
+    /// Price of one bitcoin in major units of the fiat currency at creation
+    pub btc_price: f64,
+}
+
+impl QuoteInfo {
+    /// Mints a payment for the quote may come from, in priority order
+    pub fn accepted_mints<'a>(&'a self, pos_info: &'a CashuPosInfo) -> &'a [MintUrl] {
+        self.mints.as_deref().unwrap_or(&pos_info.accepted_mints)
+    }
+
+    /// Amount due when paying in `unit`
+    ///
+    /// Until a first payment settles the unit, a multi-unit quote may be paid in any
+    /// unit it has an alternative amount for.
+    pub fn amount_in(&self, unit: &CurrencyUnit) -> Option<&PosAmount> {
+        if self.amount.unit == *unit {
+            return Some(&self.amount);
+        }
+
+        match self.paid_amount.unwrap_or_default() {
+            0 => self
+                .alternative_amounts
+                .iter()
+                .flatten()
+                .find(|amount| amount.unit == *unit),
+            _ => None,
+        }
+    }
+
+    /// Make the amount in `unit` the quote amount, keeping the previous one as an alternative
+    ///
+    /// Returns false if the quote can't be paid in `unit`.
+    pub fn settle_in(&mut self, unit: &CurrencyUnit) -> bool {
+        if self.amount.unit == *unit {
+            return true;
+        }
+
+        let position = match self.paid_amount.unwrap_or_default() {
+            0 => self
+                .alternative_amounts
+                .iter()
+                .flatten()
+                .position(|amount| amount.unit == *unit),
+            _ => None,
+        };
+
+        match (position, self.alternative_amounts.as_mut()) {
+            (Some(position), Some(alternatives)) => {
+                std::mem::swap(&mut self.amount, &mut alternatives[position]);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Which timestamp a [`QuoteFilter`] time range applies to
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteTimeField {
+    #[default]
+    Created,
+    Paid,
+}
+
+impl FromStr for QuoteTimeField {
+    type Err = PosError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created" => Ok(Self::Created),
+            "paid" => Ok(Self::Paid),
+            _ => Err(PosError::InvalidQueryParameter(format!(
+                "Unknown time field: {}. Expected created or paid",
+                s
+            ))),
+        }
+    }
+}
+
+/// Predicates for listing quotes, unset fields match every quote
+#[derive(Debug, Clone, Default)]
+pub struct QuoteFilter {
+    pub state: Option<QuoteState>,
+    /// Only quotes created with this tag, e.g. one batch of bulk quotes
+    pub tag: Option<String>,
+    /// Inclusive lower bound on the `time_field` timestamp, in unix time
+    pub from: Option<u64>,
+    /// Exclusive upper bound on the `time_field` timestamp, in unix time
+    pub to: Option<u64>,
+    pub time_field: QuoteTimeField,
+}
+
+impl QuoteFilter {
+    /// Whether `quote` matches at unix time `now`, quotes without the timestamp never match a
+    /// time range
+    pub fn matches(&self, quote: &QuoteInfo, now: u64) -> bool {
+        if self.state.is_some_and(|state| quote.state_at(now) != state) {
+            return false;
+        }
+
+        if self
+            .tag
+            .as_ref()
+            .is_some_and(|tag| quote.tag.as_ref() != Some(tag))
+        {
+            return false;
+        }
+
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+
+        let timestamp = match self.time_field {
+            QuoteTimeField::Created => quote.created_at,
+            QuoteTimeField::Paid => quote.paid_at,
+        };
+
+        timestamp.is_some_and(|timestamp| {
+            self.from.is_none_or(|from| timestamp >= from)
+                && self.to.is_none_or(|to| timestamp < to)
+        })
+    }
+}
+
+/// Daily receipt number, numbers start at 1 each day and have no gaps
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Receipt {
+    /// Local date of the payment as `YYYY-MM-DD`
+    pub date: String,
+    pub number: u64,
+}
+
+/// Audit record of a payload redeemed for a quote
+///
+/// Only the Y value of each proof is kept, never its secret or signature, so the record
+/// cannot be used to spend the proofs even if the wallet had not swapped them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedPayment {
+    pub mint: MintUrl,
+    pub unit: CurrencyUnit,
+    /// Total value of the proofs in minor units of `unit`
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub amount: u64,
+    /// See [`payment_fingerprint`]
+    pub payment_fingerprint: String,
+    pub received_at: u64,
+    pub proofs: Vec<ReceivedProof>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedProof {
+    /// Hash of the secret to the curve, the id the mint tracks the proof's state by
+    pub y: PublicKey,
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub amount: u64,
+    pub keyset_id: Id,
+}
+
+/// Funds melted out of a wallet to pay a lightning invoice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub id: Uuid,
+    pub mint: MintUrl,
+    pub unit: CurrencyUnit,
+    pub bolt11: String,
+    pub melt_quote_id: String,
+    pub state: MeltQuoteState,
+    /// Invoice amount in minor units of `unit`
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub amount: u64,
+    /// Lightning fee charged by the mint, in minor units of `unit`
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub fee_paid: u64,
+    /// Invoice preimage, proof that the invoice was paid
+    pub preimage: Option<String>,
+    pub created_at: u64,
+}
+
+/// Ecash exported from a wallet as a cashu token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SentToken {
+    pub id: Uuid,
+    pub mint: MintUrl,
     pub unit: CurrencyUnit,
+    /// Value of the token in minor units of `unit`
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub amount: u64,
+    pub memo: Option<String>,
+    /// Serialized cashu token, spendable by whoever holds it
+    pub token: String,
+    pub created_at: u64,
+}
+
+/// Attempt to move a wallet's balance to the sweep lightning address
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sweep {
+    pub id: Uuid,
+    pub mint: MintUrl,
+    pub unit: CurrencyUnit,
+    pub lightning_address: String,
+    /// Invoice amount in minor units of `unit`, 0 if the attempt failed before an invoice was fetched
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub amount: u64,
+    /// Lightning fee charged by the mint, in minor units of `unit`
+    #[serde(
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub fee_paid: u64,
+    pub melt_quote_id: Option<String>,
+    /// State of the melt, `None` if the attempt failed before melting
+    pub state: Option<MeltQuoteState>,
+    /// Invoice preimage, proof that the invoice was paid
+    pub preimage: Option<String>,
+    /// Why the attempt failed
+    pub error: Option<String>,
+    pub created_at: u64,
+}
+
+/// Local date at unix time `now` for a timezone `utc_offset_minutes` ahead of UTC
+pub fn receipt_date(now: u64, utc_offset_minutes: i32) -> String {
+    let local = now as i64 + i64::from(utc_offset_minutes) * 60;
+
+    DateTime::from_timestamp(local, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+impl QuoteInfo {
+    /// State of the quote at unix time `now`, unpaid quotes past their expiry report `Expired`
+    pub fn state_at(&self, now: u64) -> QuoteState {
+        match (self.state, self.expires_at) {
+            (QuoteState::Unpaid, Some(expires_at)) if now >= expires_at => QuoteState::Expired,
+            (state, _) => state,
+        }
+    }
+}
+
+/// Amount in minor units together with the unit it is denominated in
+///
+/// Arithmetic and comparisons between amounts of different units fail with
+/// [`PosError::UnitMismatch`] instead of silently mixing units.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct PosAmount {
+    #[serde(
+        rename = "amount",
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = AmountValue))]
+    pub value: u64,
+    #[cfg_attr(feature = "openapi", schema(value_type = String, example = "sat"))]
+    pub unit: CurrencyUnit,
+}
+
+impl PosAmount {
+    pub fn new(value: u64, unit: CurrencyUnit) -> Self {
+        Self { value, unit }
+    }
+
+    pub fn zero(unit: CurrencyUnit) -> Self {
+        Self::new(0, unit)
+    }
+
+    /// Fail unless `other` is denominated in the same unit
+    pub fn ensure_same_unit(&self, other: &Self) -> Result<(), PosError> {
+        match self.unit == other.unit {
+            true => Ok(()),
+            false => Err(PosError::UnitMismatch {
+                expected: self.unit.clone(),
+                received: other.unit.clone(),
+            }),
+        }
+    }
+
+    pub fn checked_cmp(&self, other: &Self) -> Result<std::cmp::Ordering, PosError> {
+        self.ensure_same_unit(other)?;
+        Ok(self.value.cmp(&other.value))
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<Self, PosError> {
+        self.ensure_same_unit(other)?;
+        let value = self
+            .value
+            .checked_add(other.value)
+            .ok_or_else(|| PosError::InvalidAmount("Amount overflow".to_string()))?;
+
+        Ok(Self::new(value, self.unit.clone()))
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Result<Self, PosError> {
+        self.ensure_same_unit(other)?;
+        let value = self
+            .value
+            .checked_sub(other.value)
+            .ok_or_else(|| PosError::InvalidAmount("Amount underflow".to_string()))?;
+
+        Ok(Self::new(value, self.unit.clone()))
+    }
+}
+
+impl fmt::Display for PosAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_amount(self.value, &self.unit))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
 pub struct ChannelQuoteRequest {
+    /// Amount in minor units of `unit`, required unless `fiat_amount` is given
+    pub amount: Option<u64>,
+    /// Decimal price in `fiat_currency` converted to `unit` at the current exchange rate
+    pub fiat_amount: Option<String>,
+    /// `usd` or `eur`, the currency of `fiat_amount`
+    pub fiat_currency: Option<String>,
+    /// Currency unit, `sat` if not provided
+    pub unit: Option<String>,
+    /// Note for the payer, shown by wallets as the payment request description
+    pub memo: Option<String>,
+    /// Ask the customer to cover the input fees of their payload
+    #[serde(default)]
+    pub fee_inclusive: bool,
+    /// Preimage for redeeming HTLC-locked proofs
+    pub preimage: Option<String>,
+    /// URL notified once this quote is paid, overriding the configured one
+    pub webhook_url: Option<String>,
+    /// Caller's own identifier for the quote, see `GET /quote/by-reference/{reference}`
+    pub reference: Option<String>,
+    /// Subset of the accepted mints the quote may be paid from
+    pub mints: Option<Vec<String>>,
+    /// Also accept payment in every other accepted unit at its current equivalent
+    #[serde(default)]
+    pub multi_unit: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkQuoteRequest {
+    pub count: u64,
+    /// Amount of each quote in minor units of `unit`
     pub amount: u64,
+    pub unit: Option<String>,
+    pub tag: Option<String>,
+    /// Note wallets show the payer
+    pub memo: Option<String>,
+    /// Add the estimated input fees of a payment to the amount of each quote
+    #[serde(default)]
+    pub fee_inclusive: bool,
+    /// Seconds the quotes accept payment for instead of `quote_expiry_seconds`, e.g. a long
+    /// expiry for printed stickers
+    pub expiry_seconds: Option<u64>,
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
 pub enum QuoteState {
     Unpaid,
+    /// Proofs for the quote are being redeemed
+    Pending,
+    /// Payments so far cover only part of the quote amount
+    PartiallyPaid,
     Paid,
+    Expired,
+    /// Voided by the merchant before it was paid
+    Cancelled,
+    /// Proofs were redeemed but the payment could not be recorded, its payment records show
+    /// what was received
+    Unsettled,
+}
+
+impl FromStr for QuoteState {
+    type Err = PosError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Unpaid" => Ok(Self::Unpaid),
+            "Pending" => Ok(Self::Pending),
+            "PartiallyPaid" => Ok(Self::PartiallyPaid),
+            "Paid" => Ok(Self::Paid),
+            "Expired" => Ok(Self::Expired),
+            "Cancelled" => Ok(Self::Cancelled),
+            "Unsettled" => Ok(Self::Unsettled),
+            _ => Err(PosError::InvalidQueryParameter(format!(
+                "Unknown quote state: {}. Expected Unpaid, Pending, PartiallyPaid, Paid, Expired, \
+                 Cancelled or Unsettled",
+                s
+            ))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CashuPosInfo {
+    /// Accepted mints in priority order
     pub accepted_mints: Vec<MintUrl>,
+    /// Maximum number of mints listed in a payment request, unlimited if unset
+    #[serde(default)]
+    pub max_mints_per_request: Option<usize>,
+    /// Encoded payment request length above which a warning is logged
+    #[serde(default = "default_payment_request_warn_length")]
+    pub payment_request_warn_length: usize,
+    /// Units quotes may be created in, a wallet is kept for each with every accepted mint
+    #[serde(default = "default_accepted_units")]
+    pub accepted_units: Vec<CurrencyUnit>,
+    /// Longest quote memo accepted, in characters
+    #[serde(default = "default_max_memo_length")]
+    pub max_memo_length: usize,
+    #[serde(default)]
+    pub log_throttle: LogThrottleSettings,
+    #[serde(default)]
+    pub disconnect_policy: DisconnectPolicy,
+    /// Validation rules that only report would-be rejections
+    #[serde(default)]
+    pub shadow_mode: Vec<ValidationRule>,
+    /// Report per-phase timings in quote and payment responses
+    #[serde(default)]
+    pub diagnostics: bool,
+    /// Expose `POST /payment/simulate` for wallet developers
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Seconds a new quote accepts payment for, quotes never expire if unset
+    #[serde(default)]
+    pub quote_expiry_seconds: Option<u64>,
+    /// Most quotes open for payment at once, unlimited if unset
+    #[serde(default)]
+    pub max_open_quotes: Option<usize>,
+    /// Redacted capture of payment bodies for debugging wallet interop
+    #[serde(default)]
+    pub debug_capture: CaptureSettings,
+    /// Offset from UTC in minutes of the timezone whose midnight starts a new receipt day
+    #[serde(default)]
+    pub receipt_utc_offset_minutes: i32,
+    #[serde(default)]
+    pub overpayment_policy: OverpaymentPolicy,
+    /// Minor units a payment may exceed its quote by when overpayments are rejected
+    #[serde(default)]
+    pub overpayment_tolerance: u64,
+    /// Default encoding of amounts in responses, overridden by the `amounts` query parameter
+    #[serde(default)]
+    pub amount_encoding: AmountEncoding,
+    /// Accept underpaying payloads and add them up until the quote is covered
+    #[serde(default)]
+    pub partial_payments: bool,
+    /// URL notified when a quote is paid
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Expose `POST /withdraw` and `/send`, which move funds out of the wallet
+    #[serde(default)]
+    pub wallet_routes: bool,
+    /// Also serve the API at the unversioned paths it had before `/v1`
+    #[serde(default = "default_legacy_routes")]
+    pub legacy_routes: bool,
+    /// Bearer token required on merchant and admin routes, which are open if unset
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub exchange_rate: ExchangeRateSettings,
+    #[serde(default)]
+    pub consolidation: ConsolidationSettings,
+    #[serde(default)]
+    pub sweep: SweepSettings,
+    /// Relays listed in the Nostr transport of payment requests, which is left out if empty
+    #[serde(default)]
+    pub nostr_relays: Vec<String>,
+    /// Secret key payments over the Nostr transport are sent to, hex or nsec
+    #[serde(default)]
+    pub nostr_key: Option<String>,
+    /// Hex secret key whose pubkey payment requests ask proofs to be locked to (NUT-11)
+    #[serde(default)]
+    pub p2pk_key: Option<String>,
+    /// Refuse payloads with proofs not locked to the P2PK key
+    #[serde(default)]
+    pub require_p2pk: bool,
+    /// Hex key HTLC preimages are stored encrypted with, quotes can't take a preimage
+    /// without one
+    #[serde(default)]
+    pub htlc_preimage_key: Option<String>,
+}
+
+/// What to do with a payment whose client disconnected before the wallet receive started
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DisconnectPolicy {
+    /// Redeem the proofs and mark the quote paid anyway
+    #[default]
+    Complete,
+    /// Abandon the payment without touching the proofs
+    Cancel,
+}
+
+/// What to do with a payment worth more than its quote
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OverpaymentPolicy {
+    /// Redeem the proofs and record the amount received
+    #[default]
+    Accept,
+    /// Refuse payments exceeding the quote by more than the tolerance, leaving the proofs with
+    /// the customer
+    Reject,
+}
+
+pub fn default_payment_request_warn_length() -> usize {
+    1000
+}
+
+/// Units that can be configured as accepted
+pub const SUPPORTED_UNITS: [CurrencyUnit; 4] = [
+    CurrencyUnit::Sat,
+    CurrencyUnit::Msat,
+    CurrencyUnit::Usd,
+    CurrencyUnit::Eur,
+];
+
+pub fn default_legacy_routes() -> bool {
+    true
+}
+
+pub fn default_max_memo_length() -> usize {
+    256
+}
+
+pub fn default_accepted_units() -> Vec<CurrencyUnit> {
+    vec![CurrencyUnit::Sat, CurrencyUnit::Usd]
+}
+
+/// Parse configured unit names, rejecting unknown or unsupported units
+pub fn parse_accepted_units(names: &[String]) -> Result<Vec<CurrencyUnit>, PosError> {
+    let mut units = Vec::with_capacity(names.len());
+
+    for name in names {
+        let unit = parse_unit_lenient(name, &SUPPORTED_UNITS)?;
+
+        if !units.contains(&unit) {
+            units.push(unit);
+        }
+    }
+
+    Ok(units)
+}
+
+/// Hex sha256 over the sorted secrets of `proofs`
+///
+/// Identifies a payment payload independently of proof order, so a wallet
+/// resending the exact payload that paid a quote can be recognised.
+pub fn payment_fingerprint(proofs: &Proofs) -> String {
+    let mut secrets: Vec<String> = proofs.iter().map(|p| p.secret.to_string()).collect();
+    secrets.sort();
+
+    Sha256::digest(secrets.join("\n").as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Check that a HTLC preimage is 32 bytes of hex as NUT-14 expects
+pub fn validate_htlc_preimage(preimage: &str) -> Result<(), PosError> {
+    if preimage.len() != 64 || !preimage.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(PosError::InvalidHtlcPreimage);
+    }
+
+    Ok(())
+}
+
+/// Parse a currency unit typed by a person
+///
+/// Surrounding whitespace and case are ignored and common aliases such as `sats`
+/// or `US$` map to their unit. Units that aren't `allowed` are rejected with the
+/// closest allowed unit as a suggestion. Machine generated input such as payment
+/// payloads should keep using the strict `CurrencyUnit::from_str`.
+pub fn parse_unit_lenient(input: &str, allowed: &[CurrencyUnit]) -> Result<CurrencyUnit, PosError> {
+    let normalized = input.trim().to_lowercase();
+
+    let unsupported = |suggestion: Option<CurrencyUnit>, hint: Option<String>| {
+        PosError::UnsupportedCurrencyUnit {
+            given: input.to_string(),
+            allowed: allowed.to_vec(),
+            suggestion,
+            hint,
+        }
+    };
+
+    let canonical = match normalized.as_str() {
+        "sat" | "sats" | "satoshi" | "satoshis" => "sat",
+        "msat" | "msats" | "millisat" | "millisats" => "msat",
+        "usd" | "us$" | "$" | "dollar" | "dollars" => "usd",
+        "eur" | "€" | "euro" | "euros" => "eur",
+        "btc" | "xbt" | "bitcoin" => {
+            return Err(unsupported(
+                allowed
+                    .contains(&CurrencyUnit::Sat)
+                    .then_some(CurrencyUnit::Sat),
+                Some("Bitcoin amounts are denominated in sat (1 btc = 100000000 sat)".to_string()),
+            ));
+        }
+        other => other,
+    };
+
+    match CurrencyUnit::from_str(canonical) {
+        Ok(unit) if allowed.contains(&unit) => Ok(unit),
+        _ => Err(unsupported(closest_unit(&normalized, allowed), None)),
+    }
+}
+
+/// Allowed unit within a small edit distance of `input`
+fn closest_unit(input: &str, allowed: &[CurrencyUnit]) -> Option<CurrencyUnit> {
+    allowed
+        .iter()
+        .map(|unit| (edit_distance(input, &unit.to_string()), unit))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, unit)| unit.clone())
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Which accepted mints a payment request lists
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MintListMode {
+    /// Every accepted mint
+    All,
+    /// Accepted mints capped at `max_mints_per_request`
+    #[default]
+    Compact,
+}
+
+impl FromStr for MintListMode {
+    type Err = PosError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "compact" => Ok(Self::Compact),
+            _ => Err(PosError::InvalidQueryParameter(format!(
+                "Unknown mint list mode: {}. Expected all or compact",
+                s
+            ))),
+        }
+    }
+}
+
+/// How the `amount` parameter of a quote request should be interpreted
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountFormat {
+    /// Whole currency units, e.g. `4.50` dollars
+    Major,
+    /// Smallest currency units, e.g. `450` cents
+    Minor,
+}
+
+impl FromStr for AmountFormat {
+    type Err = PosError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            _ => Err(PosError::InvalidAmount(format!(
+                "Unknown amount format: {}. Expected major or minor",
+                s
+            ))),
+        }
+    }
+}
+
+/// How amount fields are written in JSON responses
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountEncoding {
+    /// JSON numbers
+    #[default]
+    Number,
+    /// Decimal strings, for clients that parse numbers as doubles like JavaScript
+    String,
+}
+
+impl FromStr for AmountEncoding {
+    type Err = PosError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "number" => Ok(Self::Number),
+            "string" => Ok(Self::String),
+            _ => Err(PosError::InvalidAmount(format!(
+                "Unknown amount encoding: {}. Expected number or string",
+                s
+            ))),
+        }
+    }
+}
+
+thread_local! {
+    /// Encoding amount fields are serialized with on this thread, see [`AmountEncoding::scope`]
+    static AMOUNT_ENCODING: Cell<AmountEncoding> = const { Cell::new(AmountEncoding::Number) };
+}
+
+impl AmountEncoding {
+    /// Run `f` with the amount fields it serializes written in this encoding
+    ///
+    /// Serialization is synchronous, so the encoding only applies to responses serialized
+    /// inside `f` and everything else, such as stored records, keeps JSON numbers.
+    pub fn scope<R>(self, f: impl FnOnce() -> R) -> R {
+        struct Restore(AmountEncoding);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                AMOUNT_ENCODING.set(self.0);
+            }
+        }
+
+        let _restore = Restore(AMOUNT_ENCODING.replace(self));
+        f()
+    }
+}
+
+/// Amount in minor units as written in responses, see [`AmountEncoding`]
+///
+/// Only describes the two shapes in the OpenAPI spec, amount fields are `u64` serialized
+/// with [`serialize_amount`].
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(untagged)]
+#[allow(dead_code)]
+pub enum AmountValue {
+    /// Default `number` encoding
+    Number(u64),
+    /// `string` encoding, a decimal string such as `"18446744073709551615"`
+    String(String),
+}
+
+/// Serialize an amount field in the encoding of the current [`AmountEncoding::scope`]
+pub fn serialize_amount<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    match AMOUNT_ENCODING.get() {
+        AmountEncoding::Number => serializer.serialize_u64(*value),
+        AmountEncoding::String => serializer.collect_str(value),
+    }
+}
+
+pub fn serialize_optional_amount<S: Serializer>(
+    value: &Option<u64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serialize_amount(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Deserialize an amount field written in either [`AmountEncoding`]
+pub fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    struct AmountVisitor;
+
+    impl de::Visitor<'_> for AmountVisitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an amount as a non-negative integer or a decimal string")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+            u64::try_from(v).map_err(|_| E::custom(format!("negative amount {}", v)))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            match v.bytes().all(|b| b.is_ascii_digit()) {
+                true => v
+                    .parse()
+                    .map_err(|_| E::custom(format!("invalid amount \"{}\"", v))),
+                false => Err(E::custom(format!("invalid amount \"{}\"", v))),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(AmountVisitor)
+}
+
+pub fn deserialize_optional_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    struct Amount(#[serde(deserialize_with = "deserialize_amount")] u64);
+
+    Ok(Option::<Amount>::deserialize(deserializer)?.map(|Amount(value)| value))
+}
+
+/// Number of decimal places between the major and minor denomination of a unit
+pub fn unit_decimals(unit: &CurrencyUnit) -> u32 {
+    match unit {
+        CurrencyUnit::Usd | CurrencyUnit::Eur => 2,
+        _ => 0,
+    }
+}
+
+/// Parse an amount string into minor units of `unit`
+///
+/// Without an explicit format, amounts containing a decimal point are read as
+/// major units and plain integers keep meaning minor units.
+pub fn parse_amount(
+    input: &str,
+    unit: &CurrencyUnit,
+    format: Option<AmountFormat>,
+) -> Result<u64, PosError> {
+    let input = input.trim();
+    let format = format.unwrap_or(match input.contains('.') {
+        true => AmountFormat::Major,
+        false => AmountFormat::Minor,
+    });
+
+    let invalid = || PosError::InvalidAmount(format!("Invalid amount format: {}", input));
+    // `u64::from_str` takes a leading `+`, amounts are plain digits only
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+
+    let amount = match format {
+        AmountFormat::Minor => {
+            if input.is_empty() || !is_digits(input) {
+                return Err(invalid());
+            }
+
+            input
+                .parse::<u64>()
+                .map_err(|_| PosError::InvalidAmount(format!("Amount too large: {}", input)))?
+        }
+        AmountFormat::Major => {
+            let decimals = unit_decimals(unit);
+            let (whole, fraction) = input.split_once('.').unwrap_or((input, ""));
+
+            if whole.is_empty() && fraction.is_empty() {
+                return Err(invalid());
+            }
+
+            if !is_digits(whole) || !is_digits(fraction) {
+                return Err(invalid());
+            }
+
+            if fraction.len() > decimals as usize {
+                return Err(PosError::InvalidAmount(format!(
+                    "Amount {} has more precision than {} supports ({} decimal places)",
+                    input, unit, decimals
+                )));
+            }
+
+            // Right pad the fraction so "4.5" reads as 450 cents
+            let fraction_scale = 10u64.pow(decimals - fraction.len() as u32);
+
+            let too_large = || PosError::InvalidAmount(format!("Amount too large: {}", input));
+
+            let whole = match whole.is_empty() {
+                true => 0,
+                false => whole.parse::<u64>().map_err(|_| too_large())?,
+            };
+            let fraction = match fraction.is_empty() {
+                true => 0,
+                false => fraction.parse::<u64>().map_err(|_| invalid())?,
+            };
+
+            whole
+                .checked_mul(10u64.pow(decimals))
+                .and_then(|w| w.checked_add(fraction * fraction_scale))
+                .ok_or_else(too_large)?
+        }
+    };
+
+    if amount == 0 {
+        return Err(PosError::InvalidAmount(
+            "Amount must be greater than zero".to_string(),
+        ));
+    }
+
+    Ok(amount)
+}
+
+/// Human readable representation of a minor unit amount, e.g. `4.50 usd`
+pub fn format_amount(amount: u64, unit: &CurrencyUnit) -> String {
+    let decimals = unit_decimals(unit);
+
+    match decimals {
+        0 => format!("{} {}", amount, unit),
+        _ => {
+            let scale = 10u64.pow(decimals);
+            format!(
+                "{}.{:0width$} {}",
+                amount / scale,
+                amount % scale,
+                unit,
+                width = decimals as usize
+            )
+        }
+    }
+}
+
+/// Duration config value
+///
+/// Accepts a plain integer number of seconds or a string of one or more
+/// `<number><unit>` parts such as `"30s"`, `"15m"`, `"2h"` or `"1h30m"`, where
+/// unit is one of `ms`, `s`, `m`, `h` or `d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConfigDuration(pub Duration);
+
+impl ConfigDuration {
+    pub fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl From<ConfigDuration> for Duration {
+    fn from(value: ConfigDuration) -> Self {
+        value.0
+    }
+}
+
+impl FromStr for ConfigDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+
+        if input.is_empty() {
+            return Err("empty duration".to_string());
+        }
+
+        if let Ok(secs) = input.parse::<u64>() {
+            return Ok(Self::from_secs(secs));
+        }
+
+        let mut total = Duration::ZERO;
+        let mut rest = input;
+
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if digits == 0 {
+                return Err(format!(
+                    "invalid duration \"{}\": expected a number before \"{}\"",
+                    input, rest
+                ));
+            }
+
+            let value: u64 = rest[..digits]
+                .parse()
+                .map_err(|_| format!("invalid duration \"{}\": number too large", input))?;
+            rest = &rest[digits..];
+
+            let unit_len = rest
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let part = match rest[..unit_len].trim() {
+                "ms" => Duration::from_millis(value),
+                "s" => Duration::from_secs(value),
+                "m" => Duration::from_secs(value.saturating_mul(60)),
+                "h" => Duration::from_secs(value.saturating_mul(60 * 60)),
+                "d" => Duration::from_secs(value.saturating_mul(24 * 60 * 60)),
+                "" => {
+                    return Err(format!(
+                        "invalid duration \"{}\": missing unit after {}, expected one of ms, s, m, h, d",
+                        input, value
+                    ));
+                }
+                unit => {
+                    return Err(format!(
+                        "invalid duration \"{}\": unknown unit \"{}\", expected one of ms, s, m, h, d",
+                        input, unit
+                    ));
+                }
+            };
+            rest = &rest[unit_len..];
+
+            total = total.saturating_add(part);
+        }
+
+        Ok(Self(total))
+    }
+}
+
+impl fmt::Display for ConfigDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis();
+
+        match millis {
+            m if m % 1000 != 0 => write!(f, "{}ms", m),
+            m if m % (60 * 60 * 1000) == 0 && m > 0 => write!(f, "{}h", m / (60 * 60 * 1000)),
+            m if m % (60 * 1000) == 0 && m > 0 => write!(f, "{}m", m / (60 * 1000)),
+            m => write!(f, "{}s", m / 1000),
+        }
+    }
+}
+
+impl Serialize for ConfigDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DurationVisitor;
+
+        impl de::Visitor<'_> for DurationVisitor {
+            type Value = ConfigDuration;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number of seconds or a duration string like \"15m\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(ConfigDuration::from_secs(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(ConfigDuration::from_secs)
+                    .map_err(|_| E::custom(format!("invalid duration {}: must not be negative", v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                ConfigDuration::from_str(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DurationVisitor)
+    }
+}
+
+/// Amount config value
+///
+/// Accepts a plain integer or a string with a unit such as `"1000 sat"`. The
+/// unit is optional; when given, it can be checked against the unit the amount
+/// applies to with [`AmountCfg::value_for`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AmountCfg {
+    pub value: u64,
+    pub unit: Option<CurrencyUnit>,
+}
+
+impl AmountCfg {
+    /// Amount for `unit`, failing if the config named a different unit
+    pub fn value_for(&self, unit: &CurrencyUnit) -> Result<u64, String> {
+        match &self.unit {
+            Some(configured) if configured != unit => Err(format!(
+                "amount {} is configured in {} but used for {}",
+                self, configured, unit
+            )),
+            _ => Ok(self.value),
+        }
+    }
+}
+
+impl FromStr for AmountCfg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        let mut parts = input.split_whitespace();
+
+        let value = parts
+            .next()
+            .ok_or_else(|| "empty amount".to_string())?
+            .parse::<u64>()
+            .map_err(|_| {
+                format!(
+                    "invalid amount \"{}\": expected an integer optionally followed by a unit, e.g. \"1000 sat\"",
+                    input
+                )
+            })?;
+
+        let unit = parts
+            .next()
+            .map(|unit| {
+                CurrencyUnit::from_str(unit)
+                    .map_err(|_| format!("invalid amount \"{}\": unknown unit \"{}\"", input, unit))
+            })
+            .transpose()?;
+
+        if parts.next().is_some() {
+            return Err(format!(
+                "invalid amount \"{}\": unexpected trailing text",
+                input
+            ));
+        }
+
+        Ok(Self { value, unit })
+    }
+}
+
+impl fmt::Display for AmountCfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unit {
+            Some(unit) => write!(f, "{} {}", self.value, unit),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
+impl Serialize for AmountCfg {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.unit {
+            Some(_) => serializer.serialize_str(&self.to_string()),
+            None => serializer.serialize_u64(self.value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AmountCfg {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl de::Visitor<'_> for AmountVisitor {
+            type Value = AmountCfg;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an integer amount or an amount string like \"1000 sat\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(AmountCfg {
+                    value: v,
+                    unit: None,
+                })
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(|value| AmountCfg { value, unit: None })
+                    .map_err(|_| E::custom(format!("invalid amount {}: must not be negative", v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                AmountCfg::from_str(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_amount_reads_decimals_as_major_units() {
+        assert_eq!(parse_amount("4.50", &CurrencyUnit::Usd, None).unwrap(), 450);
+        assert_eq!(parse_amount("4.5", &CurrencyUnit::Usd, None).unwrap(), 450);
+        assert_eq!(parse_amount(".5", &CurrencyUnit::Eur, None).unwrap(), 50);
+        assert_eq!(parse_amount("12.", &CurrencyUnit::Usd, None).unwrap(), 1200);
+        assert_eq!(
+            parse_amount(" 1.05 ", &CurrencyUnit::Usd, None).unwrap(),
+            105
+        );
+    }
+
+    #[test]
+    fn parse_amount_keeps_integers_as_minor_units() {
+        assert_eq!(parse_amount("450", &CurrencyUnit::Usd, None).unwrap(), 450);
+        assert_eq!(parse_amount("21", &CurrencyUnit::Sat, None).unwrap(), 21);
+    }
+
+    #[test]
+    fn parse_amount_format_overrides_detection() {
+        assert_eq!(
+            parse_amount("4", &CurrencyUnit::Usd, Some(AmountFormat::Major)).unwrap(),
+            400
+        );
+        assert_eq!(
+            parse_amount("21", &CurrencyUnit::Sat, Some(AmountFormat::Major)).unwrap(),
+            21
+        );
+        assert!(parse_amount("4.50", &CurrencyUnit::Usd, Some(AmountFormat::Minor)).is_err());
+    }
+
+    #[test]
+    fn parse_amount_rejects_excess_precision() {
+        assert!(parse_amount("4.505", &CurrencyUnit::Usd, None).is_err());
+        assert!(parse_amount("1.5", &CurrencyUnit::Sat, None).is_err());
+    }
+
+    #[test]
+    fn parse_amount_rejects_zero_signs_and_garbage() {
+        for input in [
+            "0", "0.00", ".0", "00", "+5", "-5", "+4.50", "4.+5", "-.5", "", ".", "4,50", "1e3",
+            "4.5.0", " ",
+        ] {
+            assert!(
+                parse_amount(input, &CurrencyUnit::Usd, None).is_err(),
+                "{:?} should be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parse_amount_rejects_overflow() {
+        let max = u64::MAX.to_string();
+        assert_eq!(
+            parse_amount(&max, &CurrencyUnit::Sat, None).unwrap(),
+            u64::MAX
+        );
+        assert!(parse_amount("18446744073709551616", &CurrencyUnit::Sat, None).is_err());
+        assert!(parse_amount(&format!("{}.00", max), &CurrencyUnit::Usd, None).is_err());
+    }
+
+    const ALLOWED: &[CurrencyUnit] = &[CurrencyUnit::Sat, CurrencyUnit::Usd];
+
+    fn suggestion(input: &str, allowed: &[CurrencyUnit]) -> Option<CurrencyUnit> {
+        match parse_unit_lenient(input, allowed) {
+            Err(PosError::UnsupportedCurrencyUnit { suggestion, .. }) => suggestion,
+            other => panic!("{:?} should be unsupported, got {:?}", input, other),
+        }
+    }
+
+    #[test]
+    fn parse_unit_lenient_maps_aliases() {
+        for input in ["sat", "sats", "satoshi", "Satoshis"] {
+            assert_eq!(
+                parse_unit_lenient(input, ALLOWED).unwrap(),
+                CurrencyUnit::Sat
+            );
+        }
+        for input in ["usd", "US$", "$", "dollar", "Dollars"] {
+            assert_eq!(
+                parse_unit_lenient(input, ALLOWED).unwrap(),
+                CurrencyUnit::Usd
+            );
+        }
+        for input in ["eur", "€", "EURO", "euros"] {
+            assert_eq!(
+                parse_unit_lenient(input, &[CurrencyUnit::Eur]).unwrap(),
+                CurrencyUnit::Eur
+            );
+        }
+        for input in ["msat", "msats", "millisat", "MilliSats"] {
+            assert_eq!(
+                parse_unit_lenient(input, &[CurrencyUnit::Msat]).unwrap(),
+                CurrencyUnit::Msat
+            );
+        }
+    }
+
+    #[test]
+    fn parse_unit_lenient_ignores_case_and_whitespace() {
+        assert_eq!(
+            parse_unit_lenient("USD ", ALLOWED).unwrap(),
+            CurrencyUnit::Usd
+        );
+        assert_eq!(
+            parse_unit_lenient("  SAT\t", ALLOWED).unwrap(),
+            CurrencyUnit::Sat
+        );
+        assert_eq!(
+            parse_unit_lenient("\nSats\n", ALLOWED).unwrap(),
+            CurrencyUnit::Sat
+        );
+    }
+
+    #[test]
+    fn parse_unit_lenient_rejects_bitcoin_with_hint() {
+        for input in ["btc", "BTC", "xbt", "bitcoin"] {
+            match parse_unit_lenient(input, ALLOWED) {
+                Err(PosError::UnsupportedCurrencyUnit {
+                    suggestion, hint, ..
+                }) => {
+                    assert_eq!(suggestion, Some(CurrencyUnit::Sat));
+                    assert!(hint.is_some_and(|hint| hint.contains("sat")));
+                }
+                other => panic!("{:?} should be rejected, got {:?}", input, other),
+            }
+        }
+
+        // No sat suggestion when sat isn't accepted
+        assert_eq!(suggestion("btc", &[CurrencyUnit::Usd]), None);
+    }
+
+    #[test]
+    fn parse_unit_lenient_rejects_units_not_allowed() {
+        match parse_unit_lenient("eur", ALLOWED) {
+            Err(PosError::UnsupportedCurrencyUnit { given, allowed, .. }) => {
+                assert_eq!(given, "eur");
+                assert_eq!(allowed, ALLOWED);
+            }
+            other => panic!("eur should be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_unit_lenient_suggests_closest_unit() {
+        assert_eq!(suggestion("sta", ALLOWED), Some(CurrencyUnit::Sat));
+        assert_eq!(suggestion("usdd", ALLOWED), Some(CurrencyUnit::Usd));
+        assert_eq!(suggestion("ud", ALLOWED), Some(CurrencyUnit::Usd));
+        assert_eq!(suggestion("yen", ALLOWED), None);
+        assert_eq!(suggestion("", ALLOWED), None);
+    }
+
+    #[test]
+    fn edit_distance_counts_edits() {
+        assert_eq!(edit_distance("sat", "sat"), 0);
+        assert_eq!(edit_distance("sta", "sat"), 2);
+        assert_eq!(edit_distance("usdd", "usd"), 1);
+        assert_eq!(edit_distance("", "eur"), 3);
+    }
+
+    fn proof(secret: &str) -> cdk::nuts::Proof {
+        serde_json::from_value(serde_json::json!({
+            "amount": 1,
+            "id": "009a1f293253e41e",
+            "secret": secret,
+            "C": "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn payment_fingerprint_ignores_proof_order_only() {
+        let fingerprint = payment_fingerprint(&vec![proof("a"), proof("b")]);
+
+        assert_eq!(
+            payment_fingerprint(&vec![proof("b"), proof("a")]),
+            fingerprint
+        );
+        assert_ne!(payment_fingerprint(&vec![proof("a")]), fingerprint);
+        assert_ne!(
+            payment_fingerprint(&vec![proof("a"), proof("c")]),
+            fingerprint
+        );
+        assert_ne!(
+            payment_fingerprint(&vec![proof("a"), proof("b"), proof("c")]),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn receipt_date_turns_at_local_midnight() {
+        // 2026-03-01 00:00 at UTC+2
+        let midnight = 1_772_316_000;
+
+        assert_eq!(receipt_date(midnight - 1, 120), "2026-02-28");
+        assert_eq!(receipt_date(midnight, 120), "2026-03-01");
+        // Still the evening before in UTC and west of it
+        assert_eq!(receipt_date(midnight, 0), "2026-02-28");
+        assert_eq!(receipt_date(midnight + 2 * 3600, -60), "2026-02-28");
+        assert_eq!(receipt_date(midnight + 3 * 3600, -60), "2026-03-01");
+    }
+
+    #[test]
+    fn htlc_preimage_must_be_32_bytes_of_hex() {
+        assert!(validate_htlc_preimage(&"ab".repeat(32)).is_ok());
+        assert!(validate_htlc_preimage(&"AB".repeat(32)).is_ok());
+
+        for preimage in ["", &"ab".repeat(31), &"ab".repeat(33), &"zz".repeat(32)] {
+            assert!(matches!(
+                validate_htlc_preimage(preimage),
+                Err(PosError::InvalidHtlcPreimage)
+            ));
+        }
+    }
+
+    fn unit() -> impl proptest::strategy::Strategy<Value = CurrencyUnit> {
+        proptest::prop_oneof![
+            proptest::strategy::Just(CurrencyUnit::Sat),
+            proptest::strategy::Just(CurrencyUnit::Msat),
+            proptest::strategy::Just(CurrencyUnit::Usd),
+            proptest::strategy::Just(CurrencyUnit::Eur),
+        ]
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn cross_unit_operations_fail(a: u64, b: u64, unit_a in unit(), unit_b in unit()) {
+            proptest::prop_assume!(unit_a != unit_b);
+
+            let a = PosAmount::new(a, unit_a);
+            let b = PosAmount::new(b, unit_b);
+
+            proptest::prop_assert!(matches!(a.checked_add(&b), Err(PosError::UnitMismatch { .. })));
+            proptest::prop_assert!(matches!(a.checked_sub(&b), Err(PosError::UnitMismatch { .. })));
+            proptest::prop_assert!(matches!(a.checked_cmp(&b), Err(PosError::UnitMismatch { .. })));
+            proptest::prop_assert!(a.ensure_same_unit(&b).is_err());
+        }
+
+        #[test]
+        fn same_unit_operations_match_u64(a: u64, b: u64, unit in unit()) {
+            let x = PosAmount::new(a, unit.clone());
+            let y = PosAmount::new(b, unit.clone());
+
+            proptest::prop_assert_eq!(x.checked_cmp(&y).unwrap(), a.cmp(&b));
+
+            match a.checked_add(b) {
+                Some(sum) => proptest::prop_assert_eq!(
+                    x.checked_add(&y).unwrap(),
+                    PosAmount::new(sum, unit.clone())
+                ),
+                None => proptest::prop_assert!(x.checked_add(&y).is_err()),
+            }
+
+            match a.checked_sub(b) {
+                Some(difference) => proptest::prop_assert_eq!(
+                    x.checked_sub(&y).unwrap(),
+                    PosAmount::new(difference, unit)
+                ),
+                None => proptest::prop_assert!(x.checked_sub(&y).is_err()),
+            }
+        }
+
+        #[test]
+        fn pos_amount_keeps_separate_wire_fields(value: u64, unit in unit()) {
+            let amount = PosAmount::new(value, unit.clone());
+            let json = serde_json::to_value(&amount).unwrap();
+
+            proptest::prop_assert_eq!(&json["amount"], &serde_json::json!(value));
+            proptest::prop_assert_eq!(&json["unit"], &serde_json::json!(unit.to_string()));
+            proptest::prop_assert_eq!(serde_json::from_value::<PosAmount>(json).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn quote_info_reads_old_shape() {
+        let quote: QuoteInfo = serde_json::from_str(
+            r#"{"id":"67e55044-10b1-426f-9247-bb680e5fe0c8","amount":100,"unit":"sat","state":"Paid"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(quote.amount, PosAmount::new(100, CurrencyUnit::Sat));
+        assert_eq!(quote.state, QuoteState::Paid);
+        assert_eq!(quote.created_at, None);
+        assert_eq!(quote.paid_at, None);
+        assert_eq!(quote.paid_amount, None);
+        assert_eq!(quote.expires_at, None);
+    }
+
+    #[test]
+    fn quote_info_keeps_timestamps() {
+        let quote: QuoteInfo = serde_json::from_str(
+            r#"{"id":"67e55044-10b1-426f-9247-bb680e5fe0c8","amount":100,"unit":"sat","state":"Paid","created_at":1700000000,"paid_at":1700000060}"#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(&quote).unwrap();
+        assert_eq!(json["created_at"], 1700000000);
+        assert_eq!(json["paid_at"], 1700000060);
+    }
+
+    /// Largest integer a double holds exactly is 2^53, the next one is where clients parsing
+    /// numbers as doubles start losing precision
+    const LARGE_AMOUNTS: [u64; 3] = [1 << 53, (1 << 53) + 1, u64::MAX];
+
+    fn encode<T: Serialize>(value: &T, encoding: AmountEncoding) -> serde_json::Value {
+        encoding.scope(|| serde_json::to_value(value).unwrap())
+    }
+
+    #[test]
+    fn amounts_round_trip_in_both_encodings() {
+        for value in LARGE_AMOUNTS {
+            let amount = PosAmount::new(value, CurrencyUnit::Sat);
+
+            let number = encode(&amount, AmountEncoding::Number);
+            assert_eq!(number["amount"], serde_json::json!(value));
+            assert_eq!(serde_json::from_value::<PosAmount>(number).unwrap(), amount);
+
+            let string = encode(&amount, AmountEncoding::String);
+            assert_eq!(string["amount"], serde_json::json!(value.to_string()));
+            assert_eq!(serde_json::from_value::<PosAmount>(string).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn optional_amounts_round_trip_in_both_encodings() {
+        let quote: QuoteInfo = serde_json::from_str(
+            r#"{"id":"67e55044-10b1-426f-9247-bb680e5fe0c8","amount":100,"unit":"sat","state":"Unpaid","created_at":1700000000}"#,
+        )
+        .unwrap();
+
+        for value in LARGE_AMOUNTS {
+            let quote = QuoteInfo {
+                paid_amount: Some(value),
+                ..quote.clone()
+            };
+
+            for encoding in [AmountEncoding::Number, AmountEncoding::String] {
+                let json = encode(&quote, encoding);
+                let read: QuoteInfo = serde_json::from_value(json).unwrap();
+                assert_eq!(read.paid_amount, Some(value));
+                assert_eq!(read.net_amount, None);
+            }
+        }
+    }
+
+    #[test]
+    fn string_encoding_only_touches_amount_fields() {
+        let sweep = Sweep {
+            id: Uuid::nil(),
+            mint: MintUrl::from_str("https://mint.example.com").unwrap(),
+            unit: CurrencyUnit::Sat,
+            lightning_address: "shop@example.com".to_string(),
+            amount: u64::MAX,
+            fee_paid: 1 << 53,
+            melt_quote_id: None,
+            state: None,
+            preimage: None,
+            error: None,
+            created_at: 1_700_000_000,
+        };
+
+        let json = encode(&sweep, AmountEncoding::String);
+        assert_eq!(json["amount"], "18446744073709551615");
+        assert_eq!(json["fee_paid"], "9007199254740992");
+        assert_eq!(json["created_at"], 1_700_000_000);
+    }
+
+    #[test]
+    fn encoding_scope_is_restored() {
+        let amount = PosAmount::new(5, CurrencyUnit::Sat);
+
+        let nested = AmountEncoding::String.scope(|| {
+            let inner = encode(&amount, AmountEncoding::Number);
+            (inner, serde_json::to_value(&amount).unwrap())
+        });
+        assert_eq!(nested.0["amount"], 5);
+        assert_eq!(nested.1["amount"], "5");
+
+        // Outside any scope, e.g. when stored, amounts stay numbers
+        assert_eq!(serde_json::to_value(&amount).unwrap()["amount"], 5);
+    }
+
+    #[test]
+    fn amount_strings_must_be_plain_digits() {
+        for json in [
+            r#""+5""#,
+            r#""-1""#,
+            r#""1.5""#,
+            r#""""#,
+            r#""18446744073709551616""#,
+            "-1",
+        ] {
+            let result: Result<PosAmount, _> =
+                serde_json::from_str(&format!(r#"{{"amount":{},"unit":"sat"}}"#, json));
+            assert!(result.is_err(), "{} was accepted", json);
+        }
+    }
 }
@@ -0,0 +1,187 @@
+//! Named payment validation rules
+//!
+//! Rules listed in the `shadow_mode` config run as usual but only report the
+//! rejection they would have made, so new checks can be observed in production
+//! before they are enforced. Those would-be rejections are counted per rule and
+//! listed by `GET /admin/shadow`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::PosError;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationRule {
+    /// The payload's mint is one of the quote's mints, or of the accepted mints if it has none
+    MintAccepted,
+    /// The payload's proofs cover the quote amount
+    AmountSufficient,
+    /// The payload's proofs don't exceed the quote amount beyond the tolerance, only checked
+    /// with the `reject` overpayment policy
+    AmountNotExcessive,
+    /// The payload's proofs come from keysets of the quote's unit
+    UnitMatch,
+    /// Every proof of the payload is locked to the POS P2PK key, only checked with
+    /// `require_p2pk`
+    P2pkLocked,
+    /// The DLEQ proofs carried by the payload's proofs verify against the mint's keys
+    DleqValid,
+    /// None of the payload's proofs were already received in an earlier partial payment of
+    /// the quote
+    ReplayCheck,
+    /// What is left of the payload after the input fees of its proofs still covers the amount
+    /// the merchant asked for, only checked on fee-inclusive quotes
+    FeePolicy,
+}
+
+impl fmt::Display for ValidationRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::MintAccepted => "mint_accepted",
+            Self::AmountSufficient => "amount_sufficient",
+            Self::AmountNotExcessive => "amount_not_excessive",
+            Self::UnitMatch => "unit_match",
+            Self::P2pkLocked => "p2pk_locked",
+            Self::DleqValid => "dleq_valid",
+            Self::ReplayCheck => "replay_check",
+            Self::FeePolicy => "fee_policy",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Rejections shadowed rules would have made, by rule
+#[derive(Debug, Default)]
+pub struct ShadowRejections(Mutex<HashMap<ValidationRule, u64>>);
+
+impl ShadowRejections {
+    /// Count a would-be rejection of `rule`, returning the count so far
+    fn record(&self, rule: ValidationRule) -> u64 {
+        let mut counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counts.entry(rule).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// Would-be rejections of each rule since the server started
+    pub fn counts(&self) -> HashMap<ValidationRule, u64> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Apply the outcome of `rule`, turning a rejection into telemetry when the rule is shadowed
+pub fn enforce(
+    rule: ValidationRule,
+    shadowed: &[ValidationRule],
+    rejections: &ShadowRejections,
+    quote_id: Uuid,
+    outcome: Result<(), PosError>,
+) -> Result<(), PosError> {
+    match outcome {
+        Err(err) if shadowed.contains(&rule) => {
+            let would_reject = rejections.record(rule);
+            tracing::warn!(
+                rule = %rule,
+                quote_id = %quote_id,
+                code = err.code(),
+                would_reject,
+                "Shadowed rule would have rejected payment: {}",
+                err
+            );
+            Ok(())
+        }
+        outcome => outcome,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: [ValidationRule; 8] = [
+        ValidationRule::MintAccepted,
+        ValidationRule::AmountSufficient,
+        ValidationRule::AmountNotExcessive,
+        ValidationRule::UnitMatch,
+        ValidationRule::P2pkLocked,
+        ValidationRule::DleqValid,
+        ValidationRule::ReplayCheck,
+        ValidationRule::FeePolicy,
+    ];
+
+    fn rejection() -> Result<(), PosError> {
+        Err(PosError::P2pkRequired(Uuid::nil()))
+    }
+
+    #[test]
+    fn rules_use_their_config_names() {
+        for rule in RULES {
+            let json = serde_json::to_value(rule).unwrap();
+            assert_eq!(json, serde_json::Value::String(rule.to_string()));
+            assert_eq!(
+                serde_json::from_value::<ValidationRule>(json).unwrap(),
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn enforced_rules_reject() {
+        let rejections = ShadowRejections::default();
+
+        for rule in RULES {
+            let err = enforce(rule, &[], &rejections, Uuid::nil(), rejection()).unwrap_err();
+            assert_eq!(err.code(), "P2PK_REQUIRED");
+            enforce(rule, &[], &rejections, Uuid::nil(), Ok(())).unwrap();
+        }
+
+        assert!(rejections.counts().is_empty());
+    }
+
+    #[test]
+    fn shadowed_rules_pass_and_count() {
+        let rejections = ShadowRejections::default();
+
+        for rule in RULES {
+            enforce(rule, &[rule], &rejections, Uuid::nil(), rejection()).unwrap();
+            enforce(rule, &[rule], &rejections, Uuid::nil(), rejection()).unwrap();
+            enforce(rule, &[rule], &rejections, Uuid::nil(), Ok(())).unwrap();
+        }
+
+        let counts = rejections.counts();
+        for rule in RULES {
+            assert_eq!(counts.get(&rule), Some(&2), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn shadowing_one_rule_leaves_the_others_enforced() {
+        let rejections = ShadowRejections::default();
+        let shadowed = [ValidationRule::FeePolicy];
+
+        enforce(
+            ValidationRule::FeePolicy,
+            &shadowed,
+            &rejections,
+            Uuid::nil(),
+            rejection(),
+        )
+        .unwrap();
+        enforce(
+            ValidationRule::ReplayCheck,
+            &shadowed,
+            &rejections,
+            Uuid::nil(),
+            rejection(),
+        )
+        .unwrap_err();
+
+        assert_eq!(rejections.counts().len(), 1);
+    }
+}
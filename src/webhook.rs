@@ -0,0 +1,94 @@
+//! Paid quote notifications
+//!
+//! Deliveries run in background tasks so they never hold up the payment
+//! response, and are retried with exponential backoff before giving up.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::types::PosAmount;
+
+/// Delivery attempts per notification, including the first
+const MAX_ATTEMPTS: u32 = 4;
+/// Wait before the first retry, doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body POSTed to the webhook URL once a quote is paid
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotePaidEvent {
+    pub quote_id: Uuid,
+    #[serde(flatten)]
+    pub amount: PosAmount,
+    /// Total value of the proofs received for the quote, in its unit
+    pub paid_amount: u64,
+    /// Unix time the quote became paid
+    pub paid_at: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WebhookSender {
+    client: reqwest::Client,
+}
+
+impl WebhookSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver `event` to `url` in the background
+    pub fn send(&self, url: String, event: QuotePaidEvent) {
+        let client = self.client.clone();
+        tokio::spawn(async move { deliver(client, url, event).await });
+    }
+}
+
+async fn deliver(client: reqwest::Client, url: String, event: QuotePaidEvent) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client
+            .post(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(response) => {
+                tracing::info!(
+                    "Delivered paid webhook for quote {} to {} ({})",
+                    event.quote_id,
+                    url,
+                    response.status()
+                );
+                return;
+            }
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                tracing::warn!(
+                    "Paid webhook for quote {} to {} failed (attempt {}/{}), retrying in {:?}: {}",
+                    event.quote_id,
+                    url,
+                    attempt,
+                    MAX_ATTEMPTS,
+                    backoff,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Giving up on paid webhook for quote {} to {} after {} attempts: {}",
+                    event.quote_id,
+                    url,
+                    MAX_ATTEMPTS,
+                    e
+                );
+            }
+        }
+    }
+}